@@ -3,7 +3,7 @@ use std::io::Result;
 fn main() -> Result<()> {
     tonic_prost_build::configure()
         .build_client(true)
-        .build_server(false)
+        .build_server(true)
         .compile_protos(
             &["proto/envoy/api/envoy/service/ext_proc/v3/external_processor.proto"],
             &[
@@ -0,0 +1,209 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tonic::transport;
+
+use crate::app::{
+    connection::{Churn, ConnectionPool, WaitForReady},
+    error::Result,
+    transport::Connector,
+};
+
+/// How streams are spread across the endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BalancePolicy {
+    /// Each endpoint in turn.
+    RoundRobin,
+    /// A uniformly random endpoint.
+    Random,
+    /// The endpoint with the fewest streams in flight, the first one on ties.
+    LeastInFlight,
+}
+
+/// Spreads the streams across the `ext_proc` endpoints, like an Envoy cluster does.
+///
+/// Each endpoint has its own `ConnectionPool`.
+#[derive(Debug, Clone)]
+pub(crate) struct Balancer {
+    endpoints: Arc<[Endpoint]>,
+    policy: BalancePolicy,
+    next: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    connections: ConnectionPool,
+    in_flight: AtomicUsize,
+}
+
+/// An endpoint picked for a stream. The stream is in flight until it is dropped.
+#[derive(Debug)]
+pub(crate) struct PickedEndpoint<'a> {
+    /// Index of the endpoint, in the order they were given.
+    pub(crate) index: usize,
+    pub(crate) connections: &'a ConnectionPool,
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for PickedEndpoint<'_> {
+    fn drop(&mut self) {
+        let _ = self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Balancer {
+    /// Creates a balancer over the connection pools of the endpoints.
+    ///
+    /// # Panics
+    /// If there is no endpoint.
+    pub(crate) fn new(endpoints: Vec<ConnectionPool>, policy: BalancePolicy) -> Self {
+        assert!(!endpoints.is_empty(), "there must be at least one endpoint");
+
+        let endpoints = endpoints
+            .into_iter()
+            .map(|connections| Endpoint {
+                connections,
+                in_flight: AtomicUsize::new(0),
+            })
+            .collect();
+
+        Self {
+            endpoints,
+            policy,
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Opens `connections` connections to each endpoint.
+    pub(crate) async fn connect(
+        endpoints: &[(transport::Endpoint, Connector)],
+        connections: NonZeroUsize,
+        churn: Churn,
+        wait_for_ready: Option<WaitForReady>,
+        policy: BalancePolicy,
+    ) -> Result<Self> {
+        let mut pools = Vec::with_capacity(endpoints.len());
+        for (endpoint, connector) in endpoints {
+            pools.push(
+                ConnectionPool::connect(
+                    endpoint.clone(),
+                    connector.clone(),
+                    connections,
+                    churn,
+                    wait_for_ready,
+                )
+                .await?,
+            );
+        }
+
+        Ok(Self::new(pools, policy))
+    }
+
+    /// Picks the endpoint of the next stream.
+    pub(crate) fn pick(&self) -> PickedEndpoint<'_> {
+        let index = match self.policy {
+            _ if self.endpoints.len() == 1 => 0,
+            BalancePolicy::RoundRobin => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.endpoints.len()
+            }
+            BalancePolicy::Random => fastrand::usize(..self.endpoints.len()),
+            BalancePolicy::LeastInFlight => self
+                .endpoints
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, endpoint)| endpoint.in_flight.load(Ordering::Relaxed))
+                .map_or(0, |(index, _)| index),
+        };
+
+        let endpoint = &self.endpoints[index];
+        let _ = endpoint.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        PickedEndpoint {
+            index,
+            connections: &endpoint.connections,
+            in_flight: &endpoint.in_flight,
+        }
+    }
+}
+
+impl From<ConnectionPool> for Balancer {
+    fn from(connections: ConnectionPool) -> Self {
+        Self::new(vec![connections], BalancePolicy::RoundRobin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::app::{
+        test_server,
+        transport::{Target, TcpSettings},
+    };
+
+    const ENDPOINT_COUNT: usize = 3;
+
+    async fn balancer(policy: BalancePolicy) -> (TempDir, Balancer) {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        test_server::serve_unix(UnixListener::bind(&path).unwrap());
+
+        let (target, uri) = Target::parse(&format!("unix:{}", path.display()), false);
+        let endpoints = (0..ENDPOINT_COUNT)
+            .map(|_| {
+                let connector = Connector::new(target.clone(), TcpSettings::default(), None);
+                (transport::Endpoint::new(uri.clone()).unwrap(), connector)
+            })
+            .collect::<Vec<_>>();
+        let balancer = Balancer::connect(&endpoints, NonZeroUsize::MIN, Churn::Never, None, policy)
+            .await
+            .unwrap();
+
+        (directory, balancer)
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_picks_each_endpoint_in_turn() {
+        let (_directory, balancer) = balancer(BalancePolicy::RoundRobin).await;
+
+        let picked = (0..6).map(|_| balancer.pick().index).collect::<Vec<_>>();
+
+        assert_eq!(picked, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_random_picks_every_endpoint() {
+        let (_directory, balancer) = balancer(BalancePolicy::Random).await;
+
+        let mut picked = [0; ENDPOINT_COUNT];
+        for _ in 0..300 {
+            picked[balancer.pick().index] += 1;
+        }
+
+        assert!(picked.iter().all(|&count| count > 0), "Got {picked:?}");
+    }
+
+    #[tokio::test]
+    async fn test_least_in_flight_avoids_busy_endpoints() {
+        let (_directory, balancer) = balancer(BalancePolicy::LeastInFlight).await;
+
+        let first = balancer.pick();
+        let second = balancer.pick();
+        assert_eq!((first.index, second.index), (0, 1));
+
+        // NOTE: The stream to the first endpoint is done, so it is the least loaded again.
+        drop(first);
+        assert_eq!(balancer.pick().index, 0);
+        drop(second);
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt::Write as _;
+
+use time::format_description::well_known::Rfc3339;
+
+use crate::app::{
+    manifest::{Manifest, RunOutcome, Step, StepOutcome},
+    slo::SloCheck,
+    summary::PERCENTILES,
+};
+
+/// The percentiles shown in the Markdown summary, by index in `PERCENTILES`.
+const MARKDOWN_PERCENTILES: [usize; 3] = [0, 2, 3];
+
+/// A test case of the `JUnit` report.
+#[derive(Debug)]
+struct TestCase {
+    classname: &'static str,
+    name: String,
+    time_s: u64,
+    result: TestResult,
+    output: Option<String>,
+}
+
+#[derive(Debug)]
+enum TestResult {
+    Passed,
+    Failure(String),
+    /// The run failed with an error during the step.
+    Error(String),
+    /// The step was planned but not run.
+    Skipped,
+}
+
+impl TestCase {
+    fn step(step: &Step, time_s: u64) -> Self {
+        let summary = &step.summary;
+        Self {
+            classname: "steps",
+            name: format!("{} req/s", summary.target_throughput),
+            time_s,
+            result: match step.outcome {
+                StepOutcome::Completed => TestResult::Passed,
+                StepOutcome::Saturated => TestResult::Failure(format!(
+                    "achieved {} req/s, {}% of the planned requests sent",
+                    summary.achieved_throughput, summary.percent_of_target_throughput
+                )),
+            },
+            output: Some(summary.to_string()),
+        }
+    }
+
+    fn slo(check: &SloCheck, target_throughput: u64) -> Self {
+        Self {
+            classname: "slo",
+            name: format!("{} at {target_throughput} req/s", check.slo),
+            time_s: 0,
+            result: if check.passed {
+                TestResult::Passed
+            } else {
+                TestResult::Failure(format!("{} {}", check.value, check.unit))
+            },
+            output: None,
+        }
+    }
+
+    fn write(&self, xml: &mut String) {
+        let _ = write!(
+            xml,
+            "\n    <testcase classname=\"{}\" name=\"{}\" time=\"{}\">",
+            self.classname,
+            escape(&self.name),
+            self.time_s
+        );
+        match &self.result {
+            TestResult::Passed => {}
+            TestResult::Failure(message) => {
+                let _ = write!(xml, "\n      <failure message=\"{}\"/>", escape(message));
+            }
+            TestResult::Error(message) => {
+                let _ = write!(xml, "\n      <error message=\"{}\"/>", escape(message));
+            }
+            TestResult::Skipped => xml.push_str("\n      <skipped/>"),
+        }
+        if let Some(output) = &self.output {
+            let _ = write!(xml, "\n      <system-out>{}</system-out>", escape(output));
+        }
+        xml.push_str("\n    </testcase>");
+    }
+}
+
+/// Formats a run as a `JUnit` XML report: a test case per step, which fails if the
+/// step could not reach its target throughput, and a test case per SLO checked for
+/// each step. The planned steps that did not run are skipped, or errors if the run
+/// failed during them.
+pub(crate) fn junit(manifest: &Manifest<'_>) -> String {
+    let test_duration_s = manifest.config.test_duration_s;
+    let mut cases = Vec::new();
+    for step in manifest.steps() {
+        cases.push(TestCase::step(step, test_duration_s));
+        for check in &step.slo {
+            cases.push(TestCase::slo(check, step.summary.target_throughput));
+        }
+    }
+    let not_run = manifest
+        .config
+        .throughputs
+        .iter()
+        .skip(manifest.steps().len());
+    for (index, target_throughput) in not_run.enumerate() {
+        let result = match (&manifest.error, manifest.outcome) {
+            (Some(error), RunOutcome::Failed) if index == 0 => TestResult::Error(error.clone()),
+            _ => TestResult::Skipped,
+        };
+        cases.push(TestCase {
+            classname: "steps",
+            name: format!("{target_throughput} req/s"),
+            time_s: 0,
+            result,
+            output: None,
+        });
+    }
+
+    let count = |matches: fn(&TestResult) -> bool| {
+        cases.iter().filter(|case| matches(&case.result)).count()
+    };
+    let time_s = manifest.finished_at.map_or(0, |finished_at| {
+        (finished_at - manifest.started_at).whole_seconds()
+    });
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n  <testsuite name=\"{}\" \
+         tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" timestamp=\"{}\" time=\"{time_s}\">",
+        escape(&format!("{} {}", env!("CARGO_PKG_NAME"), manifest.run_id)),
+        cases.len(),
+        count(|result| matches!(result, TestResult::Failure(_))),
+        count(|result| matches!(result, TestResult::Error(_))),
+        count(|result| matches!(result, TestResult::Skipped)),
+        manifest.started_at.format(&Rfc3339).unwrap_or_default(),
+    );
+    for case in &cases {
+        case.write(&mut xml);
+    }
+    xml.push_str("\n  </testsuite>\n</testsuites>\n");
+
+    xml
+}
+
+/// Formats a run as a Markdown table, one row per step, e.g. for a pull request
+/// comment. Latencies are in milliseconds.
+pub(crate) fn markdown(manifest: &Manifest<'_>) -> String {
+    let mut markdown = format!(
+        "### {} `{}`: {}\n",
+        env!("CARGO_PKG_NAME"),
+        manifest.run_id,
+        manifest.outcome.name()
+    );
+    if let Some(error) = &manifest.error {
+        let _ = writeln!(markdown, "\n> {error}");
+    }
+
+    markdown.push_str("\n| | target/s | achieved/s | streams | failed | closed early |");
+    for index in MARKDOWN_PERCENTILES {
+        let _ = write!(markdown, " p{} ms |", PERCENTILES[index]);
+    }
+    markdown.push_str(" SLOs |\n| --- | ---: | ---: | ---: | ---: | ---: |");
+    markdown.push_str(&" ---: |".repeat(MARKDOWN_PERCENTILES.len()));
+    markdown.push_str(" --- |");
+
+    for step in manifest.steps() {
+        let summary = &step.summary;
+        let breached = step
+            .slo
+            .iter()
+            .filter(|check| !check.passed)
+            .collect::<Vec<_>>();
+        let passed = step.outcome == StepOutcome::Completed && breached.is_empty();
+        let _ = write!(
+            markdown,
+            "\n| {} | {} | {} | {} | {} | {} |",
+            if passed { "✅" } else { "❌" },
+            summary.target_throughput,
+            summary.achieved_throughput,
+            summary.streams,
+            summary.failed_streams,
+            summary.closed_early_streams,
+        );
+        for index in MARKDOWN_PERCENTILES {
+            let latency = summary.percentiles[index];
+            let _ = write!(markdown, " {:.3} |", latency.as_secs_f64() * 1e3);
+        }
+        let slos = match (step.slo.len(), breached.as_slice()) {
+            (0, _) => String::new(),
+            (checked, []) => format!("{checked} passed"),
+            (_, breached) => breached
+                .iter()
+                .map(|check| format!("`{}` breached", check.slo))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let _ = write!(markdown, " {slos} |");
+    }
+    markdown.push('\n');
+
+    markdown
+}
+
+/// Escapes text for XML attributes and elements.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, num::NonZeroUsize, time::Duration};
+
+    use clap::Parser as _;
+    use hdrhistogram::Histogram;
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::app::{
+        cli::Cli, error::Error, phases::Phases, report::Metadata, scheduler::Recording, slo::Slo,
+        summary::StepSummary,
+    };
+
+    #[test]
+    fn test_junit_and_markdown_reports() {
+        let cli = Cli::parse_from(["ext-proc-load-tester", "grpc://localhost:12345"]);
+        let metadata = Metadata {
+            uris: &cli.uris,
+            test_duration_s: 10,
+            throughputs: &[100, 200, 300],
+            runtime: cli.runtime,
+            threads: NonZeroUsize::MIN,
+            connections: NonZeroUsize::MIN,
+            scheduler_tasks: None,
+            histogram_precision: 3,
+            raw_samples: false,
+            stream_records: false,
+            output_format: cli.output_format,
+            balance: cli.balance,
+            transport: &cli.transport,
+            send_compression: None,
+            accept_compression: &[],
+        };
+        let mut manifest = Manifest::new(
+            "20251018T093015123Z".to_owned(),
+            OffsetDateTime::now_utc(),
+            [],
+            &metadata,
+        );
+        let mut histogram = Histogram::new(3).unwrap();
+        histogram.record(2_000_000).unwrap();
+        let slo: Slo = "p99<1ms".parse().unwrap();
+        for (target_throughput, outcome) in
+            [(100, StepOutcome::Completed), (200, StepOutcome::Saturated)]
+        {
+            let summary = StepSummary::new(
+                target_throughput,
+                Duration::from_secs(10),
+                10 * target_throughput,
+                0,
+                1,
+                &histogram,
+                &Phases::new(Recording::default()),
+            );
+            manifest.push_step(Step {
+                started_at: OffsetDateTime::now_utc(),
+                outcome,
+                slo: slo.check(&summary).into_iter().collect(),
+                summary,
+                files: BTreeMap::new(),
+                dropped_rows: BTreeMap::new(),
+            });
+        }
+        manifest.finish(&Err(Error::CouldNotReachTargetThroughput(200, 190, 95)));
+
+        let junit = junit(&manifest);
+        assert!(
+            junit.contains(r#"tests="5" failures="3" errors="0" skipped="1""#),
+            "Got {junit}"
+        );
+        assert!(
+            junit.contains(r#"name="p99&lt;1ms at 100 req/s""#),
+            "Got {junit}"
+        );
+        assert!(junit.contains(r#"<testcase classname="steps" name="300 req/s" time="0">"#));
+
+        let markdown = markdown(&manifest);
+        let lines = markdown.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "### ext-proc-load-tester `20251018T093015123Z`: saturated"
+        );
+        let rows = lines.iter().filter(|line| line.starts_with("| ❌")).count();
+        assert_eq!(rows, 2, "Got {markdown}");
+        assert!(
+            markdown.contains("| 2.001 | 2.001 | 2.001 | `p99<1ms` breached |"),
+            "Got {markdown}"
+        );
+    }
+}
@@ -1,12 +1,46 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use tonic::{codec::CompressionEncoding, transport::Endpoint};
+
+use crate::app::{
+    balancer::BalancePolicy,
+    connection::{Churn, WaitForReady},
+    export::TableFormat,
+    health::HealthCheckSettings,
+    metadata,
+    otlp::OtlpSettings,
+    scheduler::Recording,
+    slo::Slo,
+    worker::CompressionSettings,
+};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub(crate) struct Cli {
-    /// The URI of the `ext_proc` server.
-    pub(crate) uri: String,
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// The URIs of the `ext_proc` servers. Streams are spread across them according
+    /// to the balancing policy.
+    #[arg(required = true)]
+    pub(crate) uris: Vec<String>,
+
+    /// How streams are spread across the servers.
+    #[arg(long, value_enum, default_value_t = BalancePolicy::RoundRobin)]
+    pub(crate) balance: BalancePolicy,
 
     /// The duration of each throughput level in seconds.
     #[arg(long, default_value = "10", value_parser = validate_test_duration_seconds)]
@@ -28,10 +62,440 @@ pub(crate) struct Cli {
     #[arg(long, default_value_t = 25, value_parser = validate_throughput_step)]
     pub(crate) throughput_step: u64,
 
-    /// The directory to write the results to.
+    /// The directory in which each run creates the directory of its results.
     /// Defaults to the current working directory.
     #[arg(long, value_parser = validate_result_directory)]
     pub(crate) result_directory: Option<PathBuf>,
+
+    /// The number of significant decimal digits kept by the latency histograms, from 0 to 5.
+    /// Higher values are more accurate but use more memory.
+    #[arg(long, default_value_t = 3, value_parser = validate_histogram_precision)]
+    pub(crate) histogram_precision: u8,
+
+    /// Also write the latency of every stream to `durations_<rate>.json`, as the
+    /// streams end.
+    #[arg(long)]
+    pub(crate) raw_samples: bool,
+
+    /// Also write a record of every stream to `records_<rate>.ndjson`: when it was
+    /// planned, started and ended, its task, endpoint and connection, and its outcome.
+    /// Read them with the `records` command.
+    #[arg(long)]
+    pub(crate) stream_records: bool,
+
+    /// The format of the raw samples, the stream records and the step summaries.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub(crate) output_format: OutputFormat,
+
+    /// The address on which to serve live metrics in the Prometheus text format,
+    /// at `/metrics`, e.g. `127.0.0.1:9090`.
+    #[arg(long)]
+    pub(crate) metrics_listen: Option<SocketAddr>,
+
+    /// How the progress of the steps is shown. The dashboard falls back to the bars
+    /// when stdout is not a terminal.
+    #[arg(long, value_enum, default_value_t = ProgressDisplay::Bars)]
+    pub(crate) progress: ProgressDisplay,
+
+    /// Writes the progress of the run to stdout as events, in place of the progress
+    /// bars and the table of the steps, for other tools to follow the run.
+    #[arg(long, value_enum, conflicts_with = "progress")]
+    pub(crate) events: Option<EventFormat>,
+
+    #[command(flatten)]
+    pub(crate) otlp: OtlpArgs,
+
+    /// A threshold checked after each step, e.g. `p99<5ms@2000` (p99 under 5 ms for the
+    /// steps up to 2000 req/s), `error_rate<0.1%` (the failed streams, whatever their
+    /// gRPC code, with or without `--reconnect`) or `closed_early<=0`. May be repeated. The run exits with code 22 if any is breached.
+    #[arg(long = "slo", value_name = "SLO", value_parser = validate_slo)]
+    pub(crate) slos: Vec<Slo>,
+
+    /// The number of HTTP/2 connections opened to each `ext_proc` server.
+    /// Streams are spread across the connections in a round-robin fashion.
+    /// In thread-per-core mode, the connections are split evenly across the threads,
+    /// which must each get at least one.
+    /// Defaults to the number of threads.
+    #[arg(long, value_parser = validate_connections)]
+    pub(crate) connections: Option<NonZeroUsize>,
+
+    /// The number of Tokio tasks scheduling streams.
+    /// Ignored in thread-per-core mode, where each thread runs a single task.
+    /// Defaults to the number of threads.
+    #[arg(long, value_parser = validate_scheduler_tasks)]
+    pub(crate) scheduler_tasks: Option<NonZeroUsize>,
+
+    /// The number of Tokio worker threads.
+    /// Defaults to the number of available CPUs.
+    #[arg(long, value_parser = validate_threads)]
+    pub(crate) threads: Option<NonZeroUsize>,
+
+    /// How the load generator is executed.
+    #[arg(long, value_enum, default_value_t = RuntimeMode::MultiThread)]
+    pub(crate) runtime: RuntimeMode,
+
+    /// Replace each connection by a new one after it carried this many streams.
+    /// With a value of 1, every stream opens its own connection.
+    #[arg(long, value_parser = validate_churn_streams, conflicts_with = "churn_interval")]
+    pub(crate) churn_streams: Option<NonZeroU64>,
+
+    /// Replace each connection by a new one after this many seconds.
+    #[arg(long, value_parser = validate_churn_interval)]
+    pub(crate) churn_interval: Option<Duration>,
+
+    /// Keep retrying to open the initial connections for up to this many seconds,
+    /// if the server is not listening yet.
+    #[arg(long, value_parser = validate_wait_for_ready)]
+    pub(crate) wait_for_ready: Option<Duration>,
+
+    /// The delay before the first retry of the initial connections, in milliseconds.
+    /// It doubles after each attempt, up to 5 seconds.
+    #[arg(long, default_value = "100", requires = "wait_for_ready", value_parser = validate_retry_backoff)]
+    pub(crate) retry_backoff_ms: Duration,
+
+    /// Report the windows during which each server is unavailable, for instance while
+    /// it restarts. The streams failing meanwhile are counted as failed either way, and
+    /// the connections are re-established once it is back.
+    #[arg(long)]
+    pub(crate) reconnect: bool,
+
+    #[command(flatten)]
+    pub(crate) health: HealthArgs,
+
+    #[command(flatten)]
+    pub(crate) metadata: MetadataArgs,
+
+    /// Compress the messages sent to the server with this algorithm.
+    #[arg(long, value_enum)]
+    pub(crate) send_compression: Option<Compression>,
+
+    /// Accept responses compressed with this algorithm. Can be repeated.
+    #[arg(long, value_enum)]
+    pub(crate) accept_compression: Vec<Compression>,
+
+    #[command(flatten)]
+    pub(crate) tls: TlsArgs,
+
+    #[command(flatten)]
+    pub(crate) transport: TransportArgs,
+}
+
+/// Commands run instead of a load test.
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Print the stream records written with `--stream-records`.
+    Records(RecordsArgs),
+    /// Write `report.html`, a self-contained HTML report of a run, into its directory.
+    Report(ReportArgs),
+    /// Compare the latency of the steps of two runs, and fail if it regressed.
+    Compare(CompareArgs),
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ReportArgs {
+    /// The directory of the run, with its `manifest.json`.
+    pub(crate) directory: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CompareArgs {
+    /// The directory of the baseline run.
+    pub(crate) baseline: PathBuf,
+
+    /// The directory of the run compared to the baseline.
+    pub(crate) candidate: PathBuf,
+
+    /// The increase of a latency percentile, in percent, above which it is a regression.
+    #[arg(long, default_value_t = 5.0, value_parser = validate_tolerance)]
+    pub(crate) tolerance: f64,
+
+    /// The p-value of the Mann-Whitney U test below which the latency of a step is
+    /// significantly different.
+    #[arg(long, default_value_t = 0.05, value_parser = validate_significance)]
+    pub(crate) significance: f64,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RecordsArgs {
+    /// A `records_<rate>.ndjson` file.
+    pub(crate) file: PathBuf,
+
+    /// Only print the N slowest streams, slowest first.
+    #[arg(long, value_name = "N")]
+    pub(crate) slowest: Option<usize>,
+}
+
+/// Health checks gating the run, using `grpc.health.v1.Health/Check`.
+#[derive(Args, Debug, Clone)]
+pub(crate) struct HealthArgs {
+    /// Wait for the servers to report they are serving before the run and before
+    /// each throughput step.
+    #[arg(long = "health-check")]
+    pub(crate) enabled: bool,
+
+    /// The service to check. Defaults to the overall health of the server.
+    #[arg(long = "health-service", default_value = "", requires = "enabled")]
+    pub(crate) service: String,
+
+    /// The interval between two health checks, in milliseconds.
+    #[arg(long = "health-interval-ms", default_value = "500", requires = "enabled", value_parser = validate_health_interval)]
+    pub(crate) interval: Duration,
+
+    /// How long to wait for the servers to be serving before giving up, in seconds.
+    #[arg(long = "health-timeout", default_value = "60", requires = "enabled", value_parser = validate_health_timeout)]
+    pub(crate) timeout: Duration,
+}
+
+/// Export of the metrics, and of the spans of a sample of the streams, to an
+/// OpenTelemetry collector over OTLP/gRPC. Nothing is exported by default.
+#[derive(Args, Debug, Clone)]
+pub(crate) struct OtlpArgs {
+    /// The OTLP/gRPC endpoint of the collector, e.g. `http://localhost:4317`.
+    #[arg(long = "otlp-endpoint", id = "otlp_endpoint", value_parser = validate_otlp_endpoint)]
+    pub(crate) endpoint: Option<Endpoint>,
+
+    /// The interval between two exports of the metrics, in seconds.
+    #[arg(long = "otlp-interval", id = "otlp_interval", default_value = "10", requires = "otlp_endpoint", value_parser = validate_otlp_interval)]
+    pub(crate) interval: Duration,
+
+    /// The fraction of the streams exported as spans, from 0 to 1. The `traceparent`
+    /// of each span is sent to the server, so that its spans join the trace.
+    #[arg(long = "otlp-trace-ratio", id = "otlp_trace_ratio", default_value = "0", requires = "otlp_endpoint", value_parser = validate_otlp_trace_ratio)]
+    pub(crate) trace_ratio: f64,
+}
+
+/// gRPC metadata attached to every `process` call.
+#[derive(Args, Debug, Clone)]
+pub(crate) struct MetadataArgs {
+    /// A metadata entry, formatted as `key=value`. Can be repeated.
+    #[arg(long = "grpc-metadata", value_parser = validate_grpc_metadata)]
+    pub(crate) metadata: Vec<(String, String)>,
+
+    /// A file of metadata entries, one `key=value` per line.
+    /// Empty lines and lines starting with `#` are ignored.
+    #[arg(long = "grpc-metadata-file", value_parser = validate_file)]
+    pub(crate) metadata_file: Option<PathBuf>,
+
+    /// A file containing a token, sent as `authorization: Bearer <token>`.
+    /// The file is re-read when it changes, so the token can be rotated during a run.
+    #[arg(long, value_parser = validate_file)]
+    pub(crate) bearer_token_file: Option<PathBuf>,
+}
+
+/// TLS settings of the connections to the `ext_proc` server.
+///
+/// TLS is used when the URI scheme is `https`, or when any of these options is set.
+#[derive(Args, Debug, Clone)]
+pub(crate) struct TlsArgs {
+    /// The CA bundle (PEM) used to verify the server certificate.
+    /// Defaults to the Mozilla root certificates.
+    #[arg(long = "tls-ca", value_parser = validate_file)]
+    pub(crate) ca: Option<PathBuf>,
+
+    /// The client certificate chain (PEM) presented to the server, for mTLS.
+    #[arg(long = "tls-cert", value_parser = validate_file, requires = "key")]
+    pub(crate) cert: Option<PathBuf>,
+
+    /// The private key (PEM) of the client certificate.
+    #[arg(long = "tls-key", value_parser = validate_file, requires = "cert")]
+    pub(crate) key: Option<PathBuf>,
+
+    /// The server name sent in the SNI extension and used to verify the server certificate.
+    /// Defaults to the host of the URI.
+    #[arg(long = "tls-server-name")]
+    pub(crate) server_name: Option<String>,
+
+    /// The ALPN protocols offered to the server, in order of preference.
+    #[arg(long = "tls-alpn", default_value = "h2")]
+    pub(crate) alpn: Vec<String>,
+}
+
+/// HTTP/2 and TCP settings of the connections to the `ext_proc` server.
+///
+/// Unset values use the `tonic` defaults. The values are recorded in the run metadata.
+#[derive(Args, Debug, Clone, Serialize)]
+pub(crate) struct TransportArgs {
+    /// The initial HTTP/2 stream window size, in bytes.
+    #[arg(long)]
+    pub(crate) http2_initial_stream_window_size: Option<u32>,
+
+    /// The initial HTTP/2 connection window size, in bytes.
+    #[arg(long)]
+    pub(crate) http2_initial_connection_window_size: Option<u32>,
+
+    /// Use HTTP/2 adaptive flow control, overriding the initial window sizes.
+    #[arg(long)]
+    pub(crate) http2_adaptive_window: bool,
+
+    /// The interval between HTTP/2 keepalive pings, in seconds.
+    /// Keepalive pings are disabled when unset.
+    #[arg(long)]
+    pub(crate) http2_keepalive_interval: Option<u64>,
+
+    /// How long to wait for the acknowledgement of a keepalive ping before closing the connection, in seconds.
+    #[arg(long, requires = "http2_keepalive_interval")]
+    pub(crate) http2_keepalive_timeout: Option<u64>,
+
+    /// Send keepalive pings even when there is no stream in flight.
+    #[arg(long, requires = "http2_keepalive_interval")]
+    pub(crate) http2_keepalive_while_idle: bool,
+
+    /// The maximum HTTP/2 frame size, in bytes.
+    #[arg(long)]
+    pub(crate) http2_max_frame_size: Option<u32>,
+
+    /// The maximum number of concurrent streams on a connection, enforced by the load tester.
+    #[arg(long)]
+    pub(crate) max_concurrent_streams: Option<usize>,
+
+    /// The number of requests buffered by each connection before the streams wait.
+    #[arg(long)]
+    pub(crate) request_buffer_size: Option<usize>,
+
+    /// Disable Nagle's algorithm on TCP connections.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub(crate) tcp_nodelay: bool,
+
+    /// The size of the TCP send buffer (`SO_SNDBUF`), in bytes.
+    #[arg(long)]
+    pub(crate) tcp_send_buffer_size: Option<u32>,
+
+    /// The size of the TCP receive buffer (`SO_RCVBUF`), in bytes.
+    #[arg(long)]
+    pub(crate) tcp_recv_buffer_size: Option<u32>,
+
+    /// How long to wait for a connection to be established, in milliseconds.
+    #[arg(long)]
+    pub(crate) connect_timeout_ms: Option<u64>,
+}
+
+impl Cli {
+    /// Whether the connections to the `ext_proc` server use TLS.
+    pub(crate) fn uses_tls(&self) -> bool {
+        self.uris.iter().any(|uri| uri.starts_with("https://"))
+            || self.tls.ca.is_some()
+            || self.tls.cert.is_some()
+            || self.tls.server_name.is_some()
+    }
+
+    /// The connection churn policy selected on the command line.
+    pub(crate) fn churn(&self) -> Churn {
+        match (self.churn_streams, self.churn_interval) {
+            (Some(streams), _) => Churn::EveryStreams(streams),
+            (None, Some(interval)) => Churn::EveryInterval(interval),
+            (None, None) => Churn::Never,
+        }
+    }
+
+    pub(crate) fn compression(&self) -> CompressionSettings {
+        CompressionSettings {
+            send: self.send_compression.map(CompressionEncoding::from),
+            accept: self
+                .accept_compression
+                .iter()
+                .copied()
+                .map(CompressionEncoding::from)
+                .collect(),
+        }
+    }
+
+    pub(crate) fn health_check(&self) -> Option<HealthCheckSettings> {
+        self.health.enabled.then(|| HealthCheckSettings {
+            service: self.health.service.clone(),
+            interval: self.health.interval,
+            timeout: self.health.timeout,
+        })
+    }
+
+    pub(crate) fn otlp(&self) -> Option<OtlpSettings> {
+        self.otlp.endpoint.clone().map(|endpoint| OtlpSettings {
+            endpoint,
+            interval: self.otlp.interval,
+            trace_ratio: self.otlp.trace_ratio,
+        })
+    }
+
+    pub(crate) fn recording(&self) -> Recording {
+        Recording {
+            precision: self.histogram_precision,
+        }
+    }
+
+    pub(crate) fn wait_for_ready(&self) -> Option<WaitForReady> {
+        self.wait_for_ready.map(|timeout| WaitForReady {
+            timeout,
+            initial_backoff: self.retry_backoff_ms,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RuntimeMode {
+    /// Scheduler tasks share a multi-threaded Tokio runtime.
+    MultiThread,
+    /// Each scheduler task runs on its own OS thread pinned to a CPU core, inside a
+    /// current-thread Tokio runtime owning its own connections.
+    ThreadPerCore,
+}
+
+/// How the progress of the steps is shown, see `--progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ProgressDisplay {
+    /// A progress bar per step, with the summary of the step once it ended.
+    Bars,
+    /// A full-screen view of the achieved rate against the target, the rolling p99
+    /// latency, the errors by gRPC code, the streams in flight and the CPU used by
+    /// the load tester. Press q to quit.
+    Dashboard,
+}
+
+/// The format of the events of `--events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum EventFormat {
+    /// A JSON object per line, see the README for the schema.
+    Ndjson,
+}
+
+/// The format of the files of `--output-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    /// JSON arrays for the raw samples and NDJSON for the stream records.
+    /// The step summaries are only in `manifest.json`.
+    Json,
+    /// CSV files with a header row, and `steps.csv` for the step summaries.
+    Csv,
+    /// Parquet files compressed with zstd, and `steps.parquet` for the step summaries.
+    Parquet,
+}
+
+impl OutputFormat {
+    /// The format of the tables, unless the files keep their JSON formats.
+    pub(crate) fn table_format(self) -> Option<TableFormat> {
+        match self {
+            OutputFormat::Json => None,
+            OutputFormat::Csv => Some(TableFormat::Csv),
+            OutputFormat::Parquet => Some(TableFormat::Parquet),
+        }
+    }
+}
+
+/// A gRPC message compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
 }
 
 fn validate_test_duration_seconds(v: &str) -> Result<Duration, String> {
@@ -95,3 +559,165 @@ fn validate_result_directory(v: &str) -> Result<PathBuf, String> {
 
     Ok(v)
 }
+
+fn validate_connections(v: &str) -> Result<NonZeroUsize, String> {
+    v.parse()
+        .map_err(|_| format!("connections must be a strictly positive integer, got {v}"))
+}
+
+fn validate_scheduler_tasks(v: &str) -> Result<NonZeroUsize, String> {
+    v.parse()
+        .map_err(|_| format!("scheduler tasks must be a strictly positive integer, got {v}"))
+}
+
+fn validate_threads(v: &str) -> Result<NonZeroUsize, String> {
+    v.parse()
+        .map_err(|_| format!("threads must be a strictly positive integer, got {v}"))
+}
+
+fn validate_churn_streams(v: &str) -> Result<NonZeroU64, String> {
+    v.parse()
+        .map_err(|_| format!("churn streams must be a strictly positive integer, got {v}"))
+}
+
+fn validate_churn_interval(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("churn interval must be a integer (seconds), got {v}"))?;
+
+    if v < 1 {
+        return Err(format!("churn interval must be strictly positive, got {v}"));
+    }
+
+    Ok(Duration::from_secs(v))
+}
+
+fn validate_wait_for_ready(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("wait for ready must be a integer (seconds), got {v}"))?;
+
+    Ok(Duration::from_secs(v))
+}
+
+fn validate_retry_backoff(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("retry backoff must be a integer (milliseconds), got {v}"))?;
+
+    if v < 1 {
+        return Err(format!("retry backoff must be strictly positive, got {v}"));
+    }
+
+    Ok(Duration::from_millis(v))
+}
+
+fn validate_grpc_metadata(v: &str) -> Result<(String, String), String> {
+    let (key, value) = metadata::parse_entry(v)?;
+
+    Ok((
+        key.to_string(),
+        value.to_str().map_err(|e| e.to_string())?.to_string(),
+    ))
+}
+
+fn validate_histogram_precision(v: &str) -> Result<u8, String> {
+    let v: u8 = v
+        .parse()
+        .map_err(|_| format!("histogram precision must be a integer, got {v}"))?;
+
+    if v > 5 {
+        return Err(format!("histogram precision must be at most 5, got {v}"));
+    }
+
+    Ok(v)
+}
+
+fn validate_health_interval(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("health interval must be a integer (milliseconds), got {v}"))?;
+
+    if v < 1 {
+        return Err(format!(
+            "health interval must be strictly positive, got {v}"
+        ));
+    }
+
+    Ok(Duration::from_millis(v))
+}
+
+fn validate_health_timeout(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("health timeout must be a integer (seconds), got {v}"))?;
+
+    Ok(Duration::from_secs(v))
+}
+
+fn validate_file(v: &str) -> Result<PathBuf, String> {
+    let v = PathBuf::from(v);
+
+    if !v.is_file() {
+        return Err(format!("{} is not a file", v.display()));
+    }
+
+    Ok(v)
+}
+
+fn validate_otlp_endpoint(v: &str) -> Result<Endpoint, String> {
+    Endpoint::from_shared(v.to_string())
+        .map_err(|_| format!("OTLP endpoint must be a URI, got {v}"))
+}
+
+fn validate_otlp_interval(v: &str) -> Result<Duration, String> {
+    let v: u64 = v
+        .parse()
+        .map_err(|_| format!("OTLP interval must be a integer (seconds), got {v}"))?;
+
+    if v < 1 {
+        return Err(format!("OTLP interval must be strictly positive, got {v}"));
+    }
+
+    Ok(Duration::from_secs(v))
+}
+
+fn validate_otlp_trace_ratio(v: &str) -> Result<f64, String> {
+    let v: f64 = v
+        .parse()
+        .map_err(|_| format!("OTLP trace ratio must be a number, got {v}"))?;
+
+    if !(0.0..=1.0).contains(&v) {
+        return Err(format!("OTLP trace ratio must be between 0 and 1, got {v}"));
+    }
+
+    Ok(v)
+}
+
+fn validate_tolerance(v: &str) -> Result<f64, String> {
+    let v: f64 = v
+        .parse()
+        .map_err(|_| format!("tolerance must be a number (percent), got {v}"))?;
+
+    if !(0.0..).contains(&v) {
+        return Err(format!("tolerance must be positive, got {v}"));
+    }
+
+    Ok(v)
+}
+
+fn validate_significance(v: &str) -> Result<f64, String> {
+    let v: f64 = v
+        .parse()
+        .map_err(|_| format!("significance must be a number, got {v}"))?;
+
+    if !(f64::MIN_POSITIVE..1.0).contains(&v) {
+        return Err(format!("significance must be between 0 and 1, got {v}"));
+    }
+
+    Ok(v)
+}
+
+fn validate_slo(v: &str) -> Result<Slo, String> {
+    v.parse().map_err(|e| format!("invalid SLO {v}: {e}"))
+}
@@ -0,0 +1,437 @@
+use std::{collections::BTreeMap, fmt::Write as _, io, path::Path, time::Duration};
+
+use hdrhistogram::Histogram;
+
+use crate::app::{
+    cli::CompareArgs,
+    error::{Error, Result},
+    manifest::{self, RunFiles},
+    report,
+    summary::{PERCENTILES, float},
+};
+
+/// The latency of the streams of a step, read back from its files.
+#[derive(Debug)]
+struct StepSamples {
+    histogram: Histogram<u64>,
+    /// The latency of every stream, if it was written with `--raw-samples` as JSON.
+    durations: Option<Vec<u64>>,
+}
+
+impl StepSamples {
+    /// The number of streams by latency, in nanoseconds.
+    fn counts(&self, raw: bool) -> BTreeMap<u64, u64> {
+        let mut counts = BTreeMap::new();
+        match &self.durations {
+            Some(durations) if raw => {
+                for duration in durations {
+                    *counts.entry(*duration).or_default() += 1;
+                }
+            }
+            _ => {
+                for value in self.histogram.iter_recorded() {
+                    *counts.entry(value.value_iterated_to()).or_default() += value.count_at_value();
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// The result of a Mann-Whitney U test between the latency of two steps, with the
+/// normal approximation and the correction for ties.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MannWhitney {
+    /// The probability of a difference at least as large between two samples of
+    /// the same distribution.
+    p_value: f64,
+    /// The probability that a stream of the candidate is slower than a stream of the
+    /// baseline, ties counting half.
+    slower: f64,
+}
+
+impl MannWhitney {
+    fn new(baseline: &BTreeMap<u64, u64>, candidate: &BTreeMap<u64, u64>) -> Option<Self> {
+        let mut pooled = BTreeMap::<u64, (f64, f64)>::new();
+        for (latency, count) in baseline {
+            pooled.entry(*latency).or_default().0 += float(*count);
+        }
+        for (latency, count) in candidate {
+            pooled.entry(*latency).or_default().1 += float(*count);
+        }
+        let (n1, n2) = pooled
+            .values()
+            .fold((0.0, 0.0), |(n1, n2), (b, c)| (n1 + b, n2 + c));
+        if n1 == 0.0 || n2 == 0.0 {
+            return None;
+        }
+
+        // Streams with the same latency all get the mean of their ranks.
+        let (mut ranked, mut rank_sum, mut ties) = (0.0, 0.0, 0.0);
+        for (b, c) in pooled.into_values() {
+            let streams = b + c;
+            rank_sum += c * (ranked + f64::midpoint(1.0, streams));
+            ranked += streams;
+            ties += streams.powi(3) - streams;
+        }
+
+        let n = n1 + n2;
+        let u = rank_sum - n2 * (n2 + 1.0) / 2.0;
+        let variance = n1 * n2 / 12.0 * (n + 1.0 - ties / (n * (n - 1.0)));
+        let p_value = if variance > 0.0 {
+            erfc((u - n1 * n2 / 2.0).abs() / (2.0 * variance).sqrt())
+        } else {
+            1.0
+        };
+
+        Some(Self {
+            p_value,
+            slower: u / (n1 * n2),
+        })
+    }
+}
+
+/// The latency of a step in both runs.
+#[derive(Debug)]
+struct Comparison {
+    target_throughput: u64,
+    /// The latency at each of `PERCENTILES`, in the baseline and in the candidate.
+    percentiles: [(Duration, Duration); PERCENTILES.len()],
+    /// `None` if a run has no stream in the step.
+    test: Option<MannWhitney>,
+}
+
+impl Comparison {
+    fn new(target_throughput: u64, baseline: &StepSamples, candidate: &StepSamples) -> Self {
+        let latency = |histogram: &Histogram<u64>, percentile| {
+            Duration::from_nanos(histogram.value_at_percentile(percentile))
+        };
+        // NOTE: The raw samples are more accurate than the histograms, but values
+        // from both cannot be ranked together.
+        let raw = baseline.durations.is_some() && candidate.durations.is_some();
+
+        Self {
+            target_throughput,
+            percentiles: PERCENTILES.map(|percentile| {
+                (
+                    latency(&baseline.histogram, percentile),
+                    latency(&candidate.histogram, percentile),
+                )
+            }),
+            test: MannWhitney::new(&baseline.counts(raw), &candidate.counts(raw)),
+        }
+    }
+
+    /// Whether the candidate is significantly slower, and the latency at a percentile
+    /// increased by more than the tolerance.
+    fn regressed(&self, delta: Option<f64>, args: &CompareArgs) -> bool {
+        self.test.is_some_and(|test| {
+            test.p_value < args.significance
+                && test.slower > 0.5
+                && delta.is_some_and(|delta| delta > args.tolerance)
+        })
+    }
+}
+
+/// Compares the latency of the steps of two runs with the same target throughput,
+/// for the `compare` command. Fails if the latency regressed beyond the tolerance,
+/// or if the candidate did not reach a step of the baseline.
+pub(crate) fn compare(args: &CompareArgs) -> Result<()> {
+    let baseline = read_steps(&args.baseline).map_err(Error::FailedToReadRun)?;
+    let mut candidate = read_steps(&args.candidate).map_err(Error::FailedToReadRun)?;
+
+    let mut comparisons = Vec::new();
+    let mut missing = Vec::new();
+    for (target_throughput, baseline) in &baseline {
+        match candidate.remove(target_throughput) {
+            Some(candidate) => {
+                comparisons.push(Comparison::new(*target_throughput, baseline, &candidate));
+            }
+            None => missing.push(*target_throughput),
+        }
+    }
+
+    let (table, regressions) = table(&comparisons, args);
+    println!("{table}");
+    for target_throughput in &missing {
+        println!("{target_throughput} req/s: only in the baseline, regressed");
+    }
+    for target_throughput in candidate.keys() {
+        println!("{target_throughput} req/s: only in the candidate");
+    }
+
+    match regressions + missing.len() {
+        0 => Ok(()),
+        regressions => Err(Error::LatencyRegressed(regressions)),
+    }
+}
+
+/// Reads the latency of the steps of a run, by target throughput.
+///
+/// The latency of a step is read from its histogram, or from its raw samples for a
+/// run written before the histograms were. A step with neither is invalid, rather
+/// than skipped, so that it cannot hide a regression. Raw samples with dropped rows
+/// are left out, the histogram has every stream.
+fn read_steps(directory: &Path) -> io::Result<BTreeMap<u64, StepSamples>> {
+    let run: RunFiles = serde_json::from_value(manifest::read(directory)?)?;
+    run.steps
+        .iter()
+        .map(|step| {
+            let target_throughput = step.summary.target_throughput;
+            let durations = step
+                .complete_path(directory, "durations")
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "json")
+                })
+                .map(|path| report::read_durations(&path))
+                .transpose()?;
+            let histogram = match (step.path(directory, "histogram"), &durations) {
+                (Some(path), _) => report::read_histogram(&path)?,
+                (None, Some(durations)) => report::durations_histogram(durations),
+                (None, None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the {target_throughput} req/s step of {} has no latency histogram",
+                            directory.display()
+                        ),
+                    ));
+                }
+            };
+            Ok((
+                target_throughput,
+                StepSamples {
+                    histogram,
+                    durations,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Formats the comparisons as a table, one row per step and percentile, and counts
+/// the regressions. Latencies are in milliseconds.
+fn table(comparisons: &[Comparison], args: &CompareArgs) -> (String, usize) {
+    let mut table = format!(
+        "{:>10} {:>10} {:>10} {:>10} {:>8} {:>8} {:>8}",
+        "target/s", "percentile", "baseline", "candidate", "delta%", "p-value", "slower%"
+    );
+    let mut regressions = 0;
+    for comparison in comparisons {
+        for (percentile, (baseline, candidate)) in PERCENTILES.iter().zip(comparison.percentiles) {
+            let delta = (!baseline.is_zero())
+                .then(|| (candidate.as_secs_f64() / baseline.as_secs_f64() - 1.0) * 100.0);
+            let _ = write!(
+                table,
+                "\n{:>10} {:>10} {:>10.3} {:>10.3} {:>8}",
+                comparison.target_throughput,
+                format!("p{percentile}"),
+                baseline.as_secs_f64() * 1e3,
+                candidate.as_secs_f64() * 1e3,
+                delta.map_or_else(|| "-".to_owned(), |delta| format!("{delta:+.1}")),
+            );
+            if let Some(test) = comparison.test {
+                let _ = write!(table, " {:>8.4} {:>8.1}", test.p_value, test.slower * 100.0);
+            }
+            if comparison.regressed(delta, args) {
+                table.push_str(" regressed");
+                regressions += 1;
+            }
+        }
+    }
+
+    (table, regressions)
+}
+
+/// The complementary error function, with a fractional error below 1.2e-7
+/// (Numerical Recipes, `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let coefficients = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ];
+    let polynomial = coefficients
+        .iter()
+        .rev()
+        .fold(0.0, |sum, coefficient| sum * t + coefficient);
+    let erfc = t * (polynomial - z * z).exp();
+    if x >= 0.0 { erfc } else { 2.0 - erfc }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::SystemTime};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::app::manifest::FORMAT_VERSION;
+
+    fn counts(latencies: impl IntoIterator<Item = u64>) -> BTreeMap<u64, u64> {
+        let mut counts = BTreeMap::new();
+        for latency in latencies {
+            *counts.entry(latency).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_mann_whitney() {
+        let test = MannWhitney::new(&counts(1..=5), &counts(6..=10)).unwrap();
+        assert!((test.slower - 1.0).abs() < 1e-9);
+        // NOTE: U = 25, with a mean of 12.5 and a variance of 275 / 12.
+        assert!((test.p_value - 0.009_024).abs() < 1e-5, "Got {test:?}");
+
+        let test = MannWhitney::new(&counts([1, 2, 2, 3]), &counts([1, 2, 2, 3])).unwrap();
+        assert!((test.slower - 0.5).abs() < 1e-9);
+        assert!((test.p_value - 1.0).abs() < 1e-6, "Got {test:?}");
+
+        assert!(MannWhitney::new(&counts([]), &counts([1])).is_none());
+    }
+
+    async fn write_run(directory: &Path, latencies_ms: impl IntoIterator<Item = u64> + Clone) {
+        let mut steps = Vec::new();
+        for target_throughput in [100, 200] {
+            let mut histogram = Histogram::<u64>::new(3).unwrap();
+            for latency in latencies_ms.clone() {
+                histogram.record(latency * 1_000_000).unwrap();
+            }
+            report::write_histogram(
+                directory,
+                target_throughput,
+                SystemTime::now(),
+                Duration::from_secs(10),
+                &histogram,
+            )
+            .await
+            .unwrap();
+            steps.push(serde_json::json!({
+                "summary": { "target_throughput": target_throughput },
+                "files": { "histogram": format!("histogram_{target_throughput}.hlog") },
+            }));
+        }
+        let manifest = serde_json::json!({ "format_version": FORMAT_VERSION, "steps": steps });
+        fs::write(directory.join("manifest.json"), manifest.to_string()).unwrap();
+    }
+
+    fn args(baseline: &TempDir, candidate: &TempDir) -> CompareArgs {
+        CompareArgs {
+            baseline: baseline.path().to_owned(),
+            candidate: candidate.path().to_owned(),
+            tolerance: 10.0,
+            significance: 0.05,
+        }
+    }
+
+    /// Writes the `durations_<rate>.json` files of a run written before the manifest.
+    fn write_legacy_run(directory: &Path, latencies_ms: impl IntoIterator<Item = u64> + Clone) {
+        let durations = latencies_ms
+            .into_iter()
+            .map(|latency| latency * 1_000_000)
+            .collect::<Vec<_>>();
+        for target_throughput in [100, 200] {
+            fs::write(
+                directory.join(format!("durations_{target_throughput}.json")),
+                serde_json::to_string(&durations).unwrap(),
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare_reads_runs_without_manifest() {
+        let (baseline, same, slower) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        write_legacy_run(baseline.path(), 100..=1_000);
+        write_run(same.path(), 100..=1_000).await;
+        write_legacy_run(slower.path(), 120..=1_200);
+
+        compare(&args(&baseline, &same)).unwrap();
+        match compare(&args(&baseline, &slower)) {
+            Err(Error::LatencyRegressed(regressions)) => {
+                assert_eq!(regressions, 2 * PERCENTILES.len());
+            }
+            result => panic!("Expected a regression, got {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare_flags_regressions() {
+        let (baseline, same, slower) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        write_run(baseline.path(), 100..=1_000).await;
+        write_run(same.path(), 100..=1_000).await;
+        write_run(slower.path(), 120..=1_200).await;
+
+        compare(&args(&baseline, &same)).unwrap();
+        match compare(&args(&baseline, &slower)) {
+            Err(Error::LatencyRegressed(regressions)) => {
+                assert_eq!(regressions, 2 * PERCENTILES.len());
+            }
+            result => panic!("Expected a regression, got {result:?}"),
+        }
+        // Faster is not a regression.
+        compare(&args(&slower, &baseline)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compare_ignores_raw_samples_with_dropped_rows() {
+        let directory = TempDir::new().unwrap();
+        write_run(directory.path(), 100..=1_000).await;
+        fs::write(directory.path().join("durations_100.json"), "[1000000]").unwrap();
+        let manifest = serde_json::json!({
+            "format_version": FORMAT_VERSION,
+            "steps": [{
+                "summary": { "target_throughput": 100 },
+                "files": {
+                    "histogram": "histogram_100.hlog",
+                    "durations": "durations_100.json",
+                },
+                "dropped_rows": { "durations": 900 },
+            }],
+        });
+        fs::write(directory.path().join("manifest.json"), manifest.to_string()).unwrap();
+
+        let steps = read_steps(directory.path()).unwrap();
+        assert!(steps[&100].durations.is_none());
+        assert_eq!(steps[&100].histogram.len(), 901);
+    }
+
+    #[tokio::test]
+    async fn test_compare_fails_on_steps_without_latency() {
+        let (baseline, candidate) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        write_run(baseline.path(), 100..=1_000).await;
+        let manifest = serde_json::json!({
+            "format_version": FORMAT_VERSION,
+            "steps": [
+                { "summary": { "target_throughput": 100 }, "files": {} },
+                { "summary": { "target_throughput": 200 }, "files": {} },
+            ],
+        });
+        fs::write(candidate.path().join("manifest.json"), manifest.to_string()).unwrap();
+
+        match compare(&args(&baseline, &candidate)) {
+            Err(Error::FailedToReadRun(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+            result => panic!("Expected the run to be invalid, got {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::{self, Instant};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::app::{
+    error::{Error, Result},
+    transport::Connector,
+};
+
+/// When the connections of a `ConnectionPool` are replaced by new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Churn {
+    /// Connections are opened once and reused for the whole run.
+    Never,
+    /// A connection is replaced once it has carried this many streams.
+    /// With a value of 1, every stream opens its own connection.
+    EveryStreams(NonZeroU64),
+    /// A connection is replaced once it is older than this.
+    EveryInterval(Duration),
+}
+
+/// Identifier of the next connection opened, unique within the run.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Maximum delay between two attempts to open the initial connections.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How long to keep retrying to open the initial connections while the server is
+/// not ready yet, instead of failing on the first attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WaitForReady {
+    pub(crate) timeout: Duration,
+    /// Delay before the first retry, doubled after each attempt up to `MAX_BACKOFF`.
+    pub(crate) initial_backoff: Duration,
+}
+
+/// A fixed set of HTTP/2 connections to the `ext_proc` server.
+///
+/// Streams are spread across the connections in a round-robin fashion, so the
+/// number of connections is independent from the number of scheduler tasks.
+/// Depending on the `Churn` policy, connections are replaced by new ones during
+/// the run, to exercise the connection setup of the server.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionPool {
+    endpoint: Endpoint,
+    connector: Connector,
+    churn: Churn,
+    connections: Connections,
+    next: Arc<AtomicUsize>,
+}
+
+/// The connections of a `ConnectionPool`, only locked when they can be replaced.
+#[derive(Debug, Clone)]
+enum Connections {
+    Fixed(Arc<[Connection]>),
+    /// The lock is only held to pick a connection, never while opening a new one.
+    Churned(Arc<[Mutex<Connection>]>),
+}
+
+impl Connections {
+    fn len(&self) -> usize {
+        match self {
+            Self::Fixed(connections) => connections.len(),
+            Self::Churned(connections) => connections.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    id: u64,
+    channel: Channel,
+    opened_at: Instant,
+    streams: u64,
+    /// Duration of the TLS handshake, if the connection uses TLS.
+    tls_handshake: Option<Duration>,
+    /// Whether a stream is opening the connection replacing this one, in which
+    /// case the other streams keep using this one until it is done.
+    replacing: bool,
+}
+
+impl Connection {
+    async fn open(endpoint: &Endpoint, connector: &Connector) -> Result<Self> {
+        let connector = connector.for_connection();
+        let channel = endpoint
+            .connect_with_connector(connector.clone())
+            .await
+            .map_err(Error::FailedToConnectToEndpoint)?;
+
+        Ok(Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            channel,
+            opened_at: Instant::now(),
+            streams: 0,
+            tls_handshake: connector.tls_handshake(),
+            replacing: false,
+        })
+    }
+
+    /// Opens a connection, retrying with an exponential backoff until the server is ready.
+    async fn open_when_ready(
+        endpoint: &Endpoint,
+        connector: &Connector,
+        wait_for_ready: WaitForReady,
+    ) -> Result<Self> {
+        let deadline = Instant::now() + wait_for_ready.timeout;
+        let mut backoff = wait_for_ready.initial_backoff;
+        loop {
+            match Self::open(endpoint, connector).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) if Instant::now() + backoff > deadline => return Err(e),
+                Err(_) => {
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Whether the churn policy requires replacing this connection.
+    fn expired(&self, churn: Churn) -> bool {
+        match churn {
+            Churn::Never => false,
+            Churn::EveryStreams(streams) => self.streams >= streams.get(),
+            Churn::EveryInterval(interval) => self.opened_at.elapsed() >= interval,
+        }
+    }
+
+    fn acquired(&self, start: Instant, new_connection: bool) -> AcquiredChannel {
+        AcquiredChannel {
+            channel: self.channel.clone(),
+            connection: self.id,
+            wait: start.elapsed(),
+            new_connection,
+            tls_handshake: self.tls_handshake.filter(|_| new_connection),
+        }
+    }
+}
+
+/// A channel handed out by a `ConnectionPool` for a single stream.
+#[derive(Debug)]
+pub(crate) struct AcquiredChannel {
+    pub(crate) channel: Channel,
+    /// Identifier of the connection, unique within the run.
+    pub(crate) connection: u64,
+    /// Time spent waiting for the channel, including opening a new connection.
+    pub(crate) wait: Duration,
+    /// Whether a new connection was opened for this stream.
+    pub(crate) new_connection: bool,
+    /// Duration of the TLS handshake of the new connection, if any.
+    pub(crate) tls_handshake: Option<Duration>,
+}
+
+impl ConnectionPool {
+    /// Opens `connections` connections to the given endpoint, using `connector`.
+    ///
+    /// With `wait_for_ready`, connection failures are retried until its timeout.
+    pub(crate) async fn connect(
+        endpoint: Endpoint,
+        connector: Connector,
+        connections: NonZeroUsize,
+        churn: Churn,
+        wait_for_ready: Option<WaitForReady>,
+    ) -> Result<Self> {
+        let mut opened = Vec::with_capacity(connections.get());
+        for _ in 0..connections.get() {
+            let connection = match wait_for_ready {
+                Some(wait_for_ready) => {
+                    Connection::open_when_ready(&endpoint, &connector, wait_for_ready).await?
+                }
+                None => Connection::open(&endpoint, &connector).await?,
+            };
+            opened.push(connection);
+        }
+        let connections = match churn {
+            Churn::Never => Connections::Fixed(opened.into()),
+            _ => Connections::Churned(opened.into_iter().map(Mutex::new).collect()),
+        };
+
+        Ok(Self {
+            endpoint,
+            connector,
+            churn,
+            connections,
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns the channel to use for the next stream, opening a new connection
+    /// first if the churn policy requires it.
+    pub(crate) async fn acquire(&self) -> Result<AcquiredChannel> {
+        let start = Instant::now();
+
+        if self.churn == Churn::EveryStreams(NonZeroU64::MIN) {
+            // NOTE: The connection is never shared, so there is no need to keep it in the pool.
+            let connection = Connection::open(&self.endpoint, &self.connector).await?;
+            return Ok(AcquiredChannel {
+                channel: connection.channel,
+                connection: connection.id,
+                wait: start.elapsed(),
+                new_connection: true,
+                tls_handshake: connection.tls_handshake,
+            });
+        }
+
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        let connections = match &self.connections {
+            Connections::Fixed(connections) => return Ok(connections[idx].acquired(start, false)),
+            Connections::Churned(connections) => connections,
+        };
+        let slot = &connections[idx];
+        {
+            let mut connection = slot.lock().expect("connection lock is not poisoned");
+            if connection.replacing || !connection.expired(self.churn) {
+                connection.streams += 1;
+                return Ok(connection.acquired(start, false));
+            }
+            connection.replacing = true;
+        }
+
+        // NOTE: The previous connection is closed once the streams still using it are done.
+        let opened = Connection::open(&self.endpoint, &self.connector).await;
+        let mut connection = slot.lock().expect("connection lock is not poisoned");
+        match opened {
+            Ok(opened) => *connection = opened,
+            Err(e) => {
+                connection.replacing = false;
+                return Err(e);
+            }
+        }
+        connection.streams += 1;
+        Ok(connection.acquired(start, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::app::{
+        test_server,
+        transport::{Target, TcpSettings},
+    };
+
+    async fn connect(
+        directory: &TempDir,
+        churn: Churn,
+        wait_for_ready: Option<WaitForReady>,
+    ) -> Result<ConnectionPool> {
+        let (target, uri) = Target::parse(
+            &format!("unix:{}", directory.path().join("ext_proc.sock").display()),
+            false,
+        );
+        ConnectionPool::connect(
+            Endpoint::new(uri).unwrap(),
+            Connector::new(target, TcpSettings::default(), None),
+            NonZeroUsize::MIN,
+            churn,
+            wait_for_ready,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_connect_waits_for_ready_server() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let _handle = tokio::spawn(async move {
+            time::sleep(Duration::from_millis(300)).await;
+            test_server::serve_unix(UnixListener::bind(path).unwrap());
+        });
+
+        let wait_for_ready = WaitForReady {
+            timeout: Duration::from_secs(10),
+            initial_backoff: Duration::from_millis(50),
+        };
+        let result = connect(&directory, Churn::Never, Some(wait_for_ready)).await;
+
+        assert!(result.is_ok(), "Expected to connect, got {result:?}");
+    }
+
+    #[tokio::test]
+    async fn test_connect_gives_up_after_wait_for_ready_timeout() {
+        let directory = TempDir::new().unwrap();
+
+        let wait_for_ready = WaitForReady {
+            timeout: Duration::from_millis(200),
+            initial_backoff: Duration::from_millis(50),
+        };
+        let result = connect(&directory, Churn::Never, Some(wait_for_ready)).await;
+
+        match result {
+            Err(Error::FailedToConnectToEndpoint(_)) => {}
+            other => panic!("Expected FailedToConnectToEndpoint error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_replaced_after_their_streams() {
+        let directory = TempDir::new().unwrap();
+        test_server::serve_unix(
+            UnixListener::bind(directory.path().join("ext_proc.sock")).unwrap(),
+        );
+
+        let connections = connect(
+            &directory,
+            Churn::EveryStreams(NonZeroU64::new(2).unwrap()),
+            None,
+        )
+        .await
+        .unwrap();
+        let mut acquired = Vec::new();
+        for _ in 0..5 {
+            let channel = connections.acquire().await.unwrap();
+            acquired.push((channel.connection, channel.new_connection));
+        }
+
+        let new_connections: Vec<_> = acquired.iter().map(|(_, new)| *new).collect();
+        assert_eq!(new_connections, [false, false, true, false, true]);
+        let ids: Vec<_> = acquired.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[2], ids[3]);
+        assert!(ids[0] != ids[2] && ids[2] != ids[4] && ids[0] != ids[4]);
+
+        let connections = connect(&directory, Churn::Never, None).await.unwrap();
+        let first = connections.acquire().await.unwrap().connection;
+        for _ in 0..3 {
+            let channel = connections.acquire().await.unwrap();
+            assert_eq!((channel.connection, channel.new_connection), (first, false));
+        }
+    }
+}
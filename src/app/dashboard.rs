@@ -0,0 +1,414 @@
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal as _},
+    mem, process,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, TryRecvError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use cpu_time::ProcessTime;
+use hdrhistogram::Histogram;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    text::Line,
+    widgets::{Block, Gauge, Paragraph, Sparkline},
+};
+use tonic::Code;
+
+use crate::app::{
+    error::{Error, Result},
+    scheduler::{Progress, ProgressReporter},
+    summary::{self, StepSummary},
+};
+
+/// How often the dashboard is drawn.
+const FRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The number of frames kept in the charts, one minute.
+const HISTORY: usize = 240;
+
+/// The latency percentile charted.
+const PERCENTILE: f64 = 99.0;
+
+/// A full-screen view of the run, drawn in place of the progress bars with
+/// `--dashboard`: the achieved rate against the target, the rolling latency,
+/// the errors, the streams in flight and the CPU used by the load tester.
+///
+/// It is drawn on its own thread, from what the loops report to its `DashboardFeed`.
+#[derive(Debug)]
+pub(crate) struct Dashboard {
+    feed: DashboardFeed,
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+/// Receives the progress of the run for the dashboard.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DashboardFeed(Arc<Mutex<Shared>>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    totals: Totals,
+    /// Streams started since the previous frame.
+    sent: u64,
+    /// Latency of the streams that ended since the previous frame.
+    latency: Option<Histogram<u64>>,
+}
+
+/// What is shown as is, rather than charted.
+#[derive(Debug, Clone, Default)]
+struct Totals {
+    target_rate: u64,
+    step_started: Option<Instant>,
+    in_flight: i64,
+    /// Streams that ended during the run, by outcome.
+    completed: u64,
+    closed_early: u64,
+    /// The failed streams, by gRPC code of their error, whatever the code.
+    failures: HashMap<Code, u64>,
+    /// The steps that ended.
+    steps: Vec<StepSummary>,
+}
+
+/// The charted values, one per frame, oldest first.
+#[derive(Debug, Default)]
+struct History {
+    /// Achieved rate, in streams per second.
+    rate: Vec<u64>,
+    /// Latency at `PERCENTILE`, in microseconds.
+    latency_us: Vec<u64>,
+}
+
+/// Everything drawn in a frame.
+#[derive(Debug)]
+struct View<'a> {
+    totals: &'a Totals,
+    history: &'a History,
+    test_duration: Duration,
+    /// CPU time of the load tester over the last frame, as a percentage of a core.
+    cpu: Option<u64>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal, or returns `None` if stdout is not a terminal, in
+    /// which case the progress bars are shown instead.
+    pub(crate) fn start(test_duration: Duration) -> Result<Option<Self>> {
+        if !io::stdout().is_terminal() {
+            return Ok(None);
+        }
+
+        let terminal = ratatui::try_init().map_err(Error::FailedToDrawDashboard)?;
+        let feed = DashboardFeed::default();
+        let (stop, stopped) = mpsc::channel();
+        let shared = feed.0.clone();
+        let thread = thread::Builder::new()
+            .name("dashboard".to_owned())
+            .spawn(move || draw_until_stopped(terminal, &shared, &stopped, test_duration))
+            .map_err(Error::FailedToDrawDashboard)?;
+
+        Ok(Some(Self { feed, stop, thread }))
+    }
+
+    pub(crate) fn feed(&self) -> DashboardFeed {
+        self.feed.clone()
+    }
+
+    /// Stops drawing and gives the terminal back.
+    pub(crate) fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        let drawn = self
+            .thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the dashboard panicked")));
+        ratatui::try_restore()
+            .and(drawn)
+            .map_err(Error::FailedToDrawDashboard)
+    }
+}
+
+impl ProgressReporter for DashboardFeed {
+    fn report(&self, progress: &Progress) {
+        let mut shared = self.0.lock().expect("dashboard lock is not poisoned");
+        shared.sent += progress.sent;
+        let totals = &mut shared.totals;
+        totals.in_flight += progress.in_flight;
+        totals.completed += progress.completed;
+        totals.closed_early += progress.closed_early;
+        for (code, count) in &progress.failures {
+            *totals.failures.entry(*code).or_default() += count;
+        }
+        if let Some(latency) = &progress.latency_histogram {
+            match &mut shared.latency {
+                Some(merged) => merged
+                    .add(latency)
+                    .expect("histograms grow to fit the merged durations"),
+                None => shared.latency = Some(latency.clone()),
+            }
+        }
+    }
+
+    fn reports_percentiles(&self) -> bool {
+        true
+    }
+
+    fn start_step(&self, target_rate: u64) {
+        let mut shared = self.0.lock().expect("dashboard lock is not poisoned");
+        shared.totals.target_rate = target_rate;
+        shared.totals.step_started = Some(Instant::now());
+    }
+
+    fn finish_step(&self, summary: &StepSummary) {
+        let mut shared = self.0.lock().expect("dashboard lock is not poisoned");
+        shared.totals.steps.push(summary.clone());
+    }
+}
+
+impl History {
+    fn push(&mut self, sent: u64, elapsed: Duration, latency: Option<&Histogram<u64>>) {
+        let elapsed_ms = u64::try_from(elapsed.as_millis())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        self.rate.push(sent * 1_000 / elapsed_ms);
+        self.latency_us
+            .push(latency.map_or(0, |latency| latency.value_at_percentile(PERCENTILE) / 1_000));
+        for values in [&mut self.rate, &mut self.latency_us] {
+            if values.len() > HISTORY {
+                drop(values.drain(..values.len() - HISTORY));
+            }
+        }
+    }
+}
+
+/// Draws a frame every `FRAME_INTERVAL` until the dashboard is stopped.
+fn draw_until_stopped(
+    mut terminal: DefaultTerminal,
+    shared: &Mutex<Shared>,
+    stopped: &mpsc::Receiver<()>,
+    test_duration: Duration,
+) -> io::Result<()> {
+    let mut history = History::default();
+    let mut last_frame = Instant::now();
+    let mut cpu_time = ProcessTime::try_now().ok();
+    loop {
+        // NOTE: The terminal is in raw mode, where Ctrl-C is a key rather than a signal.
+        if event::poll(FRAME_INTERVAL.saturating_sub(last_frame.elapsed()))?
+            && let Event::Key(key) = event::read()?
+            && is_interrupt(key)
+        {
+            ratatui::restore();
+            process::exit(130);
+        }
+        if !matches!(stopped.try_recv(), Err(TryRecvError::Empty)) {
+            return Ok(());
+        }
+        if last_frame.elapsed() < FRAME_INTERVAL {
+            continue;
+        }
+
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        let (totals, sent, latency) = {
+            let mut shared = shared.lock().expect("dashboard lock is not poisoned");
+            (
+                shared.totals.clone(),
+                mem::take(&mut shared.sent),
+                shared.latency.take(),
+            )
+        };
+        history.push(sent, elapsed, latency.as_ref());
+        let cpu = cpu_time.and_then(|start| {
+            let used = start.try_elapsed().ok()?;
+            cpu_time = ProcessTime::try_now().ok();
+            u64::try_from(used.as_micros() * 100 / elapsed.as_micros().max(1)).ok()
+        });
+
+        let view = View {
+            totals: &totals,
+            history: &history,
+            test_duration,
+            cpu,
+        };
+        let _ = terminal.draw(|frame| draw(frame, &view))?;
+    }
+}
+
+fn is_interrupt(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('q')
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+fn draw(frame: &mut Frame<'_>, view: &View<'_>) {
+    let totals = view.totals;
+    let [header, rate, charts, counters, steps] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(3),
+        Constraint::Length(8),
+        Constraint::Length(6),
+        Constraint::Min(3),
+    ])
+    .areas(frame.area());
+
+    let elapsed = totals
+        .step_started
+        .map_or(0, |started| started.elapsed().as_secs());
+    frame.render_widget(
+        Line::from(format!(
+            "{}: step at {} req/s, {elapsed}s of {}s. Press q to quit.",
+            env!("CARGO_PKG_NAME"),
+            totals.target_rate,
+            view.test_duration.as_secs(),
+        )),
+        header,
+    );
+
+    let achieved = view.history.rate.last().copied().unwrap_or_default();
+    let percent = (achieved * 100)
+        .checked_div(totals.target_rate)
+        .unwrap_or_default()
+        .min(100);
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title("Achieved rate"))
+            .percent(u16::try_from(percent).unwrap_or(100))
+            .label(format!("{achieved} / {} req/s", totals.target_rate)),
+        rate,
+    );
+
+    let [rate_chart, latency_chart] =
+        Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(charts);
+    let latency = Duration::from_micros(view.history.latency_us.last().copied().unwrap_or(0));
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title("Achieved rate (req/s)"))
+            .data(latest(&view.history.rate, rate_chart))
+            .max(totals.target_rate.max(achieved)),
+        rate_chart,
+    );
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(format!("p{PERCENTILE} latency: {latency:?}")))
+            .data(latest(&view.history.latency_us, latency_chart)),
+        latency_chart,
+    );
+
+    let [errors, streams] =
+        Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(counters);
+    let mut failures = totals.failures.iter().collect::<Vec<_>>();
+    failures.sort_unstable_by_key(|(_, count)| std::cmp::Reverse(**count));
+    let error_lines = [format!("closed early: {}", totals.closed_early)]
+        .into_iter()
+        .chain(
+            failures
+                .iter()
+                .map(|(code, count)| format!("{code:?}: {count}")),
+        )
+        .map(Line::from)
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(error_lines).block(Block::bordered().title("Errors")),
+        errors,
+    );
+    let cpu = view
+        .cpu
+        .map_or_else(|| "unknown".to_owned(), |cpu| format!("{cpu}% of a core"));
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!("in flight: {}", totals.in_flight)),
+            Line::from(format!("completed: {}", totals.completed)),
+            Line::from(format!("tester CPU: {cpu}")),
+        ])
+        .block(Block::bordered().title("Streams")),
+        streams,
+    );
+
+    let table = if totals.steps.is_empty() {
+        String::new()
+    } else {
+        summary::table(&totals.steps)
+    };
+    frame.render_widget(
+        Paragraph::new(table).block(Block::bordered().title("Steps (latencies in ms)")),
+        steps,
+    );
+}
+
+/// The latest values that fit in a chart.
+fn latest(values: &[u64], area: Rect) -> &[u64] {
+    let width = usize::from(area.width.saturating_sub(2));
+    &values[values.len().saturating_sub(width)..]
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{Terminal, backend::TestBackend};
+
+    use super::*;
+
+    #[test]
+    fn test_dashboard_draws_the_reported_progress() {
+        let feed = DashboardFeed::default();
+        feed.start_step(200);
+        let mut latency = Histogram::new(2).unwrap();
+        latency.record(3_000_000).unwrap();
+        feed.report(&Progress {
+            sent: 50,
+            completed: 40,
+            closed_early: 2,
+            failed: 3,
+            failures: HashMap::from([(Code::Unavailable, 3)]),
+            in_flight: 5,
+            latency_histogram: Some(latency),
+            ..Progress::default()
+        });
+        feed.report(&Progress {
+            failed: 6,
+            failures: HashMap::from([(Code::Internal, 5), (Code::Unavailable, 1)]),
+            ..Progress::default()
+        });
+
+        let mut shared = feed.0.lock().unwrap();
+        let mut history = History::default();
+        history.push(
+            mem::take(&mut shared.sent),
+            Duration::from_millis(250),
+            shared.latency.take().as_ref(),
+        );
+        assert_eq!(history.rate, [200]);
+        // NOTE: The histogram keeps 2 significant digits.
+        assert_eq!(history.latency_us, [3_014]);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        let view = View {
+            totals: &shared.totals,
+            history: &history,
+            test_duration: Duration::from_secs(10),
+            cpu: Some(42),
+        };
+        let _ = terminal.draw(|frame| draw(frame, &view)).unwrap();
+        let screen = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(ratatui::buffer::Cell::symbol)
+            .collect::<String>();
+        for expected in [
+            "step at 200 req/s",
+            "200 / 200 req/s",
+            "p99 latency: 3.014ms",
+            "closed early: 2",
+            "Internal: 5",
+            "Unavailable: 4",
+            "in flight: 5",
+            "tester CPU: 42% of a core",
+        ] {
+            assert!(screen.contains(expected), "{expected} is not drawn");
+        }
+    }
+}
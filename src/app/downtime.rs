@@ -0,0 +1,242 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::app::report;
+
+/// Tracks the windows during which each `ext_proc` endpoint was unavailable, from
+/// the outcome of the streams sent to it.
+///
+/// A window opens when a stream fails, and closes when a stream started after it
+/// succeeds on the same endpoint. Streams started before the window opened may
+/// still succeed while the server is down, so they do not close it.
+///
+/// Successes only take the lock while a window is open on their endpoint, so that
+/// the loops of every thread do not contend on it while the servers are up.
+#[derive(Debug, Clone)]
+pub(crate) struct DowntimeTracker {
+    /// Whether each endpoint has an open window, only changed with the lock held.
+    open: Arc<[AtomicBool]>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The open window of each endpoint, indexed like the endpoints.
+    current: Vec<Option<OpenWindow>>,
+    closed: Vec<ClosedWindow>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenWindow {
+    start: Instant,
+    failed_streams: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClosedWindow {
+    endpoint: usize,
+    start: Instant,
+    end: Instant,
+    failed_streams: u64,
+}
+
+impl OpenWindow {
+    fn close(self, endpoint: usize) -> ClosedWindow {
+        ClosedWindow {
+            endpoint,
+            start: self.start,
+            end: Instant::now(),
+            failed_streams: self.failed_streams,
+        }
+    }
+}
+
+/// A window during which an endpoint was unavailable, relative to the start of the step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Downtime {
+    /// Index of the endpoint, in the order of the URIs.
+    pub(crate) endpoint: usize,
+    #[serde(rename = "start_ms", serialize_with = "report::as_millis")]
+    pub(crate) start: Duration,
+    #[serde(rename = "duration_ms", serialize_with = "report::as_millis")]
+    pub(crate) duration: Duration,
+    pub(crate) failed_streams: u64,
+}
+
+impl DowntimeTracker {
+    /// Creates a tracker of the given number of endpoints, indexed like the URIs.
+    pub(crate) fn new(endpoints: usize) -> Self {
+        Self {
+            open: (0..endpoints).map(|_| AtomicBool::new(false)).collect(),
+            state: Arc::new(Mutex::new(State {
+                current: vec![None; endpoints],
+                closed: vec![],
+            })),
+        }
+    }
+
+    /// Records a stream to `endpoint`, started at `started_at`, that failed.
+    pub(crate) fn record_failure(&self, endpoint: usize, started_at: Instant) {
+        let mut state = self.state.lock().expect("downtime tracker is not poisoned");
+        let window = state.current[endpoint].get_or_insert(OpenWindow {
+            start: started_at,
+            failed_streams: 0,
+        });
+        window.start = window.start.min(started_at);
+        window.failed_streams += 1;
+        self.open[endpoint].store(true, Ordering::Release);
+    }
+
+    /// Records a stream to `endpoint`, started at `started_at`, that succeeded.
+    pub(crate) fn record_success(&self, endpoint: usize, started_at: Instant) {
+        // NOTE: A window opened concurrently is closed by a later success instead.
+        if !self.open[endpoint].load(Ordering::Acquire) {
+            return;
+        }
+        let mut state = self.state.lock().expect("downtime tracker is not poisoned");
+        let current = &mut state.current[endpoint];
+        if let Some(window) = *current
+            && started_at >= window.start
+        {
+            *current = None;
+            self.open[endpoint].store(false, Ordering::Release);
+            state.closed.push(window.close(endpoint));
+        }
+    }
+
+    /// Returns the windows since the last call, relative to `since`, in the order
+    /// they opened. A window still open is closed at the time of the call.
+    pub(crate) fn take(&self, since: Instant) -> Vec<Downtime> {
+        let mut state = self.state.lock().expect("downtime tracker is not poisoned");
+        for open in self.open.iter() {
+            open.store(false, Ordering::Release);
+        }
+        let open = state
+            .current
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(endpoint, window)| Some(window.take()?.close(endpoint)))
+            .collect::<Vec<_>>();
+        state.closed.extend(open);
+
+        let mut downtimes = state
+            .closed
+            .drain(..)
+            .map(|window| Downtime {
+                endpoint: window.endpoint,
+                start: window.start.saturating_duration_since(since),
+                duration: window.end - window.start,
+                failed_streams: window.failed_streams,
+            })
+            .collect::<Vec<_>>();
+        downtimes.sort_by_key(|downtime| (downtime.start, downtime.endpoint));
+        downtimes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_downtime_window_closes_on_later_success() {
+        let tracker = DowntimeTracker::new(1);
+        let since = Instant::now();
+
+        let before = Instant::now();
+        tokio::time::advance(Duration::from_secs(1)).await;
+        tracker.record_failure(0, Instant::now());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        tracker.record_failure(0, Instant::now());
+        // NOTE: Started before the window opened, so it does not close it.
+        tracker.record_success(0, before);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        tracker.record_success(0, Instant::now());
+
+        assert_eq!(
+            tracker.take(since),
+            vec![Downtime {
+                endpoint: 0,
+                start: Duration::from_secs(1),
+                duration: Duration::from_secs(2),
+                failed_streams: 2,
+            }]
+        );
+        assert!(tracker.take(since).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_downtime_window_is_closed_when_taken() {
+        let tracker = DowntimeTracker::new(1);
+        let since = Instant::now();
+
+        tracker.record_failure(0, Instant::now());
+        tokio::time::advance(Duration::from_secs(3)).await;
+
+        assert_eq!(
+            tracker.take(since),
+            vec![Downtime {
+                endpoint: 0,
+                start: Duration::ZERO,
+                duration: Duration::from_secs(3),
+                failed_streams: 1,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_on_another_endpoint_keeps_the_window_open() {
+        let tracker = DowntimeTracker::new(2);
+        let since = Instant::now();
+
+        tracker.record_failure(1, Instant::now());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        tracker.record_success(0, Instant::now());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        tracker.record_success(1, Instant::now());
+
+        assert_eq!(
+            tracker.take(since),
+            vec![Downtime {
+                endpoint: 1,
+                start: Duration::ZERO,
+                duration: Duration::from_secs(2),
+                failed_streams: 1,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_downtime_window_reopens_after_closing() {
+        let tracker = DowntimeTracker::new(1);
+        let since = Instant::now();
+
+        tracker.record_success(0, Instant::now());
+        for _ in 0..2 {
+            tracker.record_failure(0, Instant::now());
+            tokio::time::advance(Duration::from_secs(1)).await;
+            tracker.record_success(0, Instant::now());
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+
+        let downtimes = tracker.take(since);
+        assert_eq!(
+            downtimes
+                .iter()
+                .map(|downtime| (downtime.start, downtime.duration))
+                .collect::<Vec<_>>(),
+            [
+                (Duration::ZERO, Duration::from_secs(1)),
+                (Duration::from_secs(2), Duration::from_secs(1)),
+            ]
+        );
+    }
+}
@@ -30,9 +30,68 @@ pub(crate) enum Error {
     ConcurrencyMustBeGreaterThanZero,
     #[error("concurrency must be less than u32::MAX: {0}")]
     ConcurrencyMustBeLessThanU32Max(TryFromIntError),
+    #[error("failed to build Tokio runtime: {0}")]
+    FailedToBuildRuntime(std::io::Error),
+    #[error("failed to spawn shard thread: {0}")]
+    FailedToSpawnShard(std::io::Error),
+    #[error("a shard thread stopped unexpectedly")]
+    ShardStopped,
+    #[error("invalid TLS configuration: {0}")]
+    InvalidTlsConfiguration(String),
+    #[error("invalid gRPC metadata: {0}")]
+    InvalidGrpcMetadata(String),
+    #[error("the server was not serving after {0:?}")]
+    ServerNotServing(std::time::Duration),
+    #[error("failed to read stream records: {0}")]
+    FailedToReadRecords(std::io::Error),
+    #[error("failed to serve metrics: {0}")]
+    FailedToServeMetrics(std::io::Error),
+    #[error("failed to export {0} OTLP requests, the last one with: {1}")]
+    FailedToExportTelemetry(u64, Box<tonic::Status>),
+    #[error("failed to read the run: {0}")]
+    FailedToReadRun(std::io::Error),
+    #[error("the latency regressed beyond the tolerance {0} times")]
+    LatencyRegressed(usize),
+    #[error("SLOs were breached {0} times, see the steps of manifest.json")]
+    SloBreached(usize),
+    #[error("failed to draw the dashboard: {0}")]
+    FailedToDrawDashboard(std::io::Error),
+    #[error(
+        "thread-per-core mode needs at least one connection per thread, got {0} connections for {1} threads"
+    )]
+    FewerConnectionsThanThreads(usize, usize),
 }
 
 impl Error {
+    /// The gRPC code of an error of a stream.
+    pub(crate) fn code(&self) -> tonic::Code {
+        match self {
+            Error::FailedToCallExtProc(status) => status.code(),
+            Error::FailedToConnectToEndpoint(_) => tonic::Code::Unavailable,
+            _ => tonic::Code::Unknown,
+        }
+    }
+
+    /// Whether the error ended a single stream, either because the connection could
+    /// not be opened or because the call ended with an error status, rather than
+    /// being an error of the load tester.
+    pub(crate) fn is_stream_failure(&self) -> bool {
+        matches!(
+            self,
+            Error::FailedToConnectToEndpoint(_) | Error::FailedToCallExtProc(_)
+        )
+    }
+
+    /// Whether a stream failed because the server is unavailable, e.g. while it
+    /// restarts, rather than because of the stream itself.
+    pub(crate) fn is_unavailable(&self) -> bool {
+        match self {
+            Error::FailedToConnectToEndpoint(_) => true,
+            Error::FailedToCallExtProc(status) => status.code() == tonic::Code::Unavailable,
+            _ => false,
+        }
+    }
+
     pub(crate) fn exit_code(&self) -> i32 {
         match *self {
             Error::FailedToCreateEndpoint(_) => 1,
@@ -45,6 +104,20 @@ impl Error {
             Error::TooManyThroughputsToTest => 8,
             Error::ConcurrencyMustBeGreaterThanZero => 9,
             Error::ConcurrencyMustBeLessThanU32Max(_) => 10,
+            Error::FailedToBuildRuntime(_) => 11,
+            Error::FailedToSpawnShard(_) => 12,
+            Error::ShardStopped => 13,
+            Error::InvalidTlsConfiguration(_) => 14,
+            Error::InvalidGrpcMetadata(_) => 15,
+            Error::ServerNotServing(_) => 16,
+            Error::FailedToReadRecords(_) => 17,
+            Error::FailedToServeMetrics(_) => 18,
+            Error::FailedToExportTelemetry(_, _) => 19,
+            Error::FailedToReadRun(_) => 20,
+            Error::LatencyRegressed(_) => 21,
+            Error::SloBreached(_) => 22,
+            Error::FailedToDrawDashboard(_) => 23,
+            Error::FewerConnectionsThanThreads(_, _) => 24,
         }
     }
 }
@@ -0,0 +1,431 @@
+use std::{
+    collections::BTreeMap,
+    io, mem,
+    path::Path,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::{self as tokio_time, Instant, MissedTickBehavior},
+};
+
+use crate::app::{
+    error::Result,
+    manifest::{RunOutcome, Step},
+    report::Metadata,
+    scheduler::{Progress, ProgressReporter, REPORT_INTERVAL},
+};
+
+/// Version of the schema of the events, incremented on incompatible changes.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// How often the `stats` events are written during a step.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes the events of a run to stdout with `--events ndjson`, one JSON object
+/// per line, in place of the progress bars and the table of the steps.
+///
+/// The `stats` events are written every `STATS_INTERVAL` from what the loops
+/// report to its `EventFeed`.
+#[derive(Debug)]
+pub(crate) struct Events {
+    feed: EventFeed,
+    stats: JoinHandle<()>,
+}
+
+/// Receives the progress of the steps, written as `stats` events, and the steps
+/// that finished.
+#[derive(Debug, Clone)]
+pub(crate) struct EventFeed {
+    window: Arc<Mutex<Window>>,
+    lines: Lines,
+}
+
+/// Writes the events as lines, e.g. to stdout, on their own thread, so that a slow
+/// reader never blocks the runtime.
+///
+/// The lines wait in memory for the reader, which is fine at a few events per second.
+#[derive(Debug, Clone)]
+struct Lines(mpsc::Sender<Output>);
+
+#[derive(Debug)]
+enum Output {
+    Line(String),
+    /// Acknowledged once the lines sent before are written.
+    Flush(oneshot::Sender<()>),
+}
+
+/// What the loops reported since the previous `stats` event.
+#[derive(Debug, Default)]
+struct Window {
+    target_throughput: u64,
+    /// `None` between steps.
+    step_started: Option<Instant>,
+    /// When the previous `stats` event was written, or the step started.
+    since: Option<Instant>,
+    /// Streams in flight, over the whole run.
+    in_flight: i64,
+    sent: u64,
+    completed: u64,
+    closed_early: u64,
+    failed: u64,
+    failures: BTreeMap<String, u64>,
+    latency: Option<Histogram<u64>>,
+}
+
+/// An event, written with the time it happened.
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    #[serde(with = "time::serde::rfc3339")]
+    time: OffsetDateTime,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    RunStarted {
+        schema_version: u32,
+        run_id: &'a str,
+        result_directory: &'a Path,
+        /// The settings of the run, as in `metadata.json`.
+        config: &'a Metadata<'a>,
+    },
+    StepStarted {
+        target_throughput: u64,
+    },
+    Stats(Stats),
+    /// The step, as in `manifest.json`.
+    StepFinished(&'a Step),
+    Error {
+        message: String,
+        exit_code: i32,
+    },
+    RunFinished {
+        outcome: RunOutcome,
+        exit_code: i32,
+    },
+}
+
+/// The progress of a step since the previous `stats` event.
+#[derive(Debug, PartialEq, Serialize)]
+struct Stats {
+    target_throughput: u64,
+    /// Since the start of the step.
+    elapsed_ms: u64,
+    /// Since the previous `stats` event.
+    window_ms: u64,
+    sent: u64,
+    completed: u64,
+    closed_early: u64,
+    failed: u64,
+    /// The failed streams, by name of the gRPC code of their error.
+    failures: BTreeMap<String, u64>,
+    /// `None` if the window is shorter than the report interval of the loops, e.g.
+    /// at the end of a step, as the streams are reported in batches.
+    achieved_throughput: Option<u64>,
+    in_flight: i64,
+    /// `None` if no stream ended without failing.
+    p50_ns: Option<u64>,
+    p99_ns: Option<u64>,
+}
+
+impl Events {
+    /// Writes the `run_started` event, and starts writing the `stats` events.
+    pub(crate) fn start(run_id: &str, result_directory: &Path, config: &Metadata<'_>) -> Self {
+        let lines = Lines::spawn(io::stdout());
+        lines.write(&Event::RunStarted {
+            schema_version: SCHEMA_VERSION,
+            run_id,
+            result_directory,
+            config,
+        });
+
+        let feed = EventFeed {
+            window: Arc::default(),
+            lines,
+        };
+        let window = feed.clone();
+        let stats = tokio::spawn(async move {
+            let mut ticks =
+                tokio_time::interval_at(Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                let _ = ticks.tick().await;
+                window.write_stats();
+            }
+        });
+
+        Self { feed, stats }
+    }
+
+    pub(crate) fn feed(&self) -> EventFeed {
+        self.feed.clone()
+    }
+
+    /// Stops writing the `stats` events, writes the `error` event if the run
+    /// failed and the `run_finished` event, and waits until they are written.
+    pub(crate) async fn finish(self, outcome: RunOutcome, result: &Result<()>) {
+        self.stats.abort();
+        let lines = &self.feed.lines;
+        let exit_code = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                lines.write(&Event::Error {
+                    message: e.to_string(),
+                    exit_code: e.exit_code(),
+                });
+                e.exit_code()
+            }
+        };
+        lines.write(&Event::RunFinished { outcome, exit_code });
+        lines.flush().await;
+    }
+}
+
+impl EventFeed {
+    /// Writes the streams reported since the last `stats` event, then the
+    /// `step_finished` event.
+    pub(crate) fn step_finished(&self, step: &Step) {
+        self.write_stats();
+        self.window
+            .lock()
+            .expect("events lock is not poisoned")
+            .step_started = None;
+        self.lines.write(&Event::StepFinished(step));
+    }
+
+    fn write_stats(&self) {
+        let stats = self
+            .window
+            .lock()
+            .expect("events lock is not poisoned")
+            .take(Instant::now());
+        if let Some(stats) = stats {
+            self.lines.write(&Event::Stats(stats));
+        }
+    }
+}
+
+impl ProgressReporter for EventFeed {
+    fn report(&self, progress: &Progress) {
+        self.window
+            .lock()
+            .expect("events lock is not poisoned")
+            .record(progress);
+    }
+
+    fn reports_percentiles(&self) -> bool {
+        true
+    }
+
+    fn start_step(&self, target_rate: u64) {
+        {
+            let mut window = self.window.lock().expect("events lock is not poisoned");
+            let now = Instant::now();
+            window.target_throughput = target_rate;
+            window.step_started = Some(now);
+            window.since = Some(now);
+        }
+        self.lines.write(&Event::StepStarted {
+            target_throughput: target_rate,
+        });
+    }
+}
+
+impl Window {
+    fn record(&mut self, progress: &Progress) {
+        self.in_flight += progress.in_flight;
+        self.sent += progress.sent;
+        self.completed += progress.completed;
+        self.closed_early += progress.closed_early;
+        self.failed += progress.failed;
+        for (code, count) in &progress.failures {
+            *self.failures.entry(format!("{code:?}")).or_default() += count;
+        }
+        if let Some(latency) = &progress.latency_histogram {
+            match &mut self.latency {
+                Some(merged) => merged
+                    .add(latency)
+                    .expect("histograms grow to fit the merged durations"),
+                None => self.latency = Some(latency.clone()),
+            }
+        }
+    }
+
+    /// The stats since the previous call, unless no step is running.
+    fn take(&mut self, now: Instant) -> Option<Stats> {
+        let step_started = self.step_started?;
+        let since = self.since.replace(now)?;
+        let millis = |duration: Duration| u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let window_ms = millis(now - since);
+        let sent = mem::take(&mut self.sent);
+        let latency = self.latency.take();
+        let percentile = |percentile| {
+            latency
+                .as_ref()
+                .map(|latency| latency.value_at_percentile(percentile))
+        };
+
+        Some(Stats {
+            target_throughput: self.target_throughput,
+            elapsed_ms: millis(now - step_started),
+            window_ms,
+            sent,
+            completed: mem::take(&mut self.completed),
+            closed_early: mem::take(&mut self.closed_early),
+            failed: mem::take(&mut self.failed),
+            failures: mem::take(&mut self.failures),
+            achieved_throughput: (now - since >= REPORT_INTERVAL).then(|| sent * 1_000 / window_ms),
+            in_flight: self.in_flight,
+            p50_ns: percentile(50.0),
+            p99_ns: percentile(99.0),
+        })
+    }
+}
+
+impl Lines {
+    fn spawn(mut output: impl io::Write + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let _handle = thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    // NOTE: A reader that goes away must not interrupt the run.
+                    Output::Line(line) => drop(writeln!(output, "{line}")),
+                    Output::Flush(done) => {
+                        drop(output.flush());
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self(sender)
+    }
+
+    /// Writes an event, as a single line.
+    fn write(&self, event: &Event<'_>) {
+        let line = Line {
+            time: OffsetDateTime::now_utc(),
+            event,
+        };
+        let json = serde_json::to_string(&line).expect("events are serializable");
+        drop(self.0.send(Output::Line(json)));
+    }
+
+    /// Waits until the events written so far are written out.
+    async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.0.send(Output::Flush(done)).is_ok() {
+            drop(flushed.await);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tonic::Code;
+
+    use super::*;
+
+    /// Lines written to memory, for the tests.
+    #[derive(Debug, Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Written {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lines_are_written_once_flushed() {
+        let written = Written::default();
+        let lines = Lines::spawn(written.clone());
+
+        lines.write(&Event::StepStarted {
+            target_throughput: 100,
+        });
+        lines.write(&Event::RunFinished {
+            outcome: RunOutcome::Failed,
+            exit_code: 3,
+        });
+        lines.flush().await;
+
+        let written = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        let events = written
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|event| event["event"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(events, ["step_started", "run_finished"]);
+    }
+
+    #[test]
+    fn test_stats_cover_the_window() {
+        let mut window = Window::default();
+        assert_eq!(window.take(Instant::now()), None);
+
+        let started = Instant::now();
+        window.target_throughput = 100;
+        window.step_started = Some(started);
+        window.since = Some(started);
+        let mut latency = Histogram::new(2).unwrap();
+        latency.record(1_000_000).unwrap();
+        for _ in 0..2 {
+            window.record(&Progress {
+                sent: 60,
+                completed: 50,
+                failed: 2,
+                failures: HashMap::from([(Code::Unavailable, 2)]),
+                in_flight: 8,
+                latency_histogram: Some(latency.clone()),
+                ..Progress::default()
+            });
+        }
+
+        let stats = window.take(started + Duration::from_secs(2)).unwrap();
+        assert_eq!(stats.elapsed_ms, 2_000);
+        assert_eq!(stats.sent, 120);
+        assert_eq!(stats.achieved_throughput, Some(60));
+        assert_eq!(stats.in_flight, 16);
+        assert_eq!(
+            stats.failures,
+            BTreeMap::from([("Unavailable".to_owned(), 4)])
+        );
+        assert!(latency.equivalent(stats.p99_ns.unwrap(), 1_000_000));
+
+        let line = serde_json::to_value(Line {
+            time: OffsetDateTime::UNIX_EPOCH,
+            event: &Event::Stats(stats),
+        })
+        .unwrap();
+        assert_eq!(line["event"], "stats");
+        assert_eq!(line["time"], "1970-01-01T00:00:00Z");
+        assert_eq!(line["failed"], 4);
+
+        // The next window starts empty, and the streams in flight carry over.
+        let stats = window.take(started + Duration::from_secs(3)).unwrap();
+        assert_eq!(
+            (stats.sent, stats.window_ms, stats.in_flight),
+            (0, 1_000, 16)
+        );
+        assert_eq!(stats.p50_ns, None);
+        let stats = window.take(started + Duration::from_millis(3_010)).unwrap();
+        assert_eq!(stats.achieved_throughput, None);
+    }
+}
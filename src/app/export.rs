@@ -0,0 +1,542 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    marker::PhantomData,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use parquet::{
+    basic::{Compression, ZstdLevel},
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+use crate::app::{
+    phases::Phases,
+    records::{StreamRecord, nanos},
+    sink::RowWriter,
+    summary::{LatencySummary, PERCENTILES, StepSummary},
+};
+
+/// Number of rows buffered before they are written as a Parquet row group.
+const ROW_GROUP_SIZE: usize = 65_536;
+
+/// The formats of the tables written with `--output-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl TableFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnKind {
+    /// A 64-bit integer, signed in Parquet.
+    Int,
+    /// A 64-bit integer, empty in CSV and null in Parquet when missing.
+    OptionalInt,
+    String,
+    /// A string, empty in CSV and null in Parquet when missing.
+    OptionalString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) kind: ColumnKind,
+}
+
+impl Column {
+    fn new(name: impl Into<String>, kind: ColumnKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Value<'a> {
+    Int(u64),
+    OptionalInt(Option<u64>),
+    String(&'a str),
+    OptionalString(Option<&'a str>),
+}
+
+/// A row of a table written with `TableWriter`.
+///
+/// The columns of a table are part of its documented schema: they are only ever
+/// appended, never renamed, removed or reordered.
+pub(crate) trait Row {
+    fn columns() -> Vec<Column>;
+    /// The values of the row, in the order of `columns`.
+    fn values(&self) -> Vec<Value<'_>>;
+}
+
+/// The raw samples of `--raw-samples`.
+impl Row for Duration {
+    fn columns() -> Vec<Column> {
+        vec![Column::new("duration_ns", ColumnKind::Int)]
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![Value::Int(nanos(*self))]
+    }
+}
+
+impl Row for StreamRecord {
+    fn columns() -> Vec<Column> {
+        use ColumnKind::{Int, OptionalInt, OptionalString, String};
+        vec![
+            Column::new("intended_start_ns", Int),
+            Column::new("start_ns", Int),
+            Column::new("end_ns", Int),
+            Column::new("task", Int),
+            Column::new("endpoint", Int),
+            Column::new("connection", Int),
+            Column::new("outcome", String),
+            Column::new("connection_wait_ns", Int),
+            Column::new("tls_handshake_ns", OptionalInt),
+            Column::new("duration_ns", Int),
+            Column::new("setup_ns", OptionalInt),
+            Column::new("request_headers_ns", OptionalInt),
+            Column::new("response_headers_ns", OptionalInt),
+            Column::new("time_to_first_response_ns", OptionalInt),
+            Column::new("error", OptionalString),
+            Column::new("scenario", String),
+        ]
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![
+            Value::Int(self.intended_start_ns),
+            Value::Int(self.start_ns),
+            Value::Int(self.end_ns),
+            Value::Int(to_u64(self.task)),
+            Value::Int(to_u64(self.endpoint)),
+            Value::Int(self.connection),
+            Value::String(self.outcome.name()),
+            Value::Int(self.connection_wait_ns),
+            Value::OptionalInt(self.tls_handshake_ns),
+            Value::Int(self.duration_ns),
+            Value::OptionalInt(self.setup_ns),
+            Value::OptionalInt(self.request_headers_ns),
+            Value::OptionalInt(self.response_headers_ns),
+            Value::OptionalInt(self.time_to_first_response_ns),
+            Value::OptionalString(self.error.as_deref()),
+            Value::String(self.scenario.name()),
+        ]
+    }
+}
+
+impl Row for StepSummary {
+    fn columns() -> Vec<Column> {
+        let mut columns = [
+            "target_throughput",
+            "achieved_throughput",
+            "percent_of_target_throughput",
+            "request_sent",
+            "streams",
+            "failed_streams",
+            "closed_early_streams",
+            "in_flight_peak",
+            "min_ns",
+            "mean_ns",
+            "stddev_ns",
+            "max_ns",
+        ]
+        .map(|name| Column::new(name, ColumnKind::Int))
+        .to_vec();
+        columns.extend(percentile_columns(""));
+        for (phase, ()) in Phases::<()>::default().named() {
+            columns.push(Column::new(format!("{phase}_streams"), ColumnKind::Int));
+            columns.push(Column::new(format!("{phase}_mean_ns"), ColumnKind::Int));
+            columns.push(Column::new(format!("{phase}_max_ns"), ColumnKind::Int));
+            columns.extend(percentile_columns(&format!("{phase}_")));
+        }
+        columns
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        let mut values = vec![
+            Value::Int(self.target_throughput),
+            Value::Int(self.achieved_throughput),
+            Value::Int(self.percent_of_target_throughput),
+            Value::Int(self.request_sent),
+            Value::Int(self.streams),
+            Value::Int(self.failed_streams),
+            Value::Int(self.closed_early_streams),
+            Value::Int(to_u64(self.in_flight_peak)),
+            Value::Int(nanos(self.min)),
+            Value::Int(nanos(self.mean)),
+            Value::Int(nanos(self.stddev)),
+            Value::Int(nanos(self.max)),
+        ];
+        values.extend(self.percentiles.map(|latency| Value::Int(nanos(latency))));
+        for (_, phase) in self.phases.named() {
+            let LatencySummary {
+                streams,
+                mean,
+                max,
+                percentiles,
+            } = phase;
+            values.extend([
+                Value::Int(*streams),
+                Value::Int(nanos(*mean)),
+                Value::Int(nanos(*max)),
+            ]);
+            values.extend(percentiles.map(|latency| Value::Int(nanos(latency))));
+        }
+        values
+    }
+}
+
+/// The columns of the percentiles, e.g. `p99_9_ns` for p99.9.
+fn percentile_columns(prefix: &str) -> impl Iterator<Item = Column> {
+    PERCENTILES.iter().map(move |percentile| {
+        let name = format!("{prefix}p{percentile}_ns").replace('.', "_");
+        Column::new(name, ColumnKind::Int)
+    })
+}
+
+fn to_u64(value: usize) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+/// Writes the rows of a table one at a time: CSV rows are written as they come,
+/// Parquet rows are buffered until a row group is full.
+pub(crate) struct TableWriter<R> {
+    format: FormatWriter,
+    row: PhantomData<fn(&R)>,
+}
+
+enum FormatWriter {
+    Csv(Box<csv::Writer<File>>),
+    Parquet(Box<ParquetWriter>),
+}
+
+impl<R: Row> TableWriter<R> {
+    /// Creates the table at `path`, and writes its header if it has one.
+    pub(crate) fn create(path: &Path, format: TableFormat) -> io::Result<Self> {
+        let columns = R::columns();
+        let file = File::create(path)?;
+        let format = match format {
+            TableFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(columns.iter().map(|column| &column.name))?;
+                FormatWriter::Csv(Box::new(writer))
+            }
+            TableFormat::Parquet => FormatWriter::Parquet(Box::new(
+                ParquetWriter::new(file, &columns).map_err(io::Error::other)?,
+            )),
+        };
+
+        Ok(Self {
+            format,
+            row: PhantomData,
+        })
+    }
+}
+
+impl<R: Row> RowWriter<R> for TableWriter<R> {
+    fn write(&mut self, row: &R) -> io::Result<()> {
+        match &mut self.format {
+            FormatWriter::Csv(writer) => {
+                writer.write_record(row.values().into_iter().map(Value::to_field))?;
+                Ok(())
+            }
+            FormatWriter::Parquet(writer) => writer.push(&row.values()).map_err(io::Error::other),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self.format {
+            FormatWriter::Csv(mut writer) => writer.flush(),
+            FormatWriter::Parquet(writer) => writer.finish().map_err(io::Error::other),
+        }
+    }
+}
+
+impl Value<'_> {
+    fn to_field(self) -> String {
+        match self {
+            Value::Int(value) | Value::OptionalInt(Some(value)) => value.to_string(),
+            Value::OptionalInt(None) | Value::OptionalString(None) => String::new(),
+            Value::String(value) | Value::OptionalString(Some(value)) => value.to_owned(),
+        }
+    }
+}
+
+struct ParquetWriter {
+    writer: SerializedFileWriter<BufWriter<File>>,
+    buffers: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+/// The values of a column, buffered until the next row group.
+enum ColumnBuffer {
+    Int(Vec<i64>),
+    /// The values that are set, and whether each row has a value (1) or not (0).
+    OptionalInt(Vec<i64>, Vec<i16>),
+    String(Vec<ByteArray>),
+    /// The values that are set, and whether each row has a value (1) or not (0).
+    OptionalString(Vec<ByteArray>, Vec<i16>),
+}
+
+impl ParquetWriter {
+    fn new(file: File, columns: &[Column]) -> parquet::errors::Result<Self> {
+        let fields = columns
+            .iter()
+            .map(|column| match column.kind {
+                ColumnKind::Int => format!("REQUIRED INT64 {};", column.name),
+                ColumnKind::OptionalInt => format!("OPTIONAL INT64 {};", column.name),
+                ColumnKind::String => format!("REQUIRED BYTE_ARRAY {} (STRING);", column.name),
+                ColumnKind::OptionalString => {
+                    format!("OPTIONAL BYTE_ARRAY {} (STRING);", column.name)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let schema = parse_message_type(&format!("message schema {{ {fields} }}"))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+
+        Ok(Self {
+            writer: SerializedFileWriter::new(
+                BufWriter::new(file),
+                Arc::new(schema),
+                Arc::new(properties),
+            )?,
+            buffers: columns
+                .iter()
+                .map(|column| match column.kind {
+                    ColumnKind::Int => ColumnBuffer::Int(vec![]),
+                    ColumnKind::OptionalInt => ColumnBuffer::OptionalInt(vec![], vec![]),
+                    ColumnKind::String => ColumnBuffer::String(vec![]),
+                    ColumnKind::OptionalString => ColumnBuffer::OptionalString(vec![], vec![]),
+                })
+                .collect(),
+            rows: 0,
+        })
+    }
+
+    fn push(&mut self, values: &[Value<'_>]) -> parquet::errors::Result<()> {
+        for (buffer, value) in self.buffers.iter_mut().zip(values) {
+            match (buffer, value) {
+                (ColumnBuffer::Int(values), Value::Int(value)) => values.push(to_i64(*value)),
+                (ColumnBuffer::OptionalInt(values, definitions), Value::OptionalInt(value)) => {
+                    definitions.push(i16::from(value.is_some()));
+                    values.extend(value.map(to_i64));
+                }
+                (ColumnBuffer::String(values), Value::String(value)) => {
+                    values.push(ByteArray::from(*value));
+                }
+                (
+                    ColumnBuffer::OptionalString(values, definitions),
+                    Value::OptionalString(value),
+                ) => {
+                    definitions.push(i16::from(value.is_some()));
+                    values.extend(value.map(ByteArray::from));
+                }
+                _ => unreachable!("values match the kind of their column"),
+            }
+        }
+        self.rows += 1;
+
+        if self.rows == ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group.
+    fn flush(&mut self) -> parquet::errors::Result<()> {
+        let mut row_group = self.writer.next_row_group()?;
+        for buffer in &mut self.buffers {
+            let mut column = row_group
+                .next_column()?
+                .expect("there is a column for each buffer");
+            let _ = match buffer {
+                ColumnBuffer::Int(values) => column
+                    .typed::<Int64Type>()
+                    .write_batch(values, None, None)?,
+                ColumnBuffer::OptionalInt(values, definitions) => column
+                    .typed::<Int64Type>()
+                    .write_batch(values, Some(definitions), None)?,
+                ColumnBuffer::String(values) => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, None, None)?,
+                ColumnBuffer::OptionalString(values, definitions) => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, Some(definitions), None)?,
+            };
+            column.close()?;
+            buffer.clear();
+        }
+        drop(row_group.close()?);
+        self.rows = 0;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> parquet::errors::Result<()> {
+        if self.rows > 0 {
+            self.flush()?;
+        }
+        drop(self.writer.close()?);
+        Ok(())
+    }
+}
+
+impl ColumnBuffer {
+    fn clear(&mut self) {
+        match self {
+            ColumnBuffer::Int(values) => values.clear(),
+            ColumnBuffer::OptionalInt(values, definitions) => {
+                values.clear();
+                definitions.clear();
+            }
+            ColumnBuffer::String(values) => values.clear(),
+            ColumnBuffer::OptionalString(values, definitions) => {
+                values.clear();
+                definitions.clear();
+            }
+        }
+    }
+}
+
+/// Parquet has no unsigned 64-bit integers, so the largest values saturate.
+fn to_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::{
+        file::{reader::FileReader, serialized_reader::SerializedFileReader},
+        record::RowAccessor,
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::app::{
+        scheduler::Recording,
+        worker::{Scenario, StreamOutcome},
+    };
+
+    fn write(path: &Path, format: TableFormat, rows: &[StreamRecord]) {
+        let mut writer = TableWriter::create(path, format).unwrap();
+        for row in rows {
+            writer.write(row).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn records(count: u64) -> Vec<StreamRecord> {
+        (0..count)
+            .map(|i| StreamRecord {
+                intended_start_ns: 1_000 * i,
+                start_ns: 1_000 * i + 10,
+                end_ns: 1_000 * i + 500,
+                task: 2,
+                endpoint: 1,
+                connection: i / 10,
+                outcome: if i % 2 == 0 {
+                    StreamOutcome::Completed
+                } else {
+                    StreamOutcome::Failed
+                },
+                connection_wait_ns: 5,
+                tls_handshake_ns: None,
+                duration_ns: 485,
+                setup_ns: (i % 2 == 0).then_some(100),
+                request_headers_ns: None,
+                response_headers_ns: None,
+                time_to_first_response_ns: (i % 2 == 0).then_some(150),
+                error: (i % 2 == 1).then(|| "Unavailable".to_owned()),
+                scenario: Scenario::Headers,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_csv_table() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("records.csv");
+
+        write(&path, TableFormat::Csv, &records(2));
+
+        let csv = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "intended_start_ns,start_ns,end_ns,task,endpoint,connection,outcome,connection_wait_ns,tls_handshake_ns,duration_ns,setup_ns,request_headers_ns,response_headers_ns,time_to_first_response_ns,error,scenario",
+                "0,10,500,2,1,0,completed,5,,485,100,,,150,,headers",
+                "1000,1010,1500,2,1,0,failed,5,,485,,,,,Unavailable,headers",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_parquet_table_in_row_groups() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("records.parquet");
+        let count = u64::try_from(ROW_GROUP_SIZE).unwrap() + 10;
+
+        write(&path, TableFormat::Parquet, &records(count));
+
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), ROW_GROUP_SIZE + 10);
+        let last = rows.last().unwrap();
+        assert_eq!(
+            last.get_long(0).unwrap(),
+            1_000 * (i64::try_from(count).unwrap() - 1)
+        );
+        assert_eq!(last.get_string(6).unwrap(), "failed");
+        assert!(last.get_long(10).is_err(), "setup_ns should be null");
+        assert_eq!(rows[0].get_long(10).unwrap(), 100);
+        assert_eq!(last.get_string(14).unwrap(), "Unavailable");
+        assert!(rows[0].get_string(14).is_err(), "error should be null");
+        assert_eq!(last.get_string(15).unwrap(), "headers");
+    }
+
+    #[test]
+    fn test_step_summary_columns_match_values() {
+        let summary = StepSummary::new(
+            10,
+            Duration::from_secs(10),
+            100,
+            0,
+            1,
+            &Recording::default().histogram(),
+            &Phases::new(Recording::default()),
+        );
+
+        let columns = StepSummary::columns();
+        assert_eq!(columns.len(), summary.values().len());
+        assert!(columns.iter().any(|column| column.name == "p99_99_ns"));
+        assert!(
+            columns
+                .iter()
+                .any(|column| column.name == "time_to_first_response_p50_ns")
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    sync::watch,
+    task::JoinSet,
+    time::{self, Instant},
+};
+use tonic::{
+    service::{Interceptor, interceptor::InterceptedService},
+    transport::{Channel, Endpoint},
+};
+use tonic_health::pb::{
+    HealthCheckRequest, health_check_response::ServingStatus, health_client::HealthClient,
+};
+
+use crate::app::{
+    error::{Error, Result},
+    report,
+    transport::Connector,
+};
+
+/// The health of an endpoint, as reported by `grpc.health.v1.Health/Check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum HealthStatus {
+    /// No check completed yet.
+    Unknown,
+    Serving,
+    NotServing,
+    /// The server does not know the checked service.
+    ServiceUnknown,
+    /// The check failed, e.g. because the server is down.
+    Unreachable,
+}
+
+/// A change of the health of an endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct HealthTransition {
+    /// Time since the start of the run.
+    #[serde(rename = "at_ms", serialize_with = "report::as_millis")]
+    pub(crate) at: Duration,
+    pub(crate) uri: String,
+    pub(crate) status: HealthStatus,
+}
+
+/// Settings of the health checks.
+#[derive(Debug, Clone)]
+pub(crate) struct HealthCheckSettings {
+    /// The checked service, the whole server if empty.
+    pub(crate) service: String,
+    pub(crate) interval: Duration,
+    /// How long to wait for the endpoints to be serving before giving up.
+    pub(crate) timeout: Duration,
+}
+
+/// Checks the health of the endpoints in the background, for the whole run.
+///
+/// Uses its own connections, so that the checks do not share the connections
+/// of the load.
+#[derive(Debug)]
+pub(crate) struct HealthWatcher {
+    /// The last status of each endpoint, with the generation of `refresh` it was
+    /// checked at.
+    statuses: watch::Receiver<Vec<(HealthStatus, u64)>>,
+    /// Incremented to check the endpoints right away, without waiting for the interval.
+    refresh: watch::Sender<u64>,
+    transitions: Arc<Mutex<Vec<HealthTransition>>>,
+    timeout: Duration,
+    _tasks: JoinSet<()>,
+}
+
+impl HealthWatcher {
+    /// Starts checking the health of the endpoints, identified by their URI, with
+    /// `interceptor` applied to the checks like to the `process` calls.
+    pub(crate) fn spawn<I>(
+        uris: &[String],
+        endpoints: &[(Endpoint, Connector)],
+        interceptor: &I,
+        settings: &HealthCheckSettings,
+    ) -> Self
+    where
+        I: Interceptor + Clone + Send + 'static,
+    {
+        let start = Instant::now();
+        let (sender, statuses) = watch::channel(vec![(HealthStatus::Unknown, 0); endpoints.len()]);
+        let sender = Arc::new(sender);
+        let (refresh, _) = watch::channel(0);
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+
+        let mut tasks = JoinSet::new();
+        for (index, (uri, (endpoint, connector))) in uris.iter().zip(endpoints).enumerate() {
+            // NOTE: Lazy, so that the server does not have to be up yet.
+            let channel = endpoint.connect_with_connector_lazy(connector.for_connection());
+            let check = Check {
+                client: HealthClient::with_interceptor(channel, interceptor.clone()),
+                service: settings.service.clone(),
+                interval: settings.interval,
+            };
+            let uri = uri.clone();
+            let sender = sender.clone();
+            let transitions = transitions.clone();
+
+            let _handle = tasks.spawn(check.run(refresh.subscribe(), move |status, generation| {
+                sender.send_modify(|statuses| {
+                    let (previous, _) = mem::replace(&mut statuses[index], (status, generation));
+                    if previous == status {
+                        return;
+                    }
+                    transitions
+                        .lock()
+                        .expect("health transitions are not poisoned")
+                        .push(HealthTransition {
+                            at: start.elapsed(),
+                            uri: uri.clone(),
+                            status,
+                        });
+                });
+            }));
+        }
+
+        Self {
+            statuses,
+            refresh,
+            transitions,
+            timeout: settings.timeout,
+            _tasks: tasks,
+        }
+    }
+
+    /// Checks the endpoints again, and waits until they all report they are serving.
+    pub(crate) async fn wait_until_serving(&self) -> Result<()> {
+        let mut generation = 0;
+        self.refresh.send_modify(|refresh| {
+            *refresh += 1;
+            generation = *refresh;
+        });
+
+        let mut statuses = self.statuses.clone();
+        let serving = time::timeout(
+            self.timeout,
+            statuses.wait_for(|statuses| {
+                statuses.iter().all(|(status, checked)| {
+                    *checked >= generation && *status == HealthStatus::Serving
+                })
+            }),
+        )
+        .await
+        .map_err(|_| Error::ServerNotServing(self.timeout))?
+        .expect("health checks run as long as the watcher");
+        drop(serving);
+
+        Ok(())
+    }
+
+    /// The transitions observed so far.
+    pub(crate) fn transitions(&self) -> Vec<HealthTransition> {
+        self.transitions
+            .lock()
+            .expect("health transitions are not poisoned")
+            .clone()
+    }
+}
+
+/// Periodic health checks of a single endpoint.
+#[derive(Debug)]
+struct Check<I> {
+    client: HealthClient<InterceptedService<Channel, I>>,
+    service: String,
+    interval: Duration,
+}
+
+impl<I> Check<I>
+where
+    I: Interceptor,
+{
+    /// Checks the health at every interval, and whenever `refresh` changes, calling
+    /// `on_check` with the status and the generation of `refresh` it was checked at.
+    async fn run(
+        mut self,
+        mut refresh: watch::Receiver<u64>,
+        on_check: impl Fn(HealthStatus, u64),
+    ) {
+        let mut interval = time::interval(self.interval);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                Ok(()) = refresh.changed() => {}
+            }
+
+            let generation = *refresh.borrow_and_update();
+            let status = self.check().await;
+            on_check(status, generation);
+        }
+    }
+
+    async fn check(&mut self) -> HealthStatus {
+        let request = HealthCheckRequest {
+            service: self.service.clone(),
+        };
+        // NOTE: A check must not take longer than the interval, or it would hide transitions.
+        let Ok(Ok(response)) = time::timeout(self.interval, self.client.check(request)).await
+        else {
+            return HealthStatus::Unreachable;
+        };
+
+        match response.into_inner().status() {
+            ServingStatus::Serving => HealthStatus::Serving,
+            ServingStatus::NotServing => HealthStatus::NotServing,
+            ServingStatus::ServiceUnknown => HealthStatus::ServiceUnknown,
+            ServingStatus::Unknown => HealthStatus::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+    use tonic_health::ServingStatus as ReportedStatus;
+
+    use super::*;
+    use crate::app::{
+        metadata::MetadataInterceptor,
+        test_server,
+        transport::{Target, TcpSettings},
+    };
+
+    const SETTINGS: HealthCheckSettings = HealthCheckSettings {
+        service: String::new(),
+        interval: Duration::from_millis(20),
+        timeout: Duration::from_millis(500),
+    };
+
+    fn watch(path: &std::path::Path) -> HealthWatcher {
+        watch_with(path, &MetadataInterceptor::default(), &SETTINGS)
+    }
+
+    fn watch_with<I>(
+        path: &std::path::Path,
+        interceptor: &I,
+        settings: &HealthCheckSettings,
+    ) -> HealthWatcher
+    where
+        I: Interceptor + Clone + Send + 'static,
+    {
+        let uri = format!("unix:{}", path.display());
+        let (target, endpoint_uri) = Target::parse(&uri, false);
+        let endpoints = [(
+            Endpoint::new(endpoint_uri).unwrap(),
+            Connector::new(target, TcpSettings::default(), None),
+        )];
+
+        HealthWatcher::spawn(&[uri], &endpoints, interceptor, settings)
+    }
+
+    fn statuses(watcher: &HealthWatcher) -> Vec<HealthStatus> {
+        watcher
+            .transitions()
+            .into_iter()
+            .map(|transition| transition.status)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_serving_waits_for_healthy_server() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let reporter = test_server::serve_unix_with_health(UnixListener::bind(&path).unwrap());
+        reporter
+            .set_service_status("", ReportedStatus::NotServing)
+            .await;
+
+        let watcher = watch(&path);
+        match watcher.wait_until_serving().await {
+            Err(Error::ServerNotServing(_)) => {}
+            other => panic!("Expected ServerNotServing error, got {other:?}"),
+        }
+
+        reporter
+            .set_service_status("", ReportedStatus::Serving)
+            .await;
+        watcher.wait_until_serving().await.unwrap();
+
+        assert_eq!(
+            statuses(&watcher),
+            [HealthStatus::NotServing, HealthStatus::Serving]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_not_serving() {
+        let directory = TempDir::new().unwrap();
+        let watcher = watch(&directory.path().join("missing.sock"));
+
+        let result = watcher.wait_until_serving().await;
+
+        assert!(result.is_err(), "Expected an error, got {result:?}");
+        assert_eq!(statuses(&watcher), [HealthStatus::Unreachable]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_serving_checks_again() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let reporter = test_server::serve_unix_with_health(UnixListener::bind(&path).unwrap());
+        reporter
+            .set_service_status("", ReportedStatus::Serving)
+            .await;
+
+        // NOTE: The interval is too long for a periodic check to happen during the test.
+        let settings = HealthCheckSettings {
+            interval: Duration::from_mins(1),
+            ..SETTINGS
+        };
+        let watcher = watch_with(&path, &MetadataInterceptor::default(), &settings);
+        watcher.wait_until_serving().await.unwrap();
+
+        reporter
+            .set_service_status("", ReportedStatus::NotServing)
+            .await;
+        let result = watcher.wait_until_serving().await;
+
+        assert!(result.is_err(), "Expected an error, got {result:?}");
+        assert_eq!(
+            statuses(&watcher),
+            [HealthStatus::Serving, HealthStatus::NotServing]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checks_go_through_the_interceptor() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let reporter = test_server::serve_unix_with_health(UnixListener::bind(&path).unwrap());
+        reporter
+            .set_service_status("", ReportedStatus::Serving)
+            .await;
+        let deny = |_: tonic::Request<()>| -> Result<_, tonic::Status> {
+            Err(tonic::Status::unauthenticated("no token"))
+        };
+
+        let watcher = watch_with(&path, &deny, &SETTINGS);
+        let result = watcher.wait_until_serving().await;
+
+        assert!(result.is_err(), "Expected an error, got {result:?}");
+        assert_eq!(statuses(&watcher), [HealthStatus::Unreachable]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{fs, io, path::Path};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::app::{
+    cli::ReportArgs,
+    error::{Error, Result},
+    manifest::{self, LEGACY_FORMAT_VERSION, RunFiles},
+    report,
+    summary::{self, LatencySummary},
+};
+
+/// The name of the report, in the run directory.
+const REPORT_FILE: &str = "report.html";
+
+/// The page of the report, in which the data of the run replaces `/*DATA*/`.
+const TEMPLATE: &str = include_str!("html_report.html");
+
+/// The number of points of the tail latency curve of each step.
+const TAIL_POINTS: u32 = 200;
+
+/// The data embedded in the report.
+#[derive(Debug, Serialize)]
+struct ReportData {
+    manifest: Value,
+    /// The data of each step of the manifest, in the same order.
+    steps: Vec<StepData>,
+}
+
+#[derive(Debug, Serialize)]
+struct StepData {
+    target_throughput: u64,
+    tail: Option<TailCurve>,
+    endpoints: Option<Value>,
+    downtime: Option<Value>,
+}
+
+/// The latency of a step by percentile, as the fraction of the streams that were
+/// slower, from 1 down to a single stream.
+#[derive(Debug, PartialEq, Serialize)]
+struct TailCurve {
+    tail: Vec<f64>,
+    latency_ns: Vec<u64>,
+}
+
+impl TailCurve {
+    fn new(histogram: &Histogram<u64>) -> Self {
+        let streams = summary::float(histogram.len().max(1));
+        let (tail, latency_ns) = (0..TAIL_POINTS)
+            .map(|point| {
+                // NOTE: The points are spread evenly on a log scale, to show the tail.
+                let tail = streams.powf(-f64::from(point) / f64::from(TAIL_POINTS - 1));
+                (tail, histogram.value_at_quantile(1.0 - tail))
+            })
+            .unzip();
+        Self { tail, latency_ns }
+    }
+}
+
+/// Writes `report.html` into a run directory, for the `report` command: a single
+/// page with the data of the run and the code of its charts, which works offline.
+pub(crate) fn write(args: &ReportArgs) -> Result<()> {
+    let data = read_run(&args.directory).map_err(Error::FailedToReadRun)?;
+
+    // NOTE: `<` is escaped so that no string of the data can close the script.
+    let json = serde_json::to_string(&data)
+        .map_err(io::Error::from)
+        .map_err(Error::WriteReport)?
+        .replace('<', "\\u003c");
+    let path = args.directory.join(REPORT_FILE);
+    fs::write(&path, TEMPLATE.replacen("/*DATA*/", &json, 1)).map_err(Error::WriteReport)?;
+
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn read_run(directory: &Path) -> io::Result<ReportData> {
+    let mut manifest = manifest::read(directory)?;
+    let run: RunFiles = serde_json::from_value(manifest.clone())?;
+    let legacy = manifest["format_version"] == LEGACY_FORMAT_VERSION;
+
+    let mut steps = Vec::with_capacity(run.steps.len());
+    for (index, step) in run.steps.iter().enumerate() {
+        let file = |kind| step.path(directory, kind);
+        let histogram = match file("histogram") {
+            Some(path) => Some(report::read_histogram(&path)?),
+            None if legacy => file("durations")
+                .map(|path| report::read_durations(&path))
+                .transpose()?
+                .map(|durations| report::durations_histogram(&durations)),
+            None => None,
+        };
+        // NOTE: A run written before the manifest only has the latency of its steps.
+        if legacy
+            && let Some(histogram) = &histogram
+            && let Value::Object(latency) = serde_json::to_value(LatencySummary::new(histogram))?
+            && let Value::Object(summary) = &mut manifest["steps"][index]["summary"]
+        {
+            summary.extend(latency);
+        }
+        steps.push(StepData {
+            target_throughput: step.summary.target_throughput,
+            tail: histogram.as_ref().map(TailCurve::new),
+            endpoints: file("endpoints").map(|path| read_json(&path)).transpose()?,
+            downtime: file("downtime").map(|path| read_json(&path)).transpose()?,
+        });
+    }
+
+    Ok(ReportData { manifest, steps })
+}
+
+fn read_json(path: &Path) -> io::Result<Value> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::app::manifest::FORMAT_VERSION;
+
+    #[tokio::test]
+    async fn test_report_embeds_the_run() {
+        let directory = TempDir::new().unwrap();
+        let mut histogram = Histogram::<u64>::new(3).unwrap();
+        for latency in 1..=1000 {
+            histogram.record(latency * 1_000).unwrap();
+        }
+        report::write_histogram(
+            directory.path(),
+            100,
+            SystemTime::now(),
+            Duration::from_secs(10),
+            &histogram,
+        )
+        .await
+        .unwrap();
+        let manifest = serde_json::json!({
+            "format_version": FORMAT_VERSION,
+            "run_id": "</script>",
+            "steps": [{
+                "summary": {
+                    "target_throughput": 100,
+                    "failures_by_code": { "Internal": 2, "Unavailable": 1 },
+                },
+                "files": { "histogram": "histogram_100.hlog" },
+            }],
+        });
+        fs::write(directory.path().join("manifest.json"), manifest.to_string()).unwrap();
+
+        write(&ReportArgs {
+            directory: directory.path().to_owned(),
+        })
+        .unwrap();
+
+        let html = fs::read_to_string(directory.path().join(REPORT_FILE)).unwrap();
+        assert!(!html.contains("/*DATA*/"));
+        assert!(
+            !html.contains("src=\"http"),
+            "Nothing should be loaded from the network"
+        );
+        assert_eq!(html.matches("</script>").count(), 2);
+        assert!(
+            html.contains(r#""failures_by_code":{"Internal":2,"Unavailable":1}"#),
+            "The error breakdown should be embedded"
+        );
+        let tail = read_run(directory.path()).unwrap().steps[0]
+            .tail
+            .take()
+            .unwrap();
+        assert_eq!(tail.latency_ns[0], histogram.min());
+        assert!(histogram.equivalent(*tail.latency_ns.last().unwrap(), 999_000));
+    }
+
+    #[test]
+    fn test_report_reads_runs_without_manifest() {
+        let directory = TempDir::new().unwrap();
+        let durations = (1..=1000)
+            .map(|latency| latency * 1_000)
+            .collect::<Vec<u64>>();
+        fs::write(
+            directory.path().join("durations_100.json"),
+            serde_json::to_string(&durations).unwrap(),
+        )
+        .unwrap();
+
+        write(&ReportArgs {
+            directory: directory.path().to_owned(),
+        })
+        .unwrap();
+
+        let data = read_run(directory.path()).unwrap();
+        let summary = &data.manifest["steps"][0]["summary"];
+        assert_eq!(summary["target_throughput"], 100);
+        assert_eq!(summary["streams"], 1000);
+        let tail = data.steps[0].tail.as_ref().unwrap();
+        assert_eq!(tail.latency_ns[0], 1_000);
+    }
+}
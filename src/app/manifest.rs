@@ -0,0 +1,543 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+
+use crate::app::{
+    error::{Error, Result},
+    report::Metadata,
+    slo::SloCheck,
+    summary::StepSummary,
+};
+
+/// Version of the format of `manifest.json`, incremented on incompatible changes.
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+/// Version of the manifest of a run written before `manifest.json` existed, which
+/// only lists the steps of its `durations_<rate>.json` files.
+pub(crate) const LEGACY_FORMAT_VERSION: u32 = 0;
+
+/// Describes a run and links to the files of its steps, in `manifest.json`.
+///
+/// The manifest is rewritten after each step, so that it also describes runs that
+/// were interrupted.
+#[derive(Debug, Serialize)]
+pub(crate) struct Manifest<'a> {
+    format_version: u32,
+    tester: Tester,
+    pub(crate) run_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub(crate) finished_at: Option<OffsetDateTime>,
+    /// Command line of the run, without the values of the gRPC metadata.
+    args: Vec<String>,
+    pub(crate) config: &'a Metadata<'a>,
+    pub(crate) outcome: RunOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tester {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RunOutcome {
+    Running,
+    /// Every step reached its target throughput.
+    Completed,
+    /// A step could not reach its target throughput, which ended the run.
+    Saturated,
+    /// Every step reached its target throughput, but an SLO was breached.
+    SloBreached,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StepOutcome {
+    Completed,
+    /// The step could not reach its target throughput.
+    Saturated,
+}
+
+impl RunOutcome {
+    /// The name of the outcome, as serialized.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            RunOutcome::Running => "running",
+            RunOutcome::Completed => "completed",
+            RunOutcome::Saturated => "saturated",
+            RunOutcome::SloBreached => "slo_breached",
+            RunOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// A step of the run, at a single target throughput.
+#[derive(Debug, Serialize)]
+pub(crate) struct Step {
+    #[serde(with = "time::serde::rfc3339")]
+    pub(crate) started_at: OffsetDateTime,
+    pub(crate) outcome: StepOutcome,
+    pub(crate) summary: StepSummary,
+    /// The files written for the step by kind, relative to the run directory.
+    pub(crate) files: BTreeMap<&'static str, String>,
+    /// The rows left out of the files of the step by kind, because they could not be
+    /// written fast enough. These files are incomplete.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) dropped_rows: BTreeMap<&'static str, u64>,
+    /// The SLOs checked for the step.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) slo: Vec<SloCheck>,
+}
+
+impl<'a> Manifest<'a> {
+    pub(crate) fn new(
+        run_id: String,
+        started_at: OffsetDateTime,
+        args: impl IntoIterator<Item = String>,
+        config: &'a Metadata<'a>,
+    ) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            tester: Tester {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            run_id,
+            started_at,
+            finished_at: None,
+            args: redact_args(args),
+            config,
+            outcome: RunOutcome::Running,
+            error: None,
+            steps: vec![],
+        }
+    }
+
+    pub(crate) fn push_step(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub(crate) fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Records the outcome of the run.
+    pub(crate) fn finish(&mut self, result: &Result<()>) {
+        self.finished_at = Some(OffsetDateTime::now_utc());
+        (self.outcome, self.error) = match result {
+            Ok(()) => (RunOutcome::Completed, None),
+            Err(e @ Error::CouldNotReachTargetThroughput(..)) => {
+                (RunOutcome::Saturated, Some(e.to_string()))
+            }
+            Err(e @ Error::SloBreached(_)) => (RunOutcome::SloBreached, Some(e.to_string())),
+            Err(e) => (RunOutcome::Failed, Some(e.to_string())),
+        };
+    }
+}
+
+/// The parts of `manifest.json` read back by the commands that work on past runs.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RunFiles {
+    pub(crate) steps: Vec<StepFiles>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StepFiles {
+    pub(crate) summary: StepTarget,
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    dropped_rows: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StepTarget {
+    pub(crate) target_throughput: u64,
+}
+
+impl StepFiles {
+    /// The path of the file of the given kind, if it was written for the step.
+    pub(crate) fn path(&self, directory: &Path, kind: &str) -> Option<PathBuf> {
+        self.files.get(kind).map(|file| directory.join(file))
+    }
+
+    /// The path of the file of the given kind, if it was written for the step without
+    /// dropping rows.
+    pub(crate) fn complete_path(&self, directory: &Path, kind: &str) -> Option<PathBuf> {
+        self.path(directory, kind)
+            .filter(|_| !self.dropped_rows.contains_key(kind))
+    }
+}
+
+/// Reads the `manifest.json` of a run directory, if this version can read it.
+///
+/// A run written before the manifest existed is described by a manifest of
+/// `LEGACY_FORMAT_VERSION`, with a step for each of its `durations_<rate>.json` files.
+pub(crate) fn read(directory: &Path) -> io::Result<Value> {
+    let manifest = match fs::read(directory.join("manifest.json")) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == ErrorKind::NotFound => return legacy_manifest(directory),
+        Err(e) => return Err(e),
+    };
+    let manifest: Value = serde_json::from_slice(&manifest)?;
+    let format_version = manifest["format_version"].as_u64().unwrap_or_default();
+    if format_version > u64::from(FORMAT_VERSION) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("manifest format version {format_version} is newer than {FORMAT_VERSION}"),
+        ));
+    }
+
+    Ok(manifest)
+}
+
+/// Lists the steps of a run written before the manifest existed, from the names of
+/// its `durations_<rate>.json` files.
+fn legacy_manifest(directory: &Path) -> io::Result<Value> {
+    let mut steps = BTreeMap::new();
+    for entry in fs::read_dir(directory)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let target_throughput = name
+            .strip_prefix("durations_")
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|rate| rate.parse::<u64>().ok());
+        if let Some(target_throughput) = target_throughput {
+            let _ = steps.insert(target_throughput, name.to_owned());
+        }
+    }
+    if steps.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "neither manifest.json nor durations_<rate>.json files in {}",
+                directory.display()
+            ),
+        ));
+    }
+
+    let steps = steps
+        .into_iter()
+        .map(|(target_throughput, file)| {
+            json!({
+                "summary": { "target_throughput": target_throughput },
+                "files": { "durations": file },
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "format_version": LEGACY_FORMAT_VERSION,
+        "run_id": directory.file_name().map(|name| name.to_string_lossy()),
+        "steps": steps,
+    }))
+}
+
+/// Names the directory of a run started at `started_at`, e.g. `20251018T093015123Z`.
+pub(crate) fn run_id(started_at: OffsetDateTime) -> String {
+    let started_at = started_at.to_offset(time::UtcOffset::UTC);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}{:03}Z",
+        started_at.year(),
+        u8::from(started_at.month()),
+        started_at.day(),
+        started_at.hour(),
+        started_at.minute(),
+        started_at.second(),
+        started_at.millisecond(),
+    )
+}
+
+/// Creates the directory of a new run in `parent`, named after its `run_id`.
+///
+/// If another run already has this directory, e.g. one started in the same
+/// millisecond, a suffix is added to the run ID. Returns the run ID and the directory.
+pub(crate) async fn create_run_directory(
+    parent: &Path,
+    run_id: &str,
+) -> io::Result<(String, PathBuf)> {
+    tokio::fs::create_dir_all(parent).await?;
+
+    let mut attempt = 0_u32;
+    loop {
+        let id = match attempt {
+            0 => run_id.to_owned(),
+            attempt => format!("{run_id}-{attempt}"),
+        };
+        let directory = parent.join(&id);
+        match tokio::fs::create_dir(&directory).await {
+            Ok(()) => return Ok((id, directory)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Hides the values of the gRPC metadata, as they may hold credentials.
+fn redact_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut is_metadata = false;
+    args.into_iter()
+        .map(|arg| {
+            let redacted = if is_metadata {
+                redact_metadata(&arg)
+            } else if let Some(entry) = arg.strip_prefix("--grpc-metadata=") {
+                format!("--grpc-metadata={}", redact_metadata(entry))
+            } else {
+                arg.clone()
+            };
+            is_metadata = arg == "--grpc-metadata";
+            redacted
+        })
+        .collect()
+}
+
+fn redact_metadata(entry: &str) -> String {
+    match entry.split_once('=') {
+        Some((key, _)) => format!("{key}=<redacted>"),
+        None => "<redacted>".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    use clap::Parser as _;
+    use hdrhistogram::Histogram;
+    use tempfile::TempDir;
+    use time::{Date, Month, UtcOffset};
+
+    use super::*;
+    use crate::app::{cli::Cli, phases::Phases, scheduler::Recording};
+
+    fn with_manifest(test: impl FnOnce(Manifest<'_>)) {
+        let cli = Cli::parse_from(["ext-proc-load-tester", "grpc://localhost:12345"]);
+        let metadata = Metadata {
+            uris: &cli.uris,
+            test_duration_s: 10,
+            throughputs: &[100],
+            runtime: cli.runtime,
+            threads: NonZeroUsize::MIN,
+            connections: NonZeroUsize::MIN,
+            scheduler_tasks: None,
+            histogram_precision: 3,
+            raw_samples: false,
+            stream_records: false,
+            output_format: cli.output_format,
+            balance: cli.balance,
+            transport: &cli.transport,
+            send_compression: None,
+            accept_compression: &[],
+        };
+        test(Manifest::new(
+            "20251018T093015123Z".to_owned(),
+            OffsetDateTime::UNIX_EPOCH,
+            ["ext-proc-load-tester".to_owned()],
+            &metadata,
+        ));
+    }
+
+    #[test]
+    fn test_manifest_json_shape() {
+        with_manifest(|mut manifest| {
+            let mut histogram = Histogram::new(3).unwrap();
+            histogram.record(1_000).unwrap();
+            manifest.push_step(Step {
+                started_at: OffsetDateTime::UNIX_EPOCH,
+                outcome: StepOutcome::Completed,
+                summary: StepSummary {
+                    failures: BTreeMap::from([("Unavailable".to_owned(), 2)]),
+                    ..StepSummary::new(
+                        100,
+                        Duration::from_secs(10),
+                        1_000,
+                        2,
+                        1,
+                        &histogram,
+                        &Phases::new(Recording::default()),
+                    )
+                },
+                files: BTreeMap::from([
+                    ("histogram", "histogram_100.hlog".to_owned()),
+                    ("records", "records_100.ndjson".to_owned()),
+                ]),
+                dropped_rows: BTreeMap::from([("records", 1_024)]),
+                slo: vec![],
+            });
+
+            let json = serde_json::to_value(&manifest).unwrap();
+            assert_eq!(json["format_version"], FORMAT_VERSION);
+            assert_eq!(json["tester"]["name"], "ext-proc-load-tester");
+            assert_eq!(json["run_id"], "20251018T093015123Z");
+            assert_eq!(json["started_at"], "1970-01-01T00:00:00Z");
+            assert_eq!(json["finished_at"], Value::Null);
+            assert_eq!(json["args"], json!(["ext-proc-load-tester"]));
+            assert_eq!(json["config"]["uris"], json!(["grpc://localhost:12345"]));
+            assert_eq!(json["outcome"], "running");
+            assert!(json.get("error").is_none());
+            let step = &json["steps"][0];
+            assert_eq!(step["outcome"], "completed");
+            assert_eq!(step["summary"]["target_throughput"], 100);
+            assert_eq!(
+                step["summary"]["failures_by_code"],
+                json!({"Unavailable": 2})
+            );
+            assert_eq!(
+                step["files"],
+                json!({"histogram": "histogram_100.hlog", "records": "records_100.ndjson"})
+            );
+            assert_eq!(step["dropped_rows"], json!({"records": 1_024}));
+            assert!(step.get("slo").is_none());
+
+            // NOTE: The commands working on past runs read it back.
+            let run: RunFiles = serde_json::from_value(json).unwrap();
+            assert_eq!(run.steps[0].summary.target_throughput, 100);
+            assert_eq!(
+                run.steps[0].path(Path::new("run"), "histogram"),
+                Some(PathBuf::from("run/histogram_100.hlog"))
+            );
+            assert_eq!(
+                run.steps[0].path(Path::new("run"), "records"),
+                Some(PathBuf::from("run/records_100.ndjson"))
+            );
+            assert_eq!(
+                run.steps[0].complete_path(Path::new("run"), "records"),
+                None
+            );
+            assert_eq!(
+                run.steps[0].complete_path(Path::new("run"), "histogram"),
+                Some(PathBuf::from("run/histogram_100.hlog"))
+            );
+        });
+    }
+
+    #[test]
+    fn test_finish_maps_the_result_to_the_outcome() {
+        with_manifest(|mut manifest| {
+            let results = [
+                (Ok(()), RunOutcome::Completed),
+                (
+                    Err(Error::CouldNotReachTargetThroughput(200, 190, 95)),
+                    RunOutcome::Saturated,
+                ),
+                (Err(Error::SloBreached(2)), RunOutcome::SloBreached),
+                (Err(Error::ShardStopped), RunOutcome::Failed),
+            ];
+            for (result, outcome) in results {
+                manifest.finish(&result);
+
+                assert_eq!(manifest.outcome, outcome);
+                assert_eq!(manifest.error, result.err().map(|e| e.to_string()));
+                assert!(manifest.finished_at.is_some());
+                let json = serde_json::to_value(&manifest).unwrap();
+                assert_eq!(json["outcome"], outcome.name());
+            }
+        });
+    }
+
+    #[test]
+    fn test_runs_without_manifest_are_read_from_their_durations() {
+        let directory = TempDir::new().unwrap();
+        for file in [
+            "durations_200.json",
+            "durations_100.json",
+            "connect_durations_100.json",
+        ] {
+            fs::write(directory.path().join(file), "[1000,2000]").unwrap();
+        }
+
+        let manifest = read(directory.path()).unwrap();
+        assert_eq!(manifest["format_version"], LEGACY_FORMAT_VERSION);
+        let run: RunFiles = serde_json::from_value(manifest).unwrap();
+        let steps = run
+            .steps
+            .iter()
+            .map(|step| {
+                (
+                    step.summary.target_throughput,
+                    step.path(directory.path(), "durations"),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            [
+                (100, Some(directory.path().join("durations_100.json"))),
+                (200, Some(directory.path().join("durations_200.json"))),
+            ]
+        );
+
+        let empty = TempDir::new().unwrap();
+        assert_eq!(read(empty.path()).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_run_directory_is_never_reused() {
+        let parent = TempDir::new().unwrap();
+        let parent = parent.path().join("results");
+
+        let (first, directory) = create_run_directory(&parent, "20251018T093015123Z")
+            .await
+            .unwrap();
+        let (second, _) = create_run_directory(&parent, "20251018T093015123Z")
+            .await
+            .unwrap();
+
+        assert_eq!(first, "20251018T093015123Z");
+        assert_eq!(directory, parent.join(&first));
+        assert_eq!(second, "20251018T093015123Z-1");
+        assert!(parent.join(&second).is_dir());
+    }
+
+    #[test]
+    fn test_run_id_is_utc_timestamp() {
+        let started_at = Date::from_calendar_date(2025, Month::October, 18)
+            .unwrap()
+            .with_hms_milli(11, 30, 15, 123)
+            .unwrap()
+            .assume_offset(UtcOffset::from_hms(2, 0, 0).unwrap());
+
+        assert_eq!(run_id(started_at), "20251018T093015123Z");
+    }
+
+    #[test]
+    fn test_redact_args_hides_metadata_values() {
+        let args = [
+            "ext-proc-load-tester",
+            "grpc://localhost:12345",
+            "--grpc-metadata",
+            "authorization=Bearer secret",
+            "--grpc-metadata=x-api-key=secret",
+            "--test-duration",
+            "10",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            redact_args(args),
+            [
+                "ext-proc-load-tester",
+                "grpc://localhost:12345",
+                "--grpc-metadata",
+                "authorization=<redacted>",
+                "--grpc-metadata=x-api-key=<redacted>",
+                "--test-duration",
+                "10",
+            ]
+        );
+    }
+}
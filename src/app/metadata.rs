@@ -0,0 +1,255 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use tokio::time::{self, Instant};
+use tonic::{
+    Request, Status,
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+    service::Interceptor,
+};
+
+use crate::app::{
+    cli::MetadataArgs,
+    error::{Error, Result},
+};
+
+/// How often the bearer token file is checked for changes.
+const TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Attaches the configured gRPC metadata, and the bearer token if any, to every
+/// `process` call.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetadataInterceptor {
+    entries: Arc<[(AsciiMetadataKey, AsciiMetadataValue)]>,
+    bearer_token: Option<Arc<BearerToken>>,
+}
+
+impl MetadataInterceptor {
+    /// Loads the metadata given on the command line and in the metadata file, and the
+    /// bearer token.
+    pub(crate) fn load(args: &MetadataArgs) -> Result<Self> {
+        let mut entries = vec![];
+        if let Some(path) = &args.metadata_file {
+            let content = fs::read_to_string(path).map_err(|e| invalid_metadata(path, &e))?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (key, value) = parse_entry(line).map_err(|e| invalid_metadata(path, &e))?;
+                entries.push((key, value));
+            }
+        }
+        for (key, value) in &args.metadata {
+            entries.push(parse_key_value(key, value).map_err(Error::InvalidGrpcMetadata)?);
+        }
+
+        let bearer_token = args
+            .bearer_token_file
+            .as_ref()
+            .map(|path| BearerToken::load(path))
+            .transpose()?;
+
+        Ok(Self {
+            entries: entries.into(),
+            bearer_token,
+        })
+    }
+}
+
+impl Interceptor for MetadataInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let metadata = request.metadata_mut();
+        for (key, value) in self.entries.iter() {
+            let _ = metadata.append(key.clone(), value.clone());
+        }
+        if let Some(bearer_token) = &self.bearer_token {
+            drop(metadata.insert("authorization", bearer_token.header()));
+        }
+
+        Ok(request)
+    }
+}
+
+/// Parses a `key=value` metadata entry.
+pub(crate) fn parse_entry(entry: &str) -> Result<(AsciiMetadataKey, AsciiMetadataValue), String> {
+    let (key, value) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("metadata must be formatted as key=value, got {entry}"))?;
+
+    parse_key_value(key.trim(), value.trim())
+}
+
+fn parse_key_value(
+    key: &str,
+    value: &str,
+) -> Result<(AsciiMetadataKey, AsciiMetadataValue), String> {
+    if key.ends_with("-bin") {
+        return Err(format!("binary metadata is not supported, got {key}"));
+    }
+    let key = AsciiMetadataKey::from_bytes(key.as_bytes())
+        .map_err(|e| format!("invalid metadata key {key}: {e}"))?;
+    let value = AsciiMetadataValue::try_from(value)
+        .map_err(|e| format!("invalid metadata value for {key}: {e}"))?;
+
+    Ok((key, value))
+}
+
+fn invalid_metadata(path: &Path, e: &impl fmt::Display) -> Error {
+    Error::InvalidGrpcMetadata(format!("{}: {e}", path.display()))
+}
+
+/// A bearer token read from a file, which is re-read in the background when the
+/// file changes, so that the token can be rotated during a run.
+#[derive(Debug)]
+struct BearerToken {
+    header: Mutex<AsciiMetadataValue>,
+}
+
+impl BearerToken {
+    /// Reads the token, and spawns the task re-reading it until the token is dropped.
+    fn load(path: &Path) -> Result<Arc<Self>> {
+        let (header, modified) = read_token(path).map_err(|e| invalid_metadata(path, &e))?;
+        let token = Arc::new(Self {
+            header: Mutex::new(header),
+        });
+
+        let _handle = tokio::spawn(reload(Arc::downgrade(&token), path.to_path_buf(), modified));
+
+        Ok(token)
+    }
+
+    /// Returns the `authorization` header.
+    fn header(&self) -> AsciiMetadataValue {
+        self.header
+            .lock()
+            .expect("bearer token is not poisoned")
+            .clone()
+    }
+}
+
+/// Re-reads the token every `TOKEN_CHECK_INTERVAL` if the file changed.
+///
+/// If the file cannot be read, e.g. while it is being replaced, the previous token
+/// is kept.
+async fn reload(token: Weak<BearerToken>, path: PathBuf, mut modified: Option<SystemTime>) {
+    let mut ticks = time::interval_at(Instant::now() + TOKEN_CHECK_INTERVAL, TOKEN_CHECK_INTERVAL);
+    loop {
+        let _ = ticks.tick().await;
+        let Some(token) = token.upgrade() else {
+            return;
+        };
+        let current = tokio::fs::metadata(&path)
+            .await
+            .and_then(|m| m.modified())
+            .ok();
+        if current == modified {
+            continue;
+        }
+
+        let read = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || read_token(&path)).await
+        };
+        if let Ok(Ok((header, read_modified))) = read {
+            *token.header.lock().expect("bearer token is not poisoned") = header;
+            modified = read_modified;
+        }
+    }
+}
+
+fn read_token(path: &Path) -> Result<(AsciiMetadataValue, Option<SystemTime>), String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let token = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("bearer token file is empty".to_string());
+    }
+
+    let header = AsciiMetadataValue::try_from(format!("Bearer {token}"))
+        .map_err(|e| format!("invalid bearer token: {e}"))?;
+
+    Ok((header, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn intercept(interceptor: &mut MetadataInterceptor) -> Request<()> {
+        interceptor.call(Request::new(())).unwrap()
+    }
+
+    #[test]
+    fn test_metadata_is_attached_to_calls() {
+        let directory = TempDir::new().unwrap();
+        let metadata_file = directory.path().join("metadata");
+        fs::write(
+            &metadata_file,
+            "# Identity of the caller\nx-envoy-peer = frontend\n\nx-tenant=a\n",
+        )
+        .unwrap();
+
+        let mut interceptor = MetadataInterceptor::load(&MetadataArgs {
+            metadata: vec![("x-tenant".to_string(), "b".to_string())],
+            metadata_file: Some(metadata_file),
+            bearer_token_file: None,
+        })
+        .unwrap();
+        let request = intercept(&mut interceptor);
+
+        let metadata = request.metadata();
+        assert_eq!(metadata.get("x-envoy-peer").unwrap(), "frontend");
+        let tenants = metadata.get_all("x-tenant").iter().collect::<Vec<_>>();
+        assert_eq!(tenants, ["a", "b"]);
+        assert!(metadata.get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_invalid_metadata_is_rejected() {
+        assert!(parse_entry("no-separator").is_err());
+        assert!(parse_entry("Invalid Key=value").is_err());
+        assert!(parse_entry("trace-bin=AAEC").is_err());
+        assert!(parse_entry("x-tenant=a").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_is_reread_when_it_changes() {
+        let directory = TempDir::new().unwrap();
+        let token_file = directory.path().join("token");
+        fs::write(&token_file, "first\n").unwrap();
+
+        let mut interceptor = MetadataInterceptor::load(&MetadataArgs {
+            metadata: vec![],
+            metadata_file: None,
+            bearer_token_file: Some(token_file.clone()),
+        })
+        .unwrap();
+        let request = intercept(&mut interceptor);
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Bearer first"
+        );
+
+        fs::write(&token_file, "second").unwrap();
+        // NOTE: Some filesystems only have a 1 second resolution for the modification time.
+        let file = fs::File::options().write(true).open(&token_file).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        let deadline = Instant::now() + TOKEN_CHECK_INTERVAL * 5;
+        loop {
+            let request = intercept(&mut interceptor);
+            if request.metadata().get("authorization").unwrap() == "Bearer second" {
+                break;
+            }
+            assert!(Instant::now() < deadline, "the token was not re-read");
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
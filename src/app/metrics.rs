@@ -0,0 +1,479 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use indicatif::ProgressBar;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::app::{
+    dashboard::DashboardFeed,
+    error::{Error, Result},
+    events::EventFeed,
+    otlp::SpanSender,
+    scheduler::{Progress, ProgressReporter},
+    summary::StepSummary,
+    worker::StreamOutcome,
+};
+
+/// Prefix of the names of the metrics.
+const PREFIX: &str = "ext_proc_load_tester";
+
+/// How long a scraper has to send its request before its connection is closed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bounds of the buckets of the latency histograms.
+pub(crate) const BUCKET_BOUNDS: [Duration; 16] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2_500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2_500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Durations counted in the buckets of a Prometheus histogram.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Buckets {
+    /// Number of durations in each bucket, not cumulative. The last bucket holds
+    /// the durations above the largest bound.
+    pub(crate) counts: [u64; BUCKET_BOUNDS.len() + 1],
+    pub(crate) sum: Duration,
+}
+
+impl Buckets {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let bucket = BUCKET_BOUNDS.partition_point(|bound| *bound < duration);
+        self.counts[bucket] += 1;
+        self.sum += duration;
+    }
+
+    /// Number of durations recorded.
+    pub(crate) fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+        let _ = writeln!(out, "# TYPE {PREFIX}_{name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKET_BOUNDS.iter().zip(self.counts) {
+            cumulative += count;
+            let le = bound.as_secs_f64();
+            let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let count = self.count();
+        let sum = self.sum.as_secs_f64();
+        let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{PREFIX}_{name}_sum {sum}");
+        let _ = writeln!(out, "{PREFIX}_{name}_count {count}");
+    }
+}
+
+#[derive(Debug, Default)]
+struct AtomicBuckets {
+    counts: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    sum_ns: AtomicU64,
+}
+
+impl AtomicBuckets {
+    fn add(&self, buckets: &Buckets) {
+        for (count, added) in self.counts.iter().zip(buckets.counts) {
+            let _ = count.fetch_add(added, Ordering::Relaxed);
+        }
+        let sum_ns = u64::try_from(buckets.sum.as_nanos()).unwrap_or(u64::MAX);
+        let _ = self.sum_ns.fetch_add(sum_ns, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> Buckets {
+        Buckets {
+            counts: self
+                .counts
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            sum: Duration::from_nanos(self.sum_ns.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The live metrics of the run, served in the Prometheus text format with
+/// `--metrics-listen` and exported with `--otlp-endpoint`.
+///
+/// They are fed by the same reports as the progress bars, so they lag behind the
+/// streams by up to one report interval.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    target_rate: AtomicU64,
+    sent: AtomicU64,
+    completed: AtomicU64,
+    closed_early: AtomicU64,
+    failed: AtomicU64,
+    in_flight: AtomicI64,
+    latency: AtomicBuckets,
+    scheduler_lag: AtomicBuckets,
+}
+
+/// The values of the metrics at some point of the run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    pub(crate) target_rate: u64,
+    pub(crate) sent: u64,
+    pub(crate) completed: u64,
+    pub(crate) closed_early: u64,
+    pub(crate) failed: u64,
+    pub(crate) in_flight: i64,
+    pub(crate) latency: Buckets,
+    pub(crate) scheduler_lag: Buckets,
+}
+
+impl Snapshot {
+    /// The number of streams that ended, by outcome.
+    pub(crate) fn outcomes(&self) -> [(StreamOutcome, u64); 3] {
+        [
+            (StreamOutcome::Completed, self.completed),
+            (StreamOutcome::ClosedEarly, self.closed_early),
+            (StreamOutcome::Failed, self.failed),
+        ]
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP {PREFIX}_target_rate Target rate of the current step, in streams per second."
+        );
+        let _ = writeln!(out, "# TYPE {PREFIX}_target_rate gauge");
+        let _ = writeln!(out, "{PREFIX}_target_rate {}", self.target_rate);
+
+        let _ = writeln!(out, "# HELP {PREFIX}_streams_sent_total Streams started.");
+        let _ = writeln!(out, "# TYPE {PREFIX}_streams_sent_total counter");
+        let _ = writeln!(out, "{PREFIX}_streams_sent_total {}", self.sent);
+
+        let _ = writeln!(
+            out,
+            "# HELP {PREFIX}_streams_total Streams ended, by outcome."
+        );
+        let _ = writeln!(out, "# TYPE {PREFIX}_streams_total counter");
+        for (outcome, count) in self.outcomes() {
+            let outcome = outcome.name();
+            let _ = writeln!(
+                out,
+                "{PREFIX}_streams_total{{outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(out, "# HELP {PREFIX}_streams_in_flight Streams in flight.");
+        let _ = writeln!(out, "# TYPE {PREFIX}_streams_in_flight gauge");
+        let _ = writeln!(out, "{PREFIX}_streams_in_flight {}", self.in_flight);
+
+        self.latency.render(
+            &mut out,
+            "stream_duration_seconds",
+            "Latency of the streams that did not fail, excluding the connection wait.",
+        );
+        self.scheduler_lag.render(
+            &mut out,
+            "scheduler_lag_seconds",
+            "Delay between the planned and the actual start of the streams.",
+        );
+
+        out
+    }
+}
+
+impl Metrics {
+    /// Serves the metrics on `address`, until the end of the run.
+    pub(crate) async fn serve(&self, address: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(Error::FailedToServeMetrics)?;
+        self.serve_on(listener);
+        Ok(())
+    }
+
+    fn serve_on(&self, listener: TcpListener) {
+        let served = self.clone();
+        let _handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let metrics = served.clone();
+                let _handle = tokio::spawn(async move {
+                    // NOTE: A scraper that goes away must not interrupt the run.
+                    drop(respond(stream, &metrics).await);
+                });
+            }
+        });
+    }
+
+    /// Sets the target rate of the current step, in streams per second.
+    pub(crate) fn set_target_rate(&self, target_rate: u64) {
+        self.0.target_rate.store(target_rate, Ordering::Relaxed);
+    }
+
+    fn record(&self, progress: &Progress) {
+        let metrics = &self.0;
+        let _ = metrics.sent.fetch_add(progress.sent, Ordering::Relaxed);
+        let _ = metrics
+            .completed
+            .fetch_add(progress.completed, Ordering::Relaxed);
+        let _ = metrics
+            .closed_early
+            .fetch_add(progress.closed_early, Ordering::Relaxed);
+        let _ = metrics.failed.fetch_add(progress.failed, Ordering::Relaxed);
+        let _ = metrics
+            .in_flight
+            .fetch_add(progress.in_flight, Ordering::Relaxed);
+        metrics.latency.add(&progress.latency);
+        metrics.scheduler_lag.add(&progress.scheduler_lag);
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let metrics = &self.0;
+        Snapshot {
+            target_rate: metrics.target_rate.load(Ordering::Relaxed),
+            sent: metrics.sent.load(Ordering::Relaxed),
+            completed: metrics.completed.load(Ordering::Relaxed),
+            closed_early: metrics.closed_early.load(Ordering::Relaxed),
+            failed: metrics.failed.load(Ordering::Relaxed),
+            in_flight: metrics.in_flight.load(Ordering::Relaxed),
+            latency: metrics.latency.load(),
+            scheduler_lag: metrics.scheduler_lag.load(),
+        }
+    }
+}
+
+/// Answers a single HTTP/1.1 request with the metrics, then closes the connection.
+/// The connection is also closed if the request is not received within
+/// `REQUEST_TIMEOUT`.
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await??;
+
+    let response = if request_path(&request) == Some(b"/metrics".as_slice()) {
+        let body = metrics.snapshot().render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads the request line and the headers of a request, up to 8 KiB.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") && !request.ends_with(b"\n\n") && request.len() < 8192 {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(request)
+}
+
+/// The path of a `GET` request, without its query, from its request line: e.g.
+/// `/metrics` for `GET /metrics?name[]=up HTTP/1.0`.
+fn request_path(request: &[u8]) -> Option<&[u8]> {
+    let line = request.split(|&byte| byte == b'\n').next()?;
+    let mut parts = line
+        .split(u8::is_ascii_whitespace)
+        .filter(|part| !part.is_empty());
+    let (method, target) = (parts.next()?, parts.next()?);
+    let path = target.split(|&byte| byte == b'?').next()?;
+
+    (method == b"GET").then_some(path)
+}
+
+/// Reports the progress of a step to its progress bar, to the dashboard if it is
+/// shown, to the events if they are written, to the metrics if they are served or
+/// exported, and the traced streams to the OTLP exporter.
+#[derive(Debug, Clone)]
+pub(crate) struct StepReporter {
+    pub(crate) progress_bar: ProgressBar,
+    pub(crate) dashboard: Option<DashboardFeed>,
+    pub(crate) events: Option<EventFeed>,
+    pub(crate) metrics: Option<Metrics>,
+    pub(crate) spans: Option<SpanSender>,
+}
+
+impl ProgressReporter for StepReporter {
+    fn report(&self, progress: &Progress) {
+        self.progress_bar.report(progress);
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.report(progress);
+        }
+        if let Some(events) = &self.events {
+            events.report(progress);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record(progress);
+        }
+        if let Some(spans) = &self.spans
+            && !progress.spans.is_empty()
+        {
+            spans.send(progress.spans.clone());
+        }
+    }
+
+    fn reports_percentiles(&self) -> bool {
+        self.dashboard.is_some() || self.events.is_some()
+    }
+
+    fn start_step(&self, target_rate: u64) {
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.start_step(target_rate);
+        }
+        if let Some(events) = &self.events {
+            events.start_step(target_rate);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.set_target_rate(target_rate);
+        }
+    }
+
+    fn finish_step(&self, summary: &StepSummary) {
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.finish_step(summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_include_their_upper_bound() {
+        let mut buckets = Buckets::default();
+        buckets.record(Duration::from_micros(100));
+        buckets.record(Duration::from_micros(101));
+        buckets.record(Duration::from_mins(1));
+
+        assert_eq!(buckets.counts[0], 1);
+        assert_eq!(buckets.counts[1], 1);
+        assert_eq!(buckets.counts[BUCKET_BOUNDS.len()], 1);
+    }
+
+    async fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_serves_reported_progress() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Metrics::default();
+        metrics.serve_on(listener);
+        metrics.set_target_rate(100);
+        let mut progress = Progress {
+            sent: 3,
+            completed: 1,
+            closed_early: 0,
+            failed: 1,
+            in_flight: 1,
+            ..Progress::default()
+        };
+        progress.latency.record(Duration::from_millis(2));
+        progress.scheduler_lag.record(Duration::from_micros(50));
+        metrics.record(&progress);
+        metrics.record(&Progress {
+            in_flight: -1,
+            ..Progress::default()
+        });
+
+        let response = get(address, "/metrics").await;
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "Got {response}"
+        );
+        for line in [
+            "ext_proc_load_tester_target_rate 100",
+            "ext_proc_load_tester_streams_sent_total 3",
+            "ext_proc_load_tester_streams_total{outcome=\"completed\"} 1",
+            "ext_proc_load_tester_streams_total{outcome=\"failed\"} 1",
+            "ext_proc_load_tester_streams_in_flight 0",
+            "ext_proc_load_tester_stream_duration_seconds_bucket{le=\"0.001\"} 0",
+            "ext_proc_load_tester_stream_duration_seconds_bucket{le=\"0.0025\"} 1",
+            "ext_proc_load_tester_stream_duration_seconds_count 1",
+            "ext_proc_load_tester_scheduler_lag_seconds_bucket{le=\"0.0001\"} 1",
+        ] {
+            assert!(
+                response.lines().any(|l| l == line),
+                "Missing {line} in {response}"
+            );
+        }
+
+        let response = get(address, "/").await;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "Got {response}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metrics_endpoint_closes_connections_without_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        Metrics::default().serve_on(listener);
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(REQUEST_TIMEOUT * 2, stream.read_to_end(&mut response))
+            .await
+            .expect("the connection is closed after the request timeout");
+        assert_eq!(read.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_request_path_ignores_the_query_and_the_spacing() {
+        for request in [
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /metrics?name[]=up HTTP/1.1\r\n\r\n",
+            "GET  /metrics\tHTTP/1.0\n\n",
+            "GET /metrics\r\n\r\n",
+        ] {
+            assert_eq!(
+                request_path(request.as_bytes()),
+                Some(b"/metrics".as_slice()),
+                "{request:?}"
+            );
+        }
+        assert_eq!(request_path(b"POST /metrics HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(request_path(b"GET\r\n\r\n"), None);
+        assert_eq!(
+            request_path(b"GET /metrics/other HTTP/1.1\r\n\r\n"),
+            Some(b"/metrics/other".as_slice())
+        );
+    }
+}
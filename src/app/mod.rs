@@ -1,20 +1,69 @@
-use std::{env, path::Path, time::Duration};
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Write as _,
+    num::NonZeroUsize,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime},
+};
 
 use crate::app::{
-    cli::Cli,
+    balancer::Balancer,
+    cli::{Cli, Command, EventFormat, ProgressDisplay, RuntimeMode},
+    dashboard::Dashboard,
+    downtime::DowntimeTracker,
     error::Error,
-    scheduler::{REPORT_INTERVAL, Scheduler},
+    events::{EventFeed, Events},
+    health::HealthWatcher,
+    manifest::{Manifest, RunOutcome, Step, StepOutcome},
+    metadata::MetadataInterceptor,
+    metrics::{Metrics, StepReporter},
+    otlp::{Exporter, OtlpSettings},
+    scheduler::{EndpointStats, ProgressReporter as _, REPORT_INTERVAL, Scheduler, WorkerResult},
+    sharded_scheduler::{ShardedScheduler, shard_share},
+    sink::{RowWriterTask, StepSinks},
+    summary::{LatencySummary, StepSummary},
+    transport::{Connector, Target, TcpSettings, TlsConfig, Traffic},
+    usage::{Usage, UsageMeter},
     worker::GrpcWorker,
 };
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use tokio::runtime::Handle;
+use hdrhistogram::Histogram;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use time::OffsetDateTime;
+use tokio::{runtime::Builder, time::Instant};
+use tonic::transport::Endpoint;
 
+mod balancer;
+mod ci;
 mod cli;
+mod compare;
+mod connection;
+mod dashboard;
+mod downtime;
 pub(crate) mod error;
+mod events;
+mod export;
+mod health;
+mod html_report;
+mod manifest;
+mod metadata;
+mod metrics;
+mod otlp;
+mod phases;
+mod records;
 mod report;
 mod sample_requests;
 mod scheduler;
+mod sharded_scheduler;
+mod sink;
+mod slo;
+mod summary;
+#[cfg(test)]
+mod test_server;
+mod transport;
+mod usage;
 mod worker;
 
 use error::Result;
@@ -22,40 +71,370 @@ use error::Result;
 // If we managed to send 95% of the expected requests, we consider the test successful.
 const ACCEPTABLE_PERCENTAGE_OF_TARGET_THROUGHPUT: u64 = 95;
 
-pub(crate) async fn run() -> Result<()> {
+pub(crate) fn run() -> Result<()> {
     let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Records(args)) => return records::print(args),
+        Some(Command::Report(args)) => return html_report::write(args),
+        Some(Command::Compare(args)) => return compare::compare(args),
+        None => {}
+    }
+
+    let threads = cli.threads.unwrap_or_else(default_thread_count);
+
+    let mut builder = match cli.runtime {
+        RuntimeMode::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+            let _ = builder.worker_threads(threads.get());
+            builder
+        }
+        // The shards own their runtimes, this one only drives the test plan.
+        RuntimeMode::ThreadPerCore => Builder::new_current_thread(),
+    };
 
-    let concurrency = Handle::current().metrics().num_workers();
-    let mut workers = vec![];
+    builder
+        .enable_all()
+        .build()
+        .map_err(Error::FailedToBuildRuntime)?
+        .block_on(run_with_cli(cli, threads))
+}
 
-    for _ in 0..concurrency {
-        let channel = tonic::transport::Endpoint::new(cli.uri.clone())
-            .map_err(Error::FailedToCreateEndpoint)?
-            .connect()
+fn default_thread_count() -> NonZeroUsize {
+    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
+
+async fn run_with_cli(cli: Cli, threads: NonZeroUsize) -> Result<()> {
+    let started_at = OffsetDateTime::now_utc();
+    let throughputs = get_all_throughputs(&cli)?;
+    let connections = cli.connections.unwrap_or(threads);
+    let parent_directory = match &cli.result_directory {
+        Some(dir) => dir.clone(),
+        None => env::current_dir().expect("current directory can be read"),
+    };
+    let (run_id, result_directory) =
+        manifest::create_run_directory(&parent_directory, &manifest::run_id(started_at))
             .await
-            .map_err(Error::FailedToConnectToEndpoint)?;
+            .map_err(Error::WriteReport)?;
+    let result_directory = result_directory.as_path();
 
-        let worker = GrpcWorker::new(&channel);
-        workers.push(worker);
+    let metadata = report::Metadata {
+        uris: &cli.uris,
+        test_duration_s: cli.test_duration.as_secs(),
+        throughputs: &throughputs,
+        runtime: cli.runtime,
+        threads,
+        connections,
+        scheduler_tasks: match cli.runtime {
+            RuntimeMode::MultiThread => Some(cli.scheduler_tasks.unwrap_or(threads)),
+            RuntimeMode::ThreadPerCore => None,
+        },
+        histogram_precision: cli.histogram_precision,
+        raw_samples: cli.raw_samples,
+        stream_records: cli.stream_records,
+        output_format: cli.output_format,
+        balance: cli.balance,
+        transport: &cli.transport,
+        send_compression: cli.send_compression,
+        accept_compression: &cli.accept_compression,
+    };
+    let mut manifest = Manifest::new(run_id, started_at, env::args(), &metadata);
+
+    // NOTE: Started first and finished last, so that every failure of the run is an event.
+    let events = cli
+        .events
+        .map(|EventFormat::Ndjson| Events::start(&manifest.run_id, result_directory, &metadata));
+    let result = run_in_directory(
+        &cli,
+        threads,
+        &throughputs,
+        events.as_ref().map(Events::feed),
+        &mut manifest,
+        result_directory,
+    )
+    .await;
+    if let Some(events) = events {
+        // NOTE: The outcome is only recorded once the steps started.
+        if manifest.outcome == RunOutcome::Running {
+            manifest.finish(&result);
+        }
+        events.finish(manifest.outcome, &result).await;
     }
 
-    let mut scheduler = Scheduler::new(&workers, REPORT_INTERVAL)?;
+    result
+}
+
+/// Connects to the servers, runs the steps and writes their reports into the
+/// directory of the run.
+async fn run_in_directory(
+    cli: &Cli,
+    threads: NonZeroUsize,
+    throughputs: &[u64],
+    events: Option<EventFeed>,
+    manifest: &mut Manifest<'_>,
+    result_directory: &Path,
+) -> Result<()> {
+    let downtime = cli.reconnect.then(|| DowntimeTracker::new(cli.uris.len()));
+    let interceptor = MetadataInterceptor::load(&cli.metadata)?;
+    let tls = cli
+        .uses_tls()
+        .then(|| TlsConfig::load(&cli.tls))
+        .transpose()?;
+    let endpoints = cli
+        .uris
+        .iter()
+        .map(|uri| {
+            let (target, uri) = Target::parse(uri, tls.is_some());
+            let endpoint = transport::endpoint(&uri, &cli.transport)?;
+            let connector = Connector::new(target, TcpSettings::from(&cli.transport), tls.clone());
+            Ok((endpoint, connector))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    let result_directory = match &cli.result_directory {
-        Some(dir) => Path::new(dir),
-        None => &env::current_dir().expect("current directory can be read"),
+    let health = cli
+        .health_check()
+        .map(|settings| HealthWatcher::spawn(&cli.uris, &endpoints, &interceptor, &settings));
+    if let Some(health) = &health {
+        wait_until_serving(health, result_directory).await?;
+    }
+
+    let mut observers = Observers {
+        downtime: downtime.clone(),
+        traffic: endpoints
+            .iter()
+            .map(|(_, connector)| connector.traffic().clone())
+            .collect(),
+        health,
+        metrics: None,
+        otlp: None,
+        dashboard: None,
+        events,
+    };
+    observers.start_metrics(cli, &manifest.run_id).await?;
+    let mut load_generator =
+        LoadGenerator::connect(cli, threads, endpoints, downtime, interceptor).await?;
+
+    report::write_metadata(result_directory, manifest.config)
+        .await
+        .map_err(Error::WriteReport)?;
+    report::write_manifest(result_directory, manifest)
+        .await
+        .map_err(Error::WriteReport)?;
+
+    observers.dashboard = match cli.progress {
+        ProgressDisplay::Bars => None,
+        ProgressDisplay::Dashboard => Dashboard::start(cli.test_duration)?,
     };
+    let result = load_test(
+        cli,
+        throughputs,
+        &mut load_generator,
+        &observers,
+        manifest,
+        result_directory,
+    )
+    .await;
+
+    finish_run(result, observers, manifest, result_directory).await
+}
+
+/// Gives the terminal back, prints the summaries of the steps, and writes the
+/// outcome of the run to the manifest and the CI reports.
+async fn finish_run(
+    result: Result<()>,
+    mut observers: Observers,
+    manifest: &mut Manifest<'_>,
+    result_directory: &Path,
+) -> Result<()> {
+    let drawn = observers.dashboard.take().map_or(Ok(()), Dashboard::stop);
+    // NOTE: The steps that completed are still worth comparing, e.g. up to saturation.
+    if observers.events.is_none() {
+        print_summaries(manifest.steps());
+    }
+    manifest.finish(&result);
+    let written = async {
+        report::write_manifest(result_directory, manifest).await?;
+        report::write_ci_reports(result_directory, manifest).await
+    }
+    .await
+    .map_err(Error::WriteReport);
+    // NOTE: The telemetry is best effort, its failures do not change the outcome of the run.
+    if let Some(otlp) = observers.otlp {
+        let dropped = otlp.dropped_spans();
+        if dropped > 0 {
+            eprintln!("Warning: dropped {dropped} spans, the OTLP collector was too slow");
+        }
+        if let Err(e) = otlp.shutdown().await {
+            eprintln!("Warning: {e}");
+        }
+    }
+
+    result.and(drawn).and(written)
+}
+
+/// What is observed during the run, besides the latency of the streams.
+#[derive(Debug)]
+struct Observers {
+    downtime: Option<DowntimeTracker>,
+    /// The traffic of each endpoint.
+    traffic: Vec<Traffic>,
+    health: Option<HealthWatcher>,
+    metrics: Option<Metrics>,
+    otlp: Option<Exporter>,
+    /// Shown in place of the progress bars.
+    dashboard: Option<Dashboard>,
+    /// Written in place of the progress bars.
+    events: Option<EventFeed>,
+}
+
+impl Observers {
+    /// Serves and exports the live metrics, if requested.
+    async fn start_metrics(&mut self, cli: &Cli, run_id: &str) -> Result<()> {
+        if cli.metrics_listen.is_none() && cli.otlp.endpoint.is_none() {
+            return Ok(());
+        }
+
+        let metrics = Metrics::default();
+        if let Some(address) = cli.metrics_listen {
+            metrics.serve(address).await?;
+        }
+        self.otlp = cli
+            .otlp()
+            .map(|settings| Exporter::start(&settings, metrics.clone(), run_id, &cli.uris));
+        self.metrics = Some(metrics);
+
+        Ok(())
+    }
+}
+
+/// Waits until the servers are serving, and records the health transitions so far.
+async fn wait_until_serving(health: &HealthWatcher, result_directory: &Path) -> Result<()> {
+    let result = health.wait_until_serving().await;
+
+    report::write_health(result_directory, &health.transitions())
+        .await
+        .map_err(Error::WriteReport)?;
+
+    result
+}
 
-    load_test(&cli, &mut scheduler, result_directory).await
+/// The scheduler driving the load test, depending on the runtime mode.
+#[derive(Debug)]
+enum LoadGenerator {
+    MultiThread(Scheduler<GrpcWorker>),
+    ThreadPerCore(ShardedScheduler<StepReporter>),
+}
+
+impl LoadGenerator {
+    /// Connects to the servers and creates the scheduler of the runtime mode.
+    async fn connect(
+        cli: &Cli,
+        threads: NonZeroUsize,
+        endpoints: Vec<(Endpoint, Connector)>,
+        downtime: Option<DowntimeTracker>,
+        interceptor: MetadataInterceptor,
+    ) -> Result<Self> {
+        let connections = cli.connections.unwrap_or(threads);
+        let churn = cli.churn();
+        let wait_for_ready = cli.wait_for_ready();
+        let compression = cli.compression();
+        let policy = cli.balance;
+        let traces = cli.otlp().as_ref().and_then(OtlpSettings::sampler);
+
+        let generator = match cli.runtime {
+            RuntimeMode::MultiThread => {
+                let endpoints =
+                    Balancer::connect(&endpoints, connections, churn, wait_for_ready, policy)
+                        .await?;
+
+                let worker = GrpcWorker::new(
+                    &endpoints,
+                    interceptor,
+                    compression,
+                    downtime.as_ref(),
+                    traces,
+                );
+                let workers = vec![worker; cli.scheduler_tasks.unwrap_or(threads).get()];
+
+                Self::MultiThread(Scheduler::new(&workers, REPORT_INTERVAL, cli.recording())?)
+            }
+            RuntimeMode::ThreadPerCore => {
+                if connections < threads {
+                    return Err(Error::FewerConnectionsThanThreads(
+                        connections.get(),
+                        threads.get(),
+                    ));
+                }
+
+                let scheduler = ShardedScheduler::new(
+                    threads,
+                    REPORT_INTERVAL,
+                    cli.recording(),
+                    move |shard| {
+                        let connections_per_shard =
+                            NonZeroUsize::new(shard_share(connections.get(), threads, shard))
+                                .expect("every shard has a connection");
+                        let endpoints = endpoints.clone();
+                        let downtime = downtime.clone();
+                        let interceptor = interceptor.clone();
+                        let compression = compression.clone();
+                        async move {
+                            let endpoints = Balancer::connect(
+                                &endpoints,
+                                connections_per_shard,
+                                churn,
+                                wait_for_ready,
+                                policy,
+                            )
+                            .await?;
+                            Ok(GrpcWorker::new(
+                                &endpoints,
+                                interceptor,
+                                compression,
+                                downtime.as_ref(),
+                                traces,
+                            ))
+                        }
+                    },
+                )
+                .await?;
+
+                Self::ThreadPerCore(scheduler)
+            }
+        };
+
+        Ok(generator)
+    }
+    async fn run(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+        reporter: &StepReporter,
+        sinks: &StepSinks,
+    ) -> Result<Vec<WorkerResult>> {
+        match self {
+            LoadGenerator::MultiThread(scheduler) => {
+                scheduler.run(interval, timeout, reporter, sinks).await
+            }
+            LoadGenerator::ThreadPerCore(scheduler) => {
+                scheduler.run(interval, timeout, reporter, sinks).await
+            }
+        }
+    }
 }
 
 async fn load_test(
     cli: &Cli,
-    scheduler: &mut Scheduler<GrpcWorker>,
+    throughputs: &[u64],
+    load_generator: &mut LoadGenerator,
+    observers: &Observers,
+    manifest: &mut Manifest<'_>,
     result_directory: &Path,
 ) -> Result<()> {
-    let throughputs = get_all_throughputs(cli)?;
-    let multi_progress = MultiProgress::new();
+    let multi_progress = if observers.dashboard.is_some() || observers.events.is_some() {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
     let progress_style = ProgressStyle::with_template(
         "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
     )
@@ -63,7 +442,7 @@ async fn load_test(
     .progress_chars("##-");
 
     let mut progress_bars = vec![];
-    for throughput in &throughputs {
+    for throughput in throughputs {
         let estimated_request_count = cli.test_duration.as_secs() * *throughput;
 
         let pb = multi_progress.add(ProgressBar::new(estimated_request_count));
@@ -72,12 +451,102 @@ async fn load_test(
         progress_bars.push(pb);
     }
 
-    for (throughput, pb) in throughputs.into_iter().zip(progress_bars) {
-        run_with_throughput(&pb, cli, throughput, scheduler, result_directory).await?;
+    for (&throughput, pb) in throughputs.iter().zip(progress_bars) {
+        if let Some(health) = &observers.health {
+            wait_until_serving(health, result_directory).await?;
+        }
+
+        let started_at = OffsetDateTime::now_utc();
+        let (summary, dropped_rows) = run_with_throughput(
+            &pb,
+            cli,
+            throughput,
+            load_generator,
+            observers,
+            result_directory,
+        )
+        .await?;
+
+        let saturation = (summary.percent_of_target_throughput
+            < ACCEPTABLE_PERCENTAGE_OF_TARGET_THROUGHPUT)
+            .then(|| {
+                Error::CouldNotReachTargetThroughput(
+                    throughput,
+                    summary.achieved_throughput,
+                    summary.percent_of_target_throughput,
+                )
+            });
+        let step = Step {
+            started_at,
+            outcome: match saturation {
+                Some(_) => StepOutcome::Saturated,
+                None => StepOutcome::Completed,
+            },
+            slo: cli
+                .slos
+                .iter()
+                .filter_map(|slo| slo.check(&summary))
+                .collect(),
+            summary,
+            files: report::step_files(result_directory, throughput)
+                .await
+                .map_err(Error::WriteReport)?,
+            dropped_rows,
+        };
+        if let Some(events) = &observers.events {
+            events.step_finished(&step);
+        }
+        manifest.push_step(step);
+        report::write_manifest(result_directory, manifest)
+            .await
+            .map_err(Error::WriteReport)?;
+        if let Some(format) = cli.output_format.table_format() {
+            let summaries = manifest.steps().iter().map(|step| step.summary.clone());
+            report::write_steps(result_directory, format, summaries.collect())
+                .await
+                .map_err(Error::WriteReport)?;
+        }
+
+        if let Some(e) = saturation {
+            return Err(e);
+        }
         pb.finish();
     }
 
-    Ok(())
+    if let Some(health) = &observers.health {
+        report::write_health(result_directory, &health.transitions())
+            .await
+            .map_err(Error::WriteReport)?;
+    }
+
+    let breaches = manifest
+        .steps()
+        .iter()
+        .flat_map(|step| &step.slo)
+        .filter(|check| !check.passed)
+        .count();
+    match breaches {
+        0 => Ok(()),
+        breaches => Err(Error::SloBreached(breaches)),
+    }
+}
+
+/// Prints the table comparing the steps of the run, and the SLOs they breached.
+fn print_summaries(steps: &[Step]) {
+    if !steps.is_empty() {
+        println!(
+            "\n{}",
+            summary::table(steps.iter().map(|step| &step.summary))
+        );
+    }
+    for step in steps {
+        for check in step.slo.iter().filter(|check| !check.passed) {
+            println!(
+                "SLO {} breached at {} req/s: {} {}",
+                check.slo, step.summary.target_throughput, check.value, check.unit
+            );
+        }
+    }
 }
 
 fn get_all_throughputs(cli: &Cli) -> Result<Vec<u64>> {
@@ -105,47 +574,276 @@ async fn run_with_throughput(
     pb: &ProgressBar,
     cli: &Cli,
     target_throughput: u64,
-    scheduler: &mut Scheduler<GrpcWorker>,
+    load_generator: &mut LoadGenerator,
+    observers: &Observers,
     result_directory: &Path,
-) -> Result<()> {
+) -> Result<(StepSummary, BTreeMap<&'static str, u64>)> {
     let interval = Duration::from_secs(1)
         .checked_div(target_throughput.try_into().unwrap()) // TODO: Make target throughput u32
         .expect("target throughput must not be 0");
     let timeout = cli.test_duration;
 
-    let target_request_count = cli.test_duration.as_secs() * target_throughput;
+    let start = Instant::now();
+    let started_at = SystemTime::now();
+    let usage_meter = UsageMeter::start(&observers.traffic);
+    let reporter = StepReporter {
+        progress_bar: pb.clone(),
+        dashboard: observers.dashboard.as_ref().map(Dashboard::feed),
+        events: observers.events.clone(),
+        metrics: observers.metrics.clone(),
+        spans: observers.otlp.as_ref().map(Exporter::spans),
+    };
+    reporter.start_step(target_throughput);
+    let (sinks, writers) = report::step_sinks(
+        result_directory,
+        target_throughput,
+        cli.output_format.table_format(),
+        cli.raw_samples,
+        cli.stream_records,
+    );
+    let results = load_generator
+        .run(interval, timeout, &reporter, &sinks)
+        .await?;
+    drop(sinks);
+    let usage = usage_meter.finish();
 
-    let results = scheduler.run(interval, timeout, pb).await?;
+    let mut result = WorkerResult::merge(results, cli.recording());
+    result
+        .endpoints
+        .resize_with(cli.uris.len(), || EndpointStats::new(cli.recording()));
 
-    let request_sent = results.iter().map(|r| r.request_sent).sum::<u64>();
-    let durations = results
-        .into_iter()
-        .flat_map(|r| r.durations)
-        .collect::<Vec<_>>();
+    let summary = StepSummary {
+        closed_early_streams: result.closed_early_streams,
+        failures: summary::failures_by_code(&result.failures),
+        ..StepSummary::new(
+            target_throughput,
+            cli.test_duration,
+            result.request_sent,
+            result.failed_streams,
+            result.in_flight_peak,
+            &result.histogram,
+            &result.phases,
+        )
+    };
+    reporter.finish_step(&summary);
 
-    let actual_throughput = request_sent / cli.test_duration.as_secs();
-    let percent_of_target_throughput = 100 * request_sent / target_request_count;
+    let dropped_rows = report_stream_durations(
+        result_directory,
+        target_throughput,
+        started_at,
+        start.elapsed(),
+        &result,
+        writers,
+    )
+    .await?;
 
-    if percent_of_target_throughput < ACCEPTABLE_PERCENTAGE_OF_TARGET_THROUGHPUT {
-        return Err(Error::CouldNotReachTargetThroughput(
-            target_throughput,
-            actual_throughput,
-            percent_of_target_throughput,
-        ));
+    let connections_message = report_connections(
+        result_directory,
+        target_throughput,
+        started_at,
+        start.elapsed(),
+        &result,
+    )
+    .await?;
+
+    let usage_message = report_usage(
+        result_directory,
+        target_throughput,
+        &usage,
+        result.request_sent,
+    )
+    .await?;
+
+    let endpoints_message = report_endpoints(
+        result_directory,
+        &cli.uris,
+        target_throughput,
+        &result.endpoints,
+    )
+    .await?;
+
+    let downtime_message = match &observers.downtime {
+        Some(downtime) => {
+            report_downtime(result_directory, target_throughput, downtime, start).await?
+        }
+        None => String::new(),
+    };
+
+    pb.finish_with_message(format!(
+        "{summary}{usage_message}{downtime_message}{connections_message}{endpoints_message}",
+    ));
+
+    Ok((summary, dropped_rows))
+}
+
+/// Writes the latency of the streams of a step and of their phases, and waits
+/// until the raw samples and stream records kept by the loops are written.
+///
+/// Returns the rows the loops dropped, by kind of file, if any.
+async fn report_stream_durations(
+    result_directory: &Path,
+    target_throughput: u64,
+    started_at: SystemTime,
+    elapsed: Duration,
+    result: &WorkerResult,
+    writers: Vec<(&'static str, RowWriterTask)>,
+) -> Result<BTreeMap<&'static str, u64>> {
+    report::write_histogram(
+        result_directory,
+        target_throughput,
+        started_at,
+        elapsed,
+        &result.histogram,
+    )
+    .await
+    .map_err(Error::WriteReport)?;
+    report::write_phases(
+        result_directory,
+        target_throughput,
+        started_at,
+        elapsed,
+        &result.phases,
+    )
+    .await
+    .map_err(Error::WriteReport)?;
+
+    let mut dropped_rows = BTreeMap::new();
+    for (kind, writer) in writers {
+        // NOTE: The loops are done, so no more rows are dropped.
+        let dropped = writer.dropped_rows();
+        writer.finish().await.map_err(Error::WriteReport)?;
+        if dropped > 0 {
+            eprintln!(
+                "Warning: dropped {dropped} rows of the {target_throughput} req/s step, they could not be written fast enough"
+            );
+            drop(dropped_rows.insert(kind, dropped));
+        }
     }
 
-    report::write(result_directory, target_throughput, &durations)
+    Ok(dropped_rows)
+}
+
+/// Writes the resources used by a step, and describes them per stream for the
+/// progress bar.
+async fn report_usage(
+    result_directory: &Path,
+    target_throughput: u64,
+    usage: &Usage,
+    request_sent: u64,
+) -> Result<String> {
+    report::write_usage(result_directory, target_throughput, usage)
         .await
         .map_err(Error::WriteReport)?;
 
-    let avg_duration =
-        durations.iter().sum::<Duration>() / u32::try_from(durations.len()).unwrap_or(1);
-    let min_duration = durations.iter().min().unwrap();
-    let max_duration = durations.iter().max().unwrap();
+    Ok(format!(
+        ", sent: {} B/stream, received: {} B/stream{}",
+        usage.bytes_sent / request_sent.max(1),
+        usage.bytes_received / request_sent.max(1),
+        usage
+            .cpu_time
+            .map(|cpu_time| format!(", CPU: {cpu_time:?}"))
+            .unwrap_or_default()
+    ))
+}
 
-    pb.finish_with_message(format!(
-        "{target_throughput} req/s: {percent_of_target_throughput}% of planned requests sent, avg: {avg_duration:?}, min: {min_duration:?}, max: {max_duration:?}",
-    ));
+/// Writes the windows during which the server was down since `start`, and describes
+/// them for the progress bar.
+async fn report_downtime(
+    result_directory: &Path,
+    target_throughput: u64,
+    downtime: &DowntimeTracker,
+    start: Instant,
+) -> Result<String> {
+    let downtimes = downtime.take(start);
+    report::write_downtime(result_directory, target_throughput, &downtimes)
+        .await
+        .map_err(Error::WriteReport)?;
+
+    let total = downtimes.iter().map(|d| d.duration).sum::<Duration>();
+    Ok(format!(
+        ", downtime: {total:?} in {} windows",
+        downtimes.len()
+    ))
+}
+
+/// Writes the latency of the connections opened during a step and of their TLS
+/// handshakes, if any, and describes them for the progress bar.
+async fn report_connections(
+    result_directory: &Path,
+    target_throughput: u64,
+    started_at: SystemTime,
+    elapsed: Duration,
+    result: &WorkerResult,
+) -> Result<String> {
+    if result.connect.is_empty() {
+        return Ok(String::new());
+    }
+
+    report::write_connections(
+        result_directory,
+        target_throughput,
+        started_at,
+        elapsed,
+        &result.connect,
+        &result.tls_handshake,
+    )
+    .await
+    .map_err(Error::WriteReport)?;
+
+    let mut message = latency_message("connect", "connections", &result.connect);
+    if !result.tls_handshake.is_empty() {
+        message += &latency_message("TLS handshake", "handshakes", &result.tls_handshake);
+    }
+
+    Ok(message)
+}
+
+/// Describes latencies measured separately from the streams for the progress bar,
+/// like those of the streams.
+fn latency_message(label: &str, unit: &str, histogram: &Histogram<u64>) -> String {
+    let latency = LatencySummary::new(histogram);
+    format!(
+        "\n  {label}: {} {unit}, avg: {:?}, p50: {:?}, p99: {:?}, max: {:?}",
+        latency.streams, latency.mean, latency.percentiles[0], latency.percentiles[2], latency.max,
+    )
+}
+
+/// Writes the breakdown of the streams by endpoint, when there are several, and
+/// describes it for the progress bar.
+async fn report_endpoints(
+    result_directory: &Path,
+    uris: &[String],
+    target_throughput: u64,
+    endpoints: &[EndpointStats],
+) -> Result<String> {
+    if endpoints.len() < 2 {
+        return Ok(String::new());
+    }
+
+    let reports = uris
+        .iter()
+        .zip(endpoints)
+        .map(|(uri, stats)| report::EndpointReport::new(uri, stats))
+        .collect::<Vec<_>>();
+    report::write_endpoints(result_directory, target_throughput, &reports)
+        .await
+        .map_err(Error::WriteReport)?;
+
+    let mut message = String::new();
+    for report in &reports {
+        let latency = &report.latency;
+        let _ = write!(
+            message,
+            "\n  {}: {} streams, {} failed, avg: {:?}, p50: {:?}, p99: {:?}, max: {:?}",
+            report.uri,
+            latency.streams,
+            report.failed_streams,
+            latency.mean,
+            latency.percentiles[0],
+            latency.percentiles[2],
+            latency.max,
+        );
+    }
 
-    Ok(())
+    Ok(message)
 }
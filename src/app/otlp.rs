@@ -0,0 +1,836 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use opentelemetry_proto::tonic::{
+    collector::{
+        metrics::v1::{
+            ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+            metrics_service_client::MetricsServiceClient,
+        },
+        trace::v1::{ExportTraceServiceRequest, trace_service_client::TraceServiceClient},
+    },
+    common::v1::{AnyValue, InstrumentationScope, KeyValue, any_value},
+    metrics::v1::{
+        AggregationTemporality, Gauge, Histogram, HistogramDataPoint, Metric, NumberDataPoint,
+        ResourceMetrics, ScopeMetrics, Sum, metric, number_data_point,
+    },
+    resource::v1::Resource,
+    trace::v1::{ResourceSpans, ScopeSpans, Span, Status, span, status},
+};
+use tokio::{
+    select,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tonic::{Response, transport::Endpoint};
+
+use crate::app::{
+    error::{Error, Result},
+    metrics::{BUCKET_BOUNDS, Buckets, Metrics, Snapshot},
+    records::{self, StreamRecord},
+    worker::StreamOutcome,
+};
+
+/// Name of the instrumentation scope and prefix of the names of the metrics.
+const SCOPE: &str = "ext_proc_load_tester";
+
+/// How long to wait for the collector to accept an export.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of spans sent in a single export, to stay below the default
+/// message size limit of the collectors.
+const MAX_SPANS_PER_EXPORT: usize = 1000;
+
+/// The number of batches of spans waiting to be exported, beyond which the spans
+/// are dropped rather than held in memory while the collector is slow.
+const PENDING_SPAN_BATCHES: usize = 64;
+
+/// The export of metrics and stream spans to an OpenTelemetry collector.
+#[derive(Debug, Clone)]
+pub(crate) struct OtlpSettings {
+    /// The OTLP/gRPC endpoint of the collector.
+    pub(crate) endpoint: Endpoint,
+    /// The interval between two exports of the metrics.
+    pub(crate) interval: Duration,
+    /// The fraction of the streams traced, from 0 to 1.
+    pub(crate) trace_ratio: f64,
+}
+
+impl OtlpSettings {
+    /// The sampler of the traced streams, if any stream is traced.
+    pub(crate) fn sampler(&self) -> Option<TraceSampler> {
+        (self.trace_ratio > 0.0).then_some(TraceSampler {
+            ratio: self.trace_ratio,
+        })
+    }
+}
+
+/// Picks the streams that are traced.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TraceSampler {
+    ratio: f64,
+}
+
+impl TraceSampler {
+    /// The context of a new trace, if the stream is traced.
+    pub(crate) fn sample(self) -> Option<TraceContext> {
+        (fastrand::f64() < self.ratio).then(|| TraceContext {
+            trace_id: fastrand::u128(1..),
+            span_id: fastrand::u64(1..),
+        })
+    }
+}
+
+/// The identifiers of the span of a traced stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TraceContext {
+    pub(crate) trace_id: u128,
+    pub(crate) span_id: u64,
+}
+
+impl TraceContext {
+    /// The W3C `traceparent` header of the stream, so that the spans of the server
+    /// are children of the span of the stream.
+    pub(crate) fn traceparent(self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+/// A traced stream, exported as a span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StreamSpan {
+    pub(crate) context: TraceContext,
+    pub(crate) record: StreamRecord,
+}
+
+/// Exports the metrics periodically and the spans as they are reported, each in
+/// a background task, until it is shut down.
+///
+/// Failed exports do not interrupt the run, they are reported when shutting down.
+#[derive(Debug)]
+pub(crate) struct Exporter {
+    spans: SpanSender,
+    shutdown: CancellationToken,
+    metrics_task: JoinHandle<ExportFailures>,
+    spans_task: JoinHandle<ExportFailures>,
+}
+
+/// Sends the spans of the traced streams to the `Exporter`.
+#[derive(Debug, Clone)]
+pub(crate) struct SpanSender {
+    spans: mpsc::Sender<Vec<StreamSpan>>,
+    /// The spans dropped because too many were waiting to be exported.
+    dropped: Arc<AtomicU64>,
+}
+
+impl SpanSender {
+    pub(crate) fn send(&self, spans: Vec<StreamSpan>) {
+        // NOTE: The spans reported after the shutdown are dropped without being counted.
+        if let Err(TrySendError::Full(spans)) = self.spans.try_send(spans) {
+            let dropped = u64::try_from(spans.len()).unwrap_or(u64::MAX);
+            let _ = self.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Exporter {
+    /// Starts exporting the metrics and the spans of the run.
+    ///
+    /// The collector is connected to lazily, so that it may start after the run.
+    pub(crate) fn start(
+        settings: &OtlpSettings,
+        metrics: Metrics,
+        run_id: &str,
+        uris: &[String],
+    ) -> Self {
+        let channel = settings
+            .endpoint
+            .clone()
+            .timeout(EXPORT_TIMEOUT)
+            .connect_lazy();
+        let client = Client {
+            metrics: MetricsServiceClient::new(channel.clone()),
+            traces: TraceServiceClient::new(channel),
+            resource: resource(run_id),
+            uris: uris.to_vec(),
+            started_at_ns: now_ns(),
+        };
+
+        let (sender, receiver) = mpsc::channel(PENDING_SPAN_BATCHES);
+        let spans = SpanSender {
+            spans: sender,
+            dropped: Arc::default(),
+        };
+        let shutdown = CancellationToken::new();
+        let metrics_task = tokio::spawn(client.clone().run_metrics(
+            settings.interval,
+            metrics,
+            spans.dropped.clone(),
+            shutdown.clone(),
+        ));
+        // NOTE: On its own task, so that a slow export of spans does not delay the metrics.
+        let spans_task = tokio::spawn(client.run_spans(receiver, shutdown.clone()));
+
+        Self {
+            spans,
+            shutdown,
+            metrics_task,
+            spans_task,
+        }
+    }
+
+    pub(crate) fn spans(&self) -> SpanSender {
+        self.spans.clone()
+    }
+
+    /// The spans dropped so far because too many were waiting to be exported.
+    pub(crate) fn dropped_spans(&self) -> u64 {
+        self.spans.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Exports what was reported since the previous exports, then stops.
+    pub(crate) async fn shutdown(self) -> Result<()> {
+        self.shutdown.cancel();
+        let metrics = self.metrics_task.await;
+        let spans = self.spans_task.await;
+        let mut failures = metrics.expect("the OTLP exporter does not panic");
+        failures.merge(spans.expect("the OTLP exporter does not panic"));
+        failures.into_result()
+    }
+}
+
+/// The exports that failed.
+#[derive(Debug, Default)]
+struct ExportFailures {
+    count: u64,
+    last_error: Option<tonic::Status>,
+}
+
+impl ExportFailures {
+    fn check<T>(&mut self, result: Result<Response<T>, tonic::Status>) {
+        if let Err(status) = result {
+            self.count += 1;
+            self.last_error = Some(status);
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.count += other.count;
+        self.last_error = other.last_error.or(self.last_error.take());
+    }
+
+    fn into_result(self) -> Result<()> {
+        match self.last_error {
+            None => Ok(()),
+            Some(status) => Err(Error::FailedToExportTelemetry(self.count, Box::new(status))),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Client {
+    metrics: MetricsServiceClient<tonic::transport::Channel>,
+    traces: TraceServiceClient<tonic::transport::Channel>,
+    resource: Resource,
+    /// The URI of each endpoint.
+    uris: Vec<String>,
+    /// Start of the cumulative metrics.
+    started_at_ns: u64,
+}
+
+impl Client {
+    async fn run_metrics(
+        mut self,
+        interval: Duration,
+        metrics: Metrics,
+        dropped_spans: Arc<AtomicU64>,
+        shutdown: CancellationToken,
+    ) -> ExportFailures {
+        let mut failures = ExportFailures::default();
+        let mut interval = time::interval_at(Instant::now() + interval, interval);
+        loop {
+            select! {
+                _ = interval.tick() => {}
+                () = shutdown.cancelled() => break,
+            }
+            let dropped_spans = dropped_spans.load(Ordering::Relaxed);
+            failures.check(
+                self.export_metrics(&metrics.snapshot(), dropped_spans)
+                    .await,
+            );
+        }
+
+        let dropped_spans = dropped_spans.load(Ordering::Relaxed);
+        failures.check(
+            self.export_metrics(&metrics.snapshot(), dropped_spans)
+                .await,
+        );
+        failures
+    }
+
+    async fn run_spans(
+        mut self,
+        mut spans: mpsc::Receiver<Vec<StreamSpan>>,
+        shutdown: CancellationToken,
+    ) -> ExportFailures {
+        let mut failures = ExportFailures::default();
+        loop {
+            select! {
+                Some(batch) = spans.recv() => {
+                    self.export_spans(batch, &mut spans, &mut failures).await;
+                }
+                () = shutdown.cancelled() => break,
+            }
+        }
+
+        self.export_spans(Vec::new(), &mut spans, &mut failures)
+            .await;
+        failures
+    }
+
+    async fn export_metrics(
+        &mut self,
+        snapshot: &Snapshot,
+        dropped_spans: u64,
+    ) -> Result<Response<ExportMetricsServiceResponse>, tonic::Status> {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(scope()),
+                    metrics: self.metrics(snapshot, dropped_spans, now_ns()),
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        self.metrics.export(request).await
+    }
+
+    /// Exports `batch` and the spans already pending behind it.
+    async fn export_spans(
+        &mut self,
+        mut batch: Vec<StreamSpan>,
+        pending: &mut mpsc::Receiver<Vec<StreamSpan>>,
+        failures: &mut ExportFailures,
+    ) {
+        while let Ok(spans) = pending.try_recv() {
+            batch.extend(spans);
+        }
+
+        for chunk in batch.chunks(MAX_SPANS_PER_EXPORT) {
+            let request = ExportTraceServiceRequest {
+                resource_spans: vec![ResourceSpans {
+                    resource: Some(self.resource.clone()),
+                    scope_spans: vec![ScopeSpans {
+                        scope: Some(scope()),
+                        spans: chunk.iter().map(|span| self.span(span)).collect(),
+                        schema_url: String::new(),
+                    }],
+                    schema_url: String::new(),
+                }],
+            };
+            failures.check(self.traces.export(request).await);
+        }
+    }
+
+    fn metrics(&self, snapshot: &Snapshot, dropped_spans: u64, now_ns: u64) -> Vec<Metric> {
+        let point = |attributes, value| NumberDataPoint {
+            attributes,
+            start_time_unix_nano: self.started_at_ns,
+            time_unix_nano: now_ns,
+            value: Some(number_data_point::Value::AsInt(value)),
+            ..NumberDataPoint::default()
+        };
+        let counter = |data_points| {
+            metric::Data::Sum(Sum {
+                data_points,
+                aggregation_temporality: i32::from(AggregationTemporality::Cumulative),
+                is_monotonic: true,
+            })
+        };
+
+        vec![
+            Metric {
+                name: format!("{SCOPE}.target_rate"),
+                description: "Target rate of the current step.".to_owned(),
+                unit: "{stream}/s".to_owned(),
+                data: Some(metric::Data::Gauge(Gauge {
+                    data_points: vec![point(vec![], saturating_i64(snapshot.target_rate))],
+                })),
+                ..Metric::default()
+            },
+            Metric {
+                name: format!("{SCOPE}.streams.sent"),
+                description: "Streams started.".to_owned(),
+                unit: "{stream}".to_owned(),
+                data: Some(counter(vec![point(vec![], saturating_i64(snapshot.sent))])),
+                ..Metric::default()
+            },
+            Metric {
+                name: format!("{SCOPE}.streams"),
+                description: "Streams ended, by outcome.".to_owned(),
+                unit: "{stream}".to_owned(),
+                data: Some(counter(
+                    snapshot
+                        .outcomes()
+                        .into_iter()
+                        .map(|(outcome, count)| {
+                            let outcome = string_attribute("outcome", outcome.name());
+                            point(vec![outcome], saturating_i64(count))
+                        })
+                        .collect(),
+                )),
+                ..Metric::default()
+            },
+            Metric {
+                name: format!("{SCOPE}.streams.in_flight"),
+                description: "Streams in flight.".to_owned(),
+                unit: "{stream}".to_owned(),
+                data: Some(metric::Data::Gauge(Gauge {
+                    data_points: vec![point(vec![], snapshot.in_flight)],
+                })),
+                ..Metric::default()
+            },
+            Metric {
+                name: format!("{SCOPE}.spans.dropped"),
+                description: "Spans dropped because too many were waiting to be exported."
+                    .to_owned(),
+                unit: "{span}".to_owned(),
+                data: Some(counter(vec![point(vec![], saturating_i64(dropped_spans))])),
+                ..Metric::default()
+            },
+            self.histogram(
+                "stream.duration",
+                "Latency of the streams that did not fail, excluding the connection wait.",
+                &snapshot.latency,
+                now_ns,
+            ),
+            self.histogram(
+                "scheduler.lag",
+                "Delay between the planned and the actual start of the streams.",
+                &snapshot.scheduler_lag,
+                now_ns,
+            ),
+        ]
+    }
+
+    fn histogram(&self, name: &str, description: &str, buckets: &Buckets, now_ns: u64) -> Metric {
+        Metric {
+            name: format!("{SCOPE}.{name}"),
+            description: description.to_owned(),
+            unit: "s".to_owned(),
+            data: Some(metric::Data::Histogram(Histogram {
+                data_points: vec![HistogramDataPoint {
+                    start_time_unix_nano: self.started_at_ns,
+                    time_unix_nano: now_ns,
+                    count: buckets.count(),
+                    sum: Some(buckets.sum.as_secs_f64()),
+                    bucket_counts: buckets.counts.to_vec(),
+                    explicit_bounds: BUCKET_BOUNDS.iter().map(Duration::as_secs_f64).collect(),
+                    ..HistogramDataPoint::default()
+                }],
+                aggregation_temporality: i32::from(AggregationTemporality::Cumulative),
+            })),
+            ..Metric::default()
+        }
+    }
+
+    /// The span of a stream, with an event at the end of each phase it reached.
+    fn span(&self, stream: &StreamSpan) -> Span {
+        let record = &stream.record;
+
+        let mut events = vec![];
+        let mut at = record.start_ns;
+        let phases = [
+            // NOTE: Reused connections are acquired instantly.
+            (
+                "connection",
+                Some(record.connection_wait_ns).filter(|&wait| wait > 0),
+            ),
+            ("setup", record.setup_ns),
+            ("request_headers", record.request_headers_ns),
+            ("response_headers", record.response_headers_ns),
+        ];
+        for (phase, duration_ns) in phases {
+            let Some(duration_ns) = duration_ns else {
+                continue;
+            };
+            at = at.saturating_add(duration_ns);
+            events.push(span::Event {
+                time_unix_nano: at,
+                name: phase.to_owned(),
+                attributes: vec![int_attribute("duration_ns", duration_ns)],
+                dropped_attributes_count: 0,
+            });
+        }
+
+        let endpoint = self.uris.get(record.endpoint).cloned().unwrap_or_default();
+        let lag_ns = record.start_ns.saturating_sub(record.intended_start_ns);
+        Span {
+            trace_id: stream.context.trace_id.to_be_bytes().to_vec(),
+            span_id: stream.context.span_id.to_be_bytes().to_vec(),
+            name: "envoy.service.ext_proc.v3.ExternalProcessor/Process".to_owned(),
+            kind: i32::from(span::SpanKind::Client),
+            start_time_unix_nano: record.start_ns,
+            end_time_unix_nano: record.end_ns,
+            attributes: vec![
+                string_attribute("rpc.system", "grpc"),
+                string_attribute("rpc.service", "envoy.service.ext_proc.v3.ExternalProcessor"),
+                string_attribute("rpc.method", "Process"),
+                string_attribute(&format!("{SCOPE}.endpoint"), endpoint),
+                int_attribute(&format!("{SCOPE}.connection"), record.connection),
+                int_attribute(
+                    &format!("{SCOPE}.task"),
+                    u64::try_from(record.task).unwrap_or(u64::MAX),
+                ),
+                string_attribute(&format!("{SCOPE}.outcome"), record.outcome.name()),
+                int_attribute(&format!("{SCOPE}.scheduler_lag_ns"), lag_ns),
+            ],
+            events,
+            status: (record.outcome == StreamOutcome::Failed).then(|| Status {
+                message: format!(
+                    "the stream failed with {}",
+                    record.error.as_deref().unwrap_or("Unknown")
+                ),
+                code: i32::from(status::StatusCode::Error),
+            }),
+            ..Span::default()
+        }
+    }
+}
+
+fn resource(run_id: &str) -> Resource {
+    Resource {
+        attributes: vec![
+            string_attribute("service.name", "ext-proc-load-tester"),
+            string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+            string_attribute(&format!("{SCOPE}.run_id"), run_id),
+        ],
+        ..Resource::default()
+    }
+}
+
+fn scope() -> InstrumentationScope {
+    InstrumentationScope {
+        name: SCOPE.to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        ..InstrumentationScope::default()
+    }
+}
+
+fn string_attribute(key: &str, value: impl Into<String>) -> KeyValue {
+    KeyValue {
+        key: key.to_owned(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.into())),
+        }),
+    }
+}
+
+fn int_attribute(key: &str, value: u64) -> KeyValue {
+    KeyValue {
+        key: key.to_owned(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::IntValue(saturating_i64(value))),
+        }),
+    }
+}
+
+fn saturating_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+fn now_ns() -> u64 {
+    records::nanos(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroUsize, sync::Mutex};
+
+    use indicatif::ProgressBar;
+    use opentelemetry_proto::tonic::collector::{
+        metrics::v1::metrics_service_server::{MetricsService, MetricsServiceServer},
+        trace::v1::{
+            ExportTraceServiceResponse,
+            trace_service_server::{TraceService, TraceServiceServer},
+        },
+    };
+    use tempfile::TempDir;
+    use tokio::net::{TcpListener, UnixListener};
+    use tonic::{Request, Status, transport::Server};
+
+    use super::*;
+    use crate::{
+        app::{
+            balancer::{BalancePolicy, Balancer},
+            connection::Churn,
+            metrics::StepReporter,
+            scheduler::{Progress, ProgressReporter},
+            test_server::{self, EchoServer},
+            transport::{Connector, Target, TcpSettings},
+            worker::{CompressionSettings, GrpcWorker, Scenario, Worker},
+        },
+        generated::envoy::service::ext_proc::v3::processing_request,
+    };
+
+    /// A collector keeping the requests it receives.
+    #[derive(Debug, Clone, Default)]
+    struct Collector {
+        metrics: Arc<Mutex<Vec<ExportMetricsServiceRequest>>>,
+        traces: Arc<Mutex<Vec<ExportTraceServiceRequest>>>,
+    }
+
+    #[tonic::async_trait]
+    impl MetricsService for Collector {
+        async fn export(
+            &self,
+            request: Request<ExportMetricsServiceRequest>,
+        ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+            self.metrics.lock().unwrap().push(request.into_inner());
+            Ok(Response::new(ExportMetricsServiceResponse::default()))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: Request<ExportTraceServiceRequest>,
+        ) -> Result<Response<ExportTraceServiceResponse>, Status> {
+            self.traces.lock().unwrap().push(request.into_inner());
+            Ok(Response::new(ExportTraceServiceResponse::default()))
+        }
+    }
+
+    async fn serve_collector() -> (Collector, Endpoint) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint =
+            Endpoint::from_shared(format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let incoming = futures::stream::unfold(listener, async |listener| {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+
+        let collector = Collector::default();
+        let _handle = tokio::spawn(
+            Server::builder()
+                .add_service(MetricsServiceServer::new(collector.clone()))
+                .add_service(TraceServiceServer::new(collector.clone()))
+                .serve_with_incoming(incoming),
+        );
+
+        (collector, endpoint)
+    }
+
+    fn settings(endpoint: Endpoint) -> OtlpSettings {
+        OtlpSettings {
+            endpoint,
+            interval: Duration::from_mins(1),
+            trace_ratio: 1.0,
+        }
+    }
+
+    fn span(trace_id: u128) -> StreamSpan {
+        StreamSpan {
+            context: TraceContext {
+                trace_id,
+                span_id: 7,
+            },
+            record: StreamRecord {
+                intended_start_ns: 1_000,
+                start_ns: 2_000,
+                end_ns: 9_000,
+                task: 0,
+                endpoint: 0,
+                connection: 1,
+                outcome: StreamOutcome::Completed,
+                connection_wait_ns: 0,
+                tls_handshake_ns: None,
+                duration_ns: 7_000,
+                setup_ns: Some(1_000),
+                request_headers_ns: Some(2_000),
+                response_headers_ns: Some(4_000),
+                time_to_first_response_ns: Some(3_000),
+                error: None,
+                scenario: Scenario::Headers,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exporter_sends_metrics_and_spans_to_collector() {
+        let (collector, endpoint) = serve_collector().await;
+        let metrics = Metrics::default();
+        let exporter = Exporter::start(
+            &settings(endpoint),
+            metrics.clone(),
+            "run",
+            &["grpc://localhost:12345".to_owned()],
+        );
+
+        let reporter = StepReporter {
+            progress_bar: ProgressBar::hidden(),
+            dashboard: None,
+            events: None,
+            metrics: Some(metrics),
+            spans: Some(exporter.spans()),
+        };
+        let mut progress = Progress {
+            sent: 2,
+            completed: 2,
+            spans: vec![span(1), span(2)],
+            ..Progress::default()
+        };
+        progress.latency.record(Duration::from_millis(2));
+        progress.latency.record(Duration::from_millis(2));
+        reporter.report(&progress);
+        exporter.shutdown().await.unwrap();
+
+        let spans = collector.traces.lock().unwrap()[0].resource_spans[0].scope_spans[0]
+            .spans
+            .clone();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].trace_id, 1_u128.to_be_bytes());
+        assert_eq!(spans[0].span_id, 7_u64.to_be_bytes());
+        let events = spans[0]
+            .events
+            .iter()
+            .map(|event| (event.name.as_str(), event.time_unix_nano))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                ("setup", 3_000),
+                ("request_headers", 5_000),
+                ("response_headers", 9_000)
+            ]
+        );
+
+        let requests = collector.metrics.lock().unwrap();
+        let metrics = &requests.last().unwrap().resource_metrics[0].scope_metrics[0].metrics;
+        let sent = metrics
+            .iter()
+            .find(|metric| metric.name == "ext_proc_load_tester.streams.sent")
+            .unwrap();
+        let Some(metric::Data::Sum(sum)) = &sent.data else {
+            panic!("Expected a sum, got {sent:?}");
+        };
+        assert_eq!(
+            sum.data_points[0].value,
+            Some(number_data_point::Value::AsInt(2))
+        );
+        let duration = metrics
+            .iter()
+            .find(|metric| metric.name == "ext_proc_load_tester.stream.duration")
+            .unwrap();
+        let Some(metric::Data::Histogram(histogram)) = &duration.data else {
+            panic!("Expected a histogram, got {duration:?}");
+        };
+        assert_eq!(histogram.data_points[0].count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_exporter_reports_failed_exports_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint =
+            Endpoint::from_shared(format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        // NOTE: Nothing listens on the endpoint anymore.
+        drop(listener);
+
+        let exporter = Exporter::start(&settings(endpoint), Metrics::default(), "run", &[]);
+        exporter.spans().send(vec![span(1)]);
+
+        match exporter.shutdown().await {
+            Err(Error::FailedToExportTelemetry(2, _)) => {}
+            other => panic!("Expected FailedToExportTelemetry error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_spans_are_dropped_and_counted_when_too_many_are_pending() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let spans = SpanSender {
+            spans: sender,
+            dropped: Arc::default(),
+        };
+
+        spans.send(vec![span(1)]);
+        spans.send(vec![span(2), span(3)]);
+
+        assert_eq!(spans.dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(receiver.try_recv().unwrap(), [span(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_traceparent_is_sent_to_the_server() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let server = EchoServer::default();
+        test_server::serve_unix_with(UnixListener::bind(&path).unwrap(), server.clone());
+        let (target, uri) = Target::parse(&format!("unix:{}", path.display()), false);
+        let endpoints = [(
+            Endpoint::new(uri).unwrap(),
+            Connector::new(target, TcpSettings::default(), None),
+        )];
+        let endpoints = Balancer::connect(
+            &endpoints,
+            NonZeroUsize::MIN,
+            Churn::Never,
+            None,
+            BalancePolicy::RoundRobin,
+        )
+        .await
+        .unwrap();
+
+        let traceparents = Arc::new(Mutex::new(vec![]));
+        let seen = traceparents.clone();
+        let interceptor = move |request: Request<()>| {
+            if let Some(traceparent) = request.metadata().get("traceparent") {
+                seen.lock()
+                    .unwrap()
+                    .push(traceparent.to_str().unwrap().to_owned());
+            }
+            Ok(request)
+        };
+        let sampler = settings(Endpoint::from_static("http://localhost:4317")).sampler();
+        let worker = GrpcWorker::new(
+            &endpoints,
+            interceptor,
+            CompressionSettings::default(),
+            None,
+            sampler,
+        );
+
+        let stats = worker.run().await.unwrap();
+
+        let traceparent = stats.trace.unwrap().traceparent();
+        assert_eq!(traceparent.len(), 55);
+        assert_eq!(*traceparents.lock().unwrap(), [traceparent.clone()]);
+
+        let requests = server.requests.lock().unwrap();
+        let Some(processing_request::Request::RequestHeaders(headers)) = &requests[0].request
+        else {
+            panic!("Expected the request headers first, got {:?}", requests[0]);
+        };
+        let header = headers
+            .headers
+            .iter()
+            .flat_map(|headers| &headers.headers)
+            .find(|header| header.key == "traceparent")
+            .expect("the request headers hold the traceparent");
+        assert_eq!(header.raw_value, traceparent.into_bytes());
+    }
+}
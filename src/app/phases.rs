@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use crate::app::scheduler::{self, Recording};
+
+/// A value for each phase of a stream, as measured by `GrpcWorker`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Phases<T> {
+    /// From opening the stream until the server accepts it, by sending its response
+    /// headers. Servers that only send them with the first response, like grpc-go,
+    /// accept the stream once they answer the request headers, which are then part
+    /// of the setup.
+    pub(crate) setup: T,
+    /// From the server accepting the stream until it answers the request headers,
+    /// close to zero when the server sends its headers with the first response.
+    pub(crate) request_headers: T,
+    /// From sending the response headers until the server answers them.
+    pub(crate) response_headers: T,
+    /// From opening the stream until the server answers the request headers.
+    pub(crate) time_to_first_response: T,
+}
+
+/// The phases a stream went through, `None` for those it did not reach.
+pub(crate) type StreamPhases = Phases<Option<Duration>>;
+
+impl<T> Phases<T> {
+    /// The values with the name of their phase.
+    pub(crate) fn named(&self) -> [(&'static str, &T); 4] {
+        [
+            ("setup", &self.setup),
+            ("request_headers", &self.request_headers),
+            ("response_headers", &self.response_headers),
+            ("time_to_first_response", &self.time_to_first_response),
+        ]
+    }
+
+    pub(crate) fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> Phases<U> {
+        Phases {
+            setup: f(&self.setup),
+            request_headers: f(&self.request_headers),
+            response_headers: f(&self.response_headers),
+            time_to_first_response: f(&self.time_to_first_response),
+        }
+    }
+}
+
+impl Phases<Histogram<u64>> {
+    /// Creates empty histograms of durations in nanoseconds.
+    pub(crate) fn new(recording: Recording) -> Self {
+        Phases::<()>::default().map(|()| recording.histogram())
+    }
+
+    /// Records the phases a stream reached.
+    pub(crate) fn record(&mut self, phases: &StreamPhases) {
+        let histograms = [
+            &mut self.setup,
+            &mut self.request_headers,
+            &mut self.response_headers,
+            &mut self.time_to_first_response,
+        ];
+        for (histogram, (_, duration)) in histograms.into_iter().zip(phases.named()) {
+            if let Some(duration) = duration {
+                scheduler::record(histogram, *duration);
+            }
+        }
+    }
+
+    /// Adds the phases recorded in `other`, e.g. by another worker.
+    pub(crate) fn add(&mut self, other: &Self) {
+        let histograms = [
+            &mut self.setup,
+            &mut self.request_headers,
+            &mut self.response_headers,
+            &mut self.time_to_first_response,
+        ];
+        for (histogram, (_, other)) in histograms.into_iter().zip(other.named()) {
+            histogram
+                .add(other)
+                .expect("histograms grow to fit the merged durations");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_histograms_record_reached_phases() {
+        let mut histograms = Phases::new(Recording::default());
+        histograms.record(&StreamPhases {
+            setup: Some(Duration::from_millis(1)),
+            request_headers: Some(Duration::from_millis(2)),
+            response_headers: Some(Duration::from_millis(3)),
+            time_to_first_response: Some(Duration::from_millis(3)),
+        });
+        histograms.record(&StreamPhases {
+            setup: Some(Duration::from_millis(1)),
+            ..StreamPhases::default()
+        });
+
+        let mut merged = Phases::new(Recording::default());
+        merged.add(&histograms);
+        merged.add(&histograms);
+
+        assert_eq!(
+            merged.map(Histogram::len),
+            Phases {
+                setup: 4,
+                request_headers: 2,
+                response_headers: 2,
+                time_to_first_response: 2,
+            }
+        );
+        assert!(
+            merged
+                .response_headers
+                .equivalent(merged.response_headers.max(), 3_000_000)
+        );
+    }
+}
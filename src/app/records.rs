@@ -0,0 +1,230 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader, Write as _},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::time::Instant;
+
+use crate::app::{
+    cli::RecordsArgs,
+    error::{Error, Result},
+    worker::{Scenario, StreamOutcome},
+};
+
+/// A single stream, as written to `records_<rate>.ndjson` with `--stream-records`.
+///
+/// Times are in nanoseconds, since the Unix epoch for the instants.
+/// Fields are only ever added to this format, so older readers can skip them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StreamRecord {
+    /// When the scheduler planned to start the stream.
+    pub(crate) intended_start_ns: u64,
+    /// When the stream started, which is later than planned if the tester is saturated.
+    pub(crate) start_ns: u64,
+    pub(crate) end_ns: u64,
+    /// Index of the scheduler task (or shard) that sent the stream.
+    pub(crate) task: usize,
+    /// Index of the endpoint the stream was sent to.
+    pub(crate) endpoint: usize,
+    /// Identifier of the connection the stream used, 0 if it failed before getting one.
+    pub(crate) connection: u64,
+    pub(crate) outcome: StreamOutcome,
+    /// Time spent waiting for the connection, including opening a new one.
+    pub(crate) connection_wait_ns: u64,
+    /// Duration of the TLS handshake of the new connection, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tls_handshake_ns: Option<u64>,
+    /// Duration of the stream, excluding the time spent waiting for a connection.
+    pub(crate) duration_ns: u64,
+    /// Duration of the phases of the stream (see `Phases`), if it reached them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) setup_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) request_headers_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) response_headers_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) time_to_first_response_ns: Option<u64>,
+    /// The gRPC code of the error of a failed stream, e.g. `Unavailable`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    /// The exchange the stream ran. Records written before it was added ran `headers`.
+    #[serde(default)]
+    pub(crate) scenario: Scenario,
+}
+
+/// Converts the monotonic instants of a loop to wall-clock times.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WallClock {
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl WallClock {
+    pub(crate) fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch at `instant`.
+    pub(crate) fn unix_nanos(self, instant: Instant) -> u64 {
+        let time = match instant.checked_duration_since(self.instant) {
+            Some(elapsed) => self.system + elapsed,
+            None => self.system - self.instant.duration_since(instant),
+        };
+        nanos(
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+pub(crate) fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Reads NDJSON records, one at a time.
+pub(crate) fn read(reader: impl BufRead) -> impl Iterator<Item = io::Result<StreamRecord>> {
+    reader
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+/// Prints the records of a file, for the `records` command.
+pub(crate) fn print(args: &RecordsArgs) -> Result<()> {
+    let file = File::open(&args.file).map_err(Error::FailedToReadRecords)?;
+    let records = read(BufReader::new(file));
+    let mut stdout = io::stdout().lock();
+
+    let written = match args.slowest {
+        None => records
+            .into_iter()
+            .try_for_each(|record| writeln!(stdout, "{}", format_record(&record?))),
+        Some(count) => {
+            let mut records = records
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(Error::FailedToReadRecords)?;
+            records.sort_unstable_by_key(|record| std::cmp::Reverse(record.duration_ns));
+            records
+                .iter()
+                .take(count)
+                .try_for_each(|record| writeln!(stdout, "{}", format_record(record)))
+        }
+    };
+    written.map_err(Error::FailedToReadRecords)
+}
+
+fn format_record(record: &StreamRecord) -> String {
+    let start = i128::from(record.start_ns);
+    let start = OffsetDateTime::from_unix_timestamp_nanos(start)
+        .ok()
+        .and_then(|start| start.format(&Rfc3339).ok())
+        .unwrap_or_else(|| record.start_ns.to_string());
+    let mut line = format!(
+        "{start} {:?} late, {} {:?} ({:?}{}) task {} endpoint {} connection {}, waited {:?}",
+        Duration::from_nanos(record.start_ns.saturating_sub(record.intended_start_ns)),
+        record.scenario.name(),
+        Duration::from_nanos(record.duration_ns),
+        record.outcome,
+        record
+            .error
+            .as_ref()
+            .map(|error| format!(" {error}"))
+            .unwrap_or_default(),
+        record.task,
+        record.endpoint,
+        record.connection,
+        Duration::from_nanos(record.connection_wait_ns),
+    );
+    let phases = [
+        ("TLS handshake", record.tls_handshake_ns),
+        ("setup", record.setup_ns),
+        ("request headers", record.request_headers_ns),
+        ("response headers", record.response_headers_ns),
+        ("time to first response", record.time_to_first_response_ns),
+    ];
+    for (phase, duration) in phases {
+        if let Some(duration) = duration {
+            let _ = write!(line, ", {phase} {:?}", Duration::from_nanos(duration));
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(start_ns: u64, duration_ns: u64) -> StreamRecord {
+        StreamRecord {
+            intended_start_ns: start_ns - 1_000,
+            start_ns,
+            end_ns: start_ns + duration_ns,
+            task: 1,
+            endpoint: 0,
+            connection: 3,
+            outcome: StreamOutcome::ClosedEarly,
+            connection_wait_ns: 0,
+            tls_handshake_ns: None,
+            duration_ns,
+            setup_ns: Some(duration_ns / 4),
+            request_headers_ns: None,
+            response_headers_ns: None,
+            time_to_first_response_ns: None,
+            error: None,
+            scenario: Scenario::Headers,
+        }
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let records = vec![
+            record(1_760_779_815_123_000_000, 2_000_000),
+            StreamRecord {
+                outcome: StreamOutcome::Failed,
+                connection: 0,
+                tls_handshake_ns: Some(500_000),
+                error: Some("Unavailable".to_owned()),
+                ..record(1_760_779_815_124_000_000, 5_000)
+            },
+        ];
+
+        let ndjson = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap() + "\n")
+            .collect::<String>();
+        assert!(
+            ndjson.contains(r#""outcome":"closed_early""#),
+            "Got {ndjson}"
+        );
+        assert!(ndjson.contains(r#""error":"Unavailable""#), "Got {ndjson}");
+        assert!(ndjson.contains(r#""scenario":"headers""#), "Got {ndjson}");
+
+        let read = read(ndjson.as_bytes())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_wall_clock_converts_instants() {
+        let clock = WallClock::now();
+        let now = clock.unix_nanos(clock.instant);
+
+        assert_eq!(
+            clock.unix_nanos(clock.instant + Duration::from_millis(5)),
+            now + 5_000_000
+        );
+        assert_eq!(
+            clock.unix_nanos(clock.instant - Duration::from_millis(5)),
+            now - 5_000_000
+        );
+    }
+}
@@ -1,37 +1,402 @@
-use std::{path::Path, time::Duration};
+use std::{
+    collections::BTreeMap,
+    io::Write as _,
+    mem,
+    num::NonZeroUsize,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use hdrhistogram::{
+    Histogram,
+    serialization::{
+        Deserializer, V2DeflateSerializer,
+        interval_log::{IntervalLogIterator, IntervalLogWriterBuilder, LogEntry, Tag},
+    },
+};
+use serde::Serialize;
 
-use tokio::{
-    fs::File,
-    io::{AsyncWriteExt as _, BufWriter},
+use crate::app::{
+    balancer::BalancePolicy,
+    ci,
+    cli::{Compression, OutputFormat, RuntimeMode, TransportArgs},
+    downtime::Downtime,
+    export::{TableFormat, TableWriter},
+    health::HealthTransition,
+    manifest::Manifest,
+    phases::Phases,
+    records::StreamRecord,
+    scheduler::{self, EndpointStats, Recording},
+    sink::{self, RowWriter, RowWriterTask, StepSinks},
+    summary::{LatencySummary, StepSummary},
+    usage::Usage,
 };
 
-pub(crate) async fn write(
+/// The kinds of files that may be written for each step, with their extension.
+const STEP_FILES: [(&str, &str); 12] = [
+    ("histogram", "hlog"),
+    ("phases", "hlog"),
+    ("durations", "json"),
+    ("durations", "csv"),
+    ("durations", "parquet"),
+    ("connections", "hlog"),
+    ("usage", "json"),
+    ("downtime", "json"),
+    ("endpoints", "json"),
+    ("records", "ndjson"),
+    ("records", "csv"),
+    ("records", "parquet"),
+];
+
+fn step_file_name(kind: &str, target_throughput: u64, extension: &str) -> String {
+    format!("{kind}_{target_throughput}.{extension}")
+}
+
+/// Serializes a duration as whole milliseconds, for the `_ms` fields of the reports.
+pub(crate) fn as_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Lists the files written for a step, by kind.
+pub(crate) async fn step_files(
+    directory_path: &Path,
+    target_throughput: u64,
+) -> Result<BTreeMap<&'static str, String>, std::io::Error> {
+    let mut files = BTreeMap::new();
+    for (kind, extension) in STEP_FILES {
+        let file_name = step_file_name(kind, target_throughput, extension);
+        if tokio::fs::try_exists(directory_path.join(&file_name)).await? {
+            drop(files.insert(kind, file_name));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Writes the histogram of the stream durations of a step, in nanoseconds, as an
+/// `HdrHistogram` interval log, readable by the `HdrHistogram` tools.
+pub(crate) async fn write_histogram(
     directory_path: &Path,
     target_throughput: u64,
-    durations: &[Duration],
+    started_at: SystemTime,
+    duration: Duration,
+    histogram: &Histogram<u64>,
 ) -> Result<(), std::io::Error> {
-    let file_name = format!("durations_{target_throughput}.json");
-    let file_path = directory_path.join(file_name);
+    let tag = target_throughput.to_string();
+    let log = histogram_log(started_at, duration, [(tag.as_str(), histogram)])?;
+    let file_name = step_file_name("histogram", target_throughput, "hlog");
+    tokio::fs::write(directory_path.join(file_name), log).await
+}
 
-    let f = File::create(file_path).await?;
-    let mut writer = BufWriter::new(f);
+/// Reads the histogram of a `histogram_<rate>.hlog` file.
+pub(crate) fn read_histogram(file_path: &Path) -> Result<Histogram<u64>, std::io::Error> {
+    let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let log = std::fs::read(file_path)?;
+    for entry in IntervalLogIterator::new(&log) {
+        if let LogEntry::Interval(interval) = entry.map_err(|e| invalid(format!("{e:?}")))? {
+            let encoded = BASE64_STANDARD
+                .decode(interval.encoded_histogram())
+                .map_err(|e| invalid(e.to_string()))?;
+            return Deserializer::new()
+                .deserialize(&mut encoded.as_slice())
+                .map_err(|e| invalid(e.to_string()));
+        }
+    }
 
-    writer.write_all(b"[").await?;
+    Err(invalid(format!("no histogram in {}", file_path.display())))
+}
 
-    let mut has_previous_value = false;
+/// Reads a `durations_<rate>.json` file, the latency of every stream in nanoseconds.
+pub(crate) fn read_durations(file_path: &Path) -> Result<Vec<u64>, std::io::Error> {
+    Ok(serde_json::from_slice(&std::fs::read(file_path)?)?)
+}
+
+/// The histogram of latencies in nanoseconds, e.g. of the raw samples of a run
+/// written before the histograms were.
+pub(crate) fn durations_histogram(durations: &[u64]) -> Histogram<u64> {
+    let mut histogram = Recording::default().histogram();
     for duration in durations {
-        if has_previous_value {
-            writer.write_all(b",").await?;
-        }
+        scheduler::record(&mut histogram, Duration::from_nanos(*duration));
+    }
+    histogram
+}
+
+/// Writes the histograms of the phases of the streams of a step, in a single
+/// interval log tagged by phase.
+pub(crate) async fn write_phases(
+    directory_path: &Path,
+    target_throughput: u64,
+    started_at: SystemTime,
+    duration: Duration,
+    phases: &Phases<Histogram<u64>>,
+) -> Result<(), std::io::Error> {
+    let log = histogram_log(started_at, duration, phases.named())?;
+    let file_name = step_file_name("phases", target_throughput, "hlog");
+    tokio::fs::write(directory_path.join(file_name), log).await
+}
+
+fn histogram_log<'a>(
+    started_at: SystemTime,
+    duration: Duration,
+    histograms: impl IntoIterator<Item = (&'a str, &'a Histogram<u64>)>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut log = Vec::new();
+    let mut serializer = V2DeflateSerializer::new();
+    let mut writer = IntervalLogWriterBuilder::new()
+        .with_start_time(started_at)
+        .with_max_value_divisor(1e6)
+        .begin_log_with(&mut log, &mut serializer)?;
+    for (tag, histogram) in histograms {
         writer
-            .write_all(format!("{}", duration.as_nanos()).as_bytes())
-            .await?;
-        has_previous_value = true;
+            .write_histogram(histogram, Duration::ZERO, duration, Tag::new(tag))
+            .map_err(std::io::Error::other)?;
+    }
+
+    Ok(log)
+}
+
+/// Writes the histograms of the latency of the connections opened during a step and
+/// of their TLS handshakes, in a single interval log tagged `connect` and
+/// `tls_handshake`. Empty histograms are left out.
+pub(crate) async fn write_connections(
+    directory_path: &Path,
+    target_throughput: u64,
+    started_at: SystemTime,
+    duration: Duration,
+    connect: &Histogram<u64>,
+    tls_handshake: &Histogram<u64>,
+) -> Result<(), std::io::Error> {
+    let histograms = [("connect", connect), ("tls_handshake", tls_handshake)]
+        .into_iter()
+        .filter(|(_, histogram)| !histogram.is_empty());
+    let log = histogram_log(started_at, duration, histograms)?;
+    let file_name = step_file_name("connections", target_throughput, "hlog");
+    tokio::fs::write(directory_path.join(file_name), log).await
+}
+
+/// Spawns the writers of the rows the loops of a step keep: the raw samples as
+/// `durations_<rate>.<format>` and the stream records as `records_<rate>.<format>`,
+/// JSON and NDJSON without a table format. The writers are returned with the kind of
+/// their file.
+pub(crate) fn step_sinks(
+    directory_path: &Path,
+    target_throughput: u64,
+    format: Option<TableFormat>,
+    raw_samples: bool,
+    stream_records: bool,
+) -> (StepSinks, Vec<(&'static str, RowWriterTask)>) {
+    let path = |kind, extension| {
+        let extension = format.map_or(extension, TableFormat::extension);
+        directory_path.join(step_file_name(kind, target_throughput, extension))
+    };
+
+    let mut writers = vec![];
+    let durations = raw_samples.then(|| {
+        let path = path("durations", "json");
+        let (sender, writer) = match format {
+            Some(format) => sink::spawn(move || TableWriter::create(&path, format)),
+            None => sink::spawn(move || DurationsWriter::create(&path)),
+        };
+        writers.push(("durations", writer));
+        sender
+    });
+    let records = stream_records.then(|| {
+        let path = path("records", "ndjson");
+        let (sender, writer) = match format {
+            Some(format) => sink::spawn(move || TableWriter::create(&path, format)),
+            None => sink::spawn(move || RecordsWriter::create(&path)),
+        };
+        writers.push(("records", writer));
+        sender
+    });
+
+    (StepSinks { durations, records }, writers)
+}
+
+/// Writes the raw samples as a JSON array of nanoseconds.
+struct DurationsWriter {
+    writer: std::io::BufWriter<std::fs::File>,
+    first: bool,
+}
+
+impl DurationsWriter {
+    fn create(file_path: &Path) -> Result<Self, std::io::Error> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(file_path)?);
+        writer.write_all(b"[")?;
+        Ok(Self {
+            writer,
+            first: true,
+        })
     }
+}
 
-    writer.write_all(b"]").await?;
+impl RowWriter<Duration> for DurationsWriter {
+    fn write(&mut self, duration: &Duration) -> Result<(), std::io::Error> {
+        if !mem::take(&mut self.first) {
+            self.writer.write_all(b",")?;
+        }
+        write!(self.writer, "{}", duration.as_nanos())
+    }
 
-    writer.flush().await?;
+    fn finish(mut self) -> Result<(), std::io::Error> {
+        self.writer.write_all(b"]")?;
+        self.writer.flush()
+    }
+}
 
-    Ok(())
+/// Writes the stream records as NDJSON.
+struct RecordsWriter(std::io::BufWriter<std::fs::File>);
+
+impl RecordsWriter {
+    fn create(file_path: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self(std::io::BufWriter::new(std::fs::File::create(
+            file_path,
+        )?)))
+    }
+}
+
+impl RowWriter<StreamRecord> for RecordsWriter {
+    fn write(&mut self, record: &StreamRecord) -> Result<(), std::io::Error> {
+        serde_json::to_writer(&mut self.0, record)?;
+        self.0.write_all(b"\n")
+    }
+
+    fn finish(mut self) -> Result<(), std::io::Error> {
+        self.0.flush()
+    }
+}
+
+/// Writes the summaries of the steps to `steps.<format>`, one row per step.
+pub(crate) async fn write_steps(
+    directory_path: &Path,
+    format: TableFormat,
+    summaries: Vec<StepSummary>,
+) -> Result<(), std::io::Error> {
+    let file_path = directory_path.join(format!("steps.{}", format.extension()));
+    // NOTE: The CSV and Parquet writers are synchronous.
+    tokio::task::spawn_blocking(move || {
+        let mut writer = TableWriter::create(&file_path, format)?;
+        for summary in &summaries {
+            writer.write(summary)?;
+        }
+        writer.finish()
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Settings of the run, recorded next to its results.
+#[derive(Debug, Serialize)]
+pub(crate) struct Metadata<'a> {
+    pub(crate) uris: &'a [String],
+    pub(crate) test_duration_s: u64,
+    /// Target throughput of each step.
+    pub(crate) throughputs: &'a [u64],
+    pub(crate) runtime: RuntimeMode,
+    pub(crate) threads: NonZeroUsize,
+    /// Connections to each server, in total across the threads.
+    pub(crate) connections: NonZeroUsize,
+    /// Scheduler tasks of the multi-thread runtime.
+    pub(crate) scheduler_tasks: Option<NonZeroUsize>,
+    pub(crate) histogram_precision: u8,
+    pub(crate) raw_samples: bool,
+    pub(crate) stream_records: bool,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) balance: BalancePolicy,
+    pub(crate) transport: &'a TransportArgs,
+    pub(crate) send_compression: Option<Compression>,
+    pub(crate) accept_compression: &'a [Compression],
+}
+
+/// Writes the settings of the run to `metadata.json`.
+pub(crate) async fn write_metadata(
+    directory_path: &Path,
+    metadata: &Metadata<'_>,
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec_pretty(metadata)?;
+    tokio::fs::write(directory_path.join("metadata.json"), json).await
+}
+
+/// Writes the description of the run to `manifest.json`.
+pub(crate) async fn write_manifest(
+    directory_path: &Path,
+    manifest: &Manifest<'_>,
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec_pretty(manifest)?;
+    tokio::fs::write(directory_path.join("manifest.json"), json).await
+}
+
+/// Writes the `JUnit` XML report and the Markdown summary of the run, for CI.
+pub(crate) async fn write_ci_reports(
+    directory_path: &Path,
+    manifest: &Manifest<'_>,
+) -> Result<(), std::io::Error> {
+    tokio::fs::write(directory_path.join("junit.xml"), ci::junit(manifest)).await?;
+    tokio::fs::write(directory_path.join("summary.md"), ci::markdown(manifest)).await
+}
+
+/// Writes the windows during which the server was unavailable during a step.
+pub(crate) async fn write_downtime(
+    directory_path: &Path,
+    target_throughput: u64,
+    downtimes: &[Downtime],
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec(downtimes)?;
+    let file_name = step_file_name("downtime", target_throughput, "json");
+    tokio::fs::write(directory_path.join(file_name), json).await
+}
+
+/// Writes the bytes on the wire and the CPU time of a step.
+pub(crate) async fn write_usage(
+    directory_path: &Path,
+    target_throughput: u64,
+    usage: &Usage,
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec(usage)?;
+    let file_name = step_file_name("usage", target_throughput, "json");
+    tokio::fs::write(directory_path.join(file_name), json).await
+}
+
+/// The streams sent to a single endpoint during a step.
+#[derive(Debug, Serialize)]
+pub(crate) struct EndpointReport<'a> {
+    pub(crate) uri: &'a str,
+    pub(crate) failed_streams: u64,
+    /// The latency of the streams that succeeded.
+    #[serde(flatten)]
+    pub(crate) latency: LatencySummary,
+}
+
+impl<'a> EndpointReport<'a> {
+    pub(crate) fn new(uri: &'a str, stats: &EndpointStats) -> Self {
+        Self {
+            uri,
+            failed_streams: stats.failed_streams,
+            latency: LatencySummary::new(&stats.histogram),
+        }
+    }
+}
+
+/// Writes the breakdown of the streams of a step by endpoint.
+pub(crate) async fn write_endpoints(
+    directory_path: &Path,
+    target_throughput: u64,
+    endpoints: &[EndpointReport<'_>],
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec(endpoints)?;
+    let file_name = step_file_name("endpoints", target_throughput, "json");
+    tokio::fs::write(directory_path.join(file_name), json).await
+}
+
+/// Writes the health transitions of the servers observed during the run.
+pub(crate) async fn write_health(
+    directory_path: &Path,
+    transitions: &[HealthTransition],
+) -> Result<(), std::io::Error> {
+    let json = serde_json::to_vec_pretty(transitions)?;
+    tokio::fs::write(directory_path.join("health.json"), json).await
 }
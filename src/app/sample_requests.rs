@@ -4,24 +4,26 @@ pub(crate) mod request_headers {
         service::ext_proc::v3::{HttpHeaders, ProcessingRequest, processing_request::Request},
     };
 
-    pub(crate) fn create_processing_request() -> ProcessingRequest {
+    /// Creates the request headers, with the `traceparent` header of the stream if
+    /// it is traced, so that the server joins the trace of the proxied request.
+    pub(crate) fn create_processing_request(traceparent: Option<String>) -> ProcessingRequest {
         ProcessingRequest {
-            request: Some(Request::RequestHeaders(create_http_headers())),
+            request: Some(Request::RequestHeaders(create_http_headers(traceparent))),
             ..Default::default()
         }
     }
 
-    fn create_http_headers() -> HttpHeaders {
+    fn create_http_headers(traceparent: Option<String>) -> HttpHeaders {
         HttpHeaders {
-            headers: Some(create_header_map()),
+            headers: Some(create_header_map(traceparent)),
             ..Default::default()
         }
     }
 
-    fn create_header_map() -> HeaderMap {
-        HeaderMap {
-            headers: vec![create_header_value()],
-        }
+    fn create_header_map(traceparent: Option<String>) -> HeaderMap {
+        let mut headers = vec![create_header_value()];
+        headers.extend(traceparent.map(create_traceparent_value));
+        HeaderMap { headers }
     }
 
     fn create_header_value() -> HeaderValue {
@@ -31,6 +33,14 @@ pub(crate) mod request_headers {
             ..Default::default()
         }
     }
+
+    fn create_traceparent_value(traceparent: String) -> HeaderValue {
+        HeaderValue {
+            key: "traceparent".to_string(),
+            raw_value: traceparent.into_bytes(),
+            ..Default::default()
+        }
+    }
 }
 
 pub(crate) mod response_headers {
@@ -1,6 +1,16 @@
-use std::{num::NonZeroU32, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    mem,
+    num::NonZeroU32,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
 use futures::stream::FuturesUnordered;
+use hdrhistogram::Histogram;
 use indicatif::ProgressBar;
 use tokio::{
     select,
@@ -10,10 +20,17 @@ use tokio::{
 };
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
+use tonic::Code;
 
 use crate::app::{
     error::{Error, Result},
-    worker::Worker,
+    metrics::Buckets,
+    otlp::{StreamSpan, TraceContext},
+    phases::{Phases, StreamPhases},
+    records::{self, StreamRecord, WallClock},
+    sink::{RowBuffer, StepSinks},
+    summary::StepSummary,
+    worker::{Scenario, StreamOutcome, StreamStats, Worker},
 };
 
 pub(crate) const REPORT_INTERVAL: Duration = Duration::from_millis(250);
@@ -36,6 +53,7 @@ pub(crate) struct Scheduler<W> {
     workers: Vec<W>,
     concurrency: NonZeroU32,
     reporter_interval: Duration,
+    recording: Recording,
 }
 
 impl<W> Scheduler<W>
@@ -45,7 +63,11 @@ where
     /// Constructs a new `Scheduler` from the provided list of workers.
     /// The number of workers defines the concurrency level.
     #[allow(dead_code)]
-    pub(crate) fn new(workers: &[W], reporter_interval: Duration) -> Result<Self> {
+    pub(crate) fn new(
+        workers: &[W],
+        reporter_interval: Duration,
+        recording: Recording,
+    ) -> Result<Self> {
         let worker_count = workers
             .len()
             .try_into()
@@ -60,6 +82,7 @@ where
             workers,
             concurrency,
             reporter_interval,
+            recording,
         })
     }
 
@@ -72,16 +95,17 @@ where
     /// # Parameters
     /// - `interval`: the desired time between individual worker invocations globally.
     /// - `timeout`: the total duration after which all workers are cancelled.
+    /// - `sinks`: where the loops write the rows they keep of every stream.
     ///
     /// # Returns
-    /// A vector of duration lists, one per worker, measuring actual execution latency.
-    /// There is no order guarantee on the returned durations.
+    /// The result of each worker, measuring actual execution latency.
     #[allow(dead_code)]
     pub(crate) async fn run(
         &mut self,
         interval: Duration,
         timeout: Duration,
         progress_reporter: &impl ProgressReporter,
+        sinks: &StepSinks,
     ) -> Result<Vec<WorkerResult>> {
         let start = Instant::now();
 
@@ -93,32 +117,31 @@ where
             .expect("duration must not overflow");
 
         let cancelation_token = CancellationToken::new();
+        let in_flight = Arc::new(InFlight::default());
         let mut set = JoinSet::new();
         let mut offset = Duration::ZERO;
-        for worker in &self.workers {
+        for (task, worker) in self.workers.iter().enumerate() {
             // Offset the start of each worker by the interval * i,
             // so that they don't tick at the same time.
             offset += interval;
             let start_time = start + offset;
 
-            // Guess the number of requests that will be sent to pre-allocate the result vector.
-            let size_hint: usize = timeout
-                .as_nanos()
-                .checked_div(loop_interval.as_nanos())
-                .expect("loop interval must not be zero")
-                .try_into()
-                .map_err(Error::EstimatedRequestCountTooLarge)?;
+            let size_hint = size_hint(timeout, loop_interval)?;
 
             let progress_reporter = progress_reporter.clone();
 
             let _handle = set.spawn(run_loop(
                 LoopParams {
+                    task,
                     start: start_time,
-                    barrier: barrier.clone(),
+                    barrier: Some(barrier.clone()),
                     interval: loop_interval,
                     cancelation_token: cancelation_token.clone(),
                     size_hint,
                     reporter_interval: self.reporter_interval,
+                    recording: self.recording,
+                    in_flight: in_flight.clone(),
+                    sinks: sinks.clone(),
                 },
                 worker.clone(),
                 progress_reporter.clone(),
@@ -139,58 +162,153 @@ where
     }
 }
 
-struct LoopParams {
-    start: Instant,
-    barrier: Arc<Barrier>,
-    interval: Duration,
-    cancelation_token: CancellationToken,
-    size_hint: usize,
-    reporter_interval: Duration,
+#[derive(Debug)]
+pub(crate) struct LoopParams {
+    /// Index of the loop, recorded with its streams.
+    pub(crate) task: usize,
+    pub(crate) start: Instant,
+    /// Barrier to wait on before starting the loop, if the loops must start together.
+    pub(crate) barrier: Option<Arc<Barrier>>,
+    pub(crate) interval: Duration,
+    pub(crate) cancelation_token: CancellationToken,
+    pub(crate) size_hint: usize,
+    pub(crate) reporter_interval: Duration,
+    pub(crate) recording: Recording,
+    /// The streams in flight, shared by all the loops of a step.
+    pub(crate) in_flight: Arc<InFlight>,
+    /// Where the loop writes the rows it keeps of its streams.
+    pub(crate) sinks: StepSinks,
+}
+
+/// The streams in flight across the loops of a step, and their peak.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl InFlight {
+    fn start(&self) {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        let _ = self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn finish(&self) {
+        self.finish_many(1);
+    }
+
+    fn finish_many(&self, streams: usize) {
+        let _ = self.current.fetch_sub(streams, Ordering::Relaxed);
+    }
+
+    /// Highest number of streams in flight at once so far.
+    pub(crate) fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// How a loop records the durations of its streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Recording {
+    /// Number of significant decimal digits of the latency histogram, from 0 to 5.
+    pub(crate) precision: u8,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self { precision: 3 }
+    }
+}
+
+impl Recording {
+    /// Creates an empty histogram of durations in nanoseconds, which grows to fit
+    /// the recorded durations.
+    pub(crate) fn histogram(self) -> Histogram<u64> {
+        Histogram::new(self.precision).expect("precision must be at most 5")
+    }
+}
+
+/// Records a duration in a histogram created by `Recording::histogram`.
+pub(crate) fn record(histogram: &mut Histogram<u64>, duration: Duration) {
+    let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    if histogram.record(nanos).is_err() {
+        // NOTE: The histogram cannot grow to fit durations of centuries.
+        histogram.saturating_record(nanos);
+    }
+}
+
+/// Guesses the number of requests a loop will send, to pre-allocate its buffers.
+pub(crate) fn size_hint(timeout: Duration, loop_interval: Duration) -> Result<usize> {
+    timeout
+        .as_nanos()
+        .checked_div(loop_interval.as_nanos())
+        .expect("loop interval must not be zero")
+        .try_into()
+        .map_err(Error::EstimatedRequestCountTooLarge)
 }
 
 /// Internal per-worker loop that schedules and runs the worker periodically.
-async fn run_loop(
+pub(crate) async fn run_loop(
     params: LoopParams,
     worker: impl Worker,
     progress_reporter: impl ProgressReporter,
 ) -> Result<WorkerResult> {
     let LoopParams {
+        task,
         start,
         barrier,
         interval,
         cancelation_token,
         size_hint,
         reporter_interval,
+        recording,
+        in_flight,
+        sinks,
     } = params;
 
     let mut worker_interval = create_interval(start, interval);
     let mut reporter_interval = create_interval(start, reporter_interval);
 
     let mut futures = FuturesUnordered::new();
-    let mut durations = Vec::with_capacity(size_hint);
+    let mut result = WorkerResult::new(recording);
+    let mut rows = LoopRows::new(sinks, size_hint);
 
-    let _ = barrier.wait().await;
-    let mut request_sent = 0_u64;
+    if let Some(barrier) = barrier {
+        let _ = barrier.wait().await;
+    }
 
-    let mut last_reported = 0_usize;
+    let mut progress = Progress::default();
+    let percentiles = progress_reporter.reports_percentiles();
+    let clock = WallClock::now();
 
     loop {
         select! {
-            _ = worker_interval.tick() => {
+            intended_start = worker_interval.tick() => {
                 // Interval ticked, time to spin a new worker.
-                futures.push(run_with_duration(&worker));
-                request_sent += 1;
+                futures.push(run_with_duration(&worker, intended_start));
+                result.request_sent += 1;
+                in_flight.start();
+                progress.sent();
             }
             _ = reporter_interval.tick() => {
-                let v = durations.len();
-                progress_reporter.report(v - last_reported);
-                last_reported = v;
+                progress_reporter.report(&mem::take(&mut progress));
             }
-            result = futures.next() => {
-                match result {
-                    Some(result) => {
+            sample = futures.next() => {
+                match sample {
+                    Some(Ok(sample)) => {
                         // Worker finished running successfully, record the duration.
-                        durations.push(result?);
+                        in_flight.finish();
+                        progress.record(&sample, task, clock, percentiles);
+                        result.record(&sample, recording);
+                        rows.record(&sample, task, clock);
+                    }
+                    Some(Err(e)) => {
+                        // NOTE: The streams still running are dropped with the loop.
+                        let ended = futures.len() + 1;
+                        in_flight.finish_many(ended);
+                        progress.in_flight -= i64::try_from(ended).unwrap_or(i64::MAX);
+                        progress_reporter.report(&progress);
+                        return Err(e);
                     }
                     None => {
                         // The stream is empty and no futures are currently running.
@@ -200,24 +318,18 @@ async fn run_loop(
                         // - the next interval tick to start new work, or
                         // - cancellation to terminate the loop.
                         select! {
-                            _ = worker_interval.tick() => {
+                            intended_start = worker_interval.tick() => {
                                 // Interval ticked, time to spin a new worker.
-                                futures.push(run_with_duration(&worker));
-                                request_sent += 1;
+                                futures.push(run_with_duration(&worker, intended_start));
+                                result.request_sent += 1;
+                                in_flight.start();
+                                progress.sent();
                             }
                             _ = reporter_interval.tick() => {
-                                let v = durations.len();
-                                progress_reporter.report(v - last_reported);
-                                last_reported = v;
-                            }
-                            () = cancelation_token.cancelled() => {
-                                // Cancelation token was cancelled, return the durations.
-                                // NOTE: No need to wait for the workers to finish, as we know they are not running.
-                                return Ok(WorkerResult {
-                                    request_sent,
-                                    durations,
-                                });
+                                progress_reporter.report(&mem::take(&mut progress));
                             }
+                            // NOTE: No need to wait for the workers to finish, as we know they are not running.
+                            () = cancelation_token.cancelled() => break,
                         }
 
                     }
@@ -225,14 +337,21 @@ async fn run_loop(
             }
             () = cancelation_token.cancelled() => {
                 // Cancelation token was cancelled, wait for the workers to finish.
-                while futures.next().await.is_some() {}
-                return Ok(WorkerResult {
-                    request_sent,
-                    durations,
-                });
+                while futures.next().await.is_some() {
+                    // NOTE: The stream is not recorded, but it is no longer in flight either.
+                    progress.in_flight -= 1;
+                    in_flight.finish();
+                }
+                break;
             }
         }
     }
+
+    // Cancelation token was cancelled, return the durations.
+    progress_reporter.report(&progress);
+    rows.flush().await;
+    result.in_flight_peak = in_flight.peak();
+    Ok(result)
 }
 
 fn create_interval(start: Instant, interval: Duration) -> tokio::time::Interval {
@@ -250,45 +369,395 @@ fn create_interval(start: Instant, interval: Duration) -> tokio::time::Interval
 #[derive(Debug)]
 pub(crate) struct WorkerResult {
     pub(crate) request_sent: u64,
-    pub(crate) durations: Vec<Duration>,
+    /// Durations of the streams that succeeded, in nanoseconds.
+    pub(crate) histogram: Histogram<u64>,
+    /// Durations of the phases the streams that succeeded reached, in nanoseconds.
+    pub(crate) phases: Phases<Histogram<u64>>,
+    /// Latency of the connections opened by the worker, in nanoseconds.
+    pub(crate) connect: Histogram<u64>,
+    /// Duration of the TLS handshakes of the connections opened by the worker, in
+    /// nanoseconds.
+    pub(crate) tls_handshake: Histogram<u64>,
+    /// Number of streams that could not connect or ended with an error status.
+    pub(crate) failed_streams: u64,
+    /// The failed streams, by gRPC code of their error.
+    pub(crate) failures: HashMap<Code, u64>,
+    /// Number of streams the server closed before answering both requests, which
+    /// breaks the `ext_proc` protocol. They are counted as succeeded.
+    pub(crate) closed_early_streams: u64,
+    /// Breakdown of the streams by endpoint, indexed like the endpoints.
+    pub(crate) endpoints: Vec<EndpointStats>,
+    /// Highest number of streams in flight at once, across all the loops of the step.
+    pub(crate) in_flight_peak: usize,
+}
+
+impl WorkerResult {
+    fn new(recording: Recording) -> Self {
+        Self {
+            request_sent: 0,
+            histogram: recording.histogram(),
+            phases: Phases::new(recording),
+            connect: recording.histogram(),
+            tls_handshake: recording.histogram(),
+            failed_streams: 0,
+            failures: HashMap::new(),
+            closed_early_streams: 0,
+            endpoints: vec![],
+            in_flight_peak: 0,
+        }
+    }
+
+    /// Merges the results of several loops, e.g. of all the loops of a step.
+    pub(crate) fn merge(results: impl IntoIterator<Item = Self>, recording: Recording) -> Self {
+        let mut merged = Self::new(recording);
+        for result in results {
+            merged.request_sent += result.request_sent;
+            merged
+                .histogram
+                .add(&result.histogram)
+                .expect("histograms grow to fit the merged durations");
+            merged.phases.add(&result.phases);
+            merged
+                .connect
+                .add(&result.connect)
+                .expect("histograms grow to fit the merged durations");
+            merged
+                .tls_handshake
+                .add(&result.tls_handshake)
+                .expect("histograms grow to fit the merged durations");
+            merged.failed_streams += result.failed_streams;
+            for (code, count) in result.failures {
+                *merged.failures.entry(code).or_default() += count;
+            }
+            merged.closed_early_streams += result.closed_early_streams;
+            if merged.endpoints.len() < result.endpoints.len() {
+                merged
+                    .endpoints
+                    .resize_with(result.endpoints.len(), || EndpointStats::new(recording));
+            }
+            for (merged, stats) in merged.endpoints.iter_mut().zip(&result.endpoints) {
+                merged.merge(stats);
+            }
+            // NOTE: The loops share their peak, but end at slightly different times.
+            merged.in_flight_peak = merged.in_flight_peak.max(result.in_flight_peak);
+        }
+
+        merged
+    }
+
+    fn record(&mut self, sample: &Sample, recording: Recording) {
+        if self.endpoints.len() <= sample.endpoint {
+            self.endpoints
+                .resize_with(sample.endpoint + 1, || EndpointStats::new(recording));
+        }
+        let endpoint = &mut self.endpoints[sample.endpoint];
+        if sample.outcome == StreamOutcome::Failed {
+            self.failed_streams += 1;
+            *self.failures.entry(sample.failure_code()).or_default() += 1;
+            endpoint.failed_streams += 1;
+            return;
+        }
+        if sample.outcome == StreamOutcome::ClosedEarly {
+            self.closed_early_streams += 1;
+        }
+
+        endpoint.record(sample.duration);
+        record(&mut self.histogram, sample.duration);
+        self.phases.record(&sample.phases);
+        if let Some(connect_duration) = sample.connect_duration {
+            record(&mut self.connect, connect_duration);
+        }
+        if let Some(tls_handshake_duration) = sample.tls_handshake_duration {
+            record(&mut self.tls_handshake, tls_handshake_duration);
+        }
+    }
+}
+
+/// The rows a loop writes to the sinks of its step, buffered in batches.
+#[derive(Debug)]
+struct LoopRows {
+    durations: Option<RowBuffer<Duration>>,
+    records: Option<RowBuffer<StreamRecord>>,
+}
+
+impl LoopRows {
+    fn new(sinks: StepSinks, size_hint: usize) -> Self {
+        Self {
+            durations: sinks
+                .durations
+                .map(|sender| RowBuffer::new(sender, size_hint)),
+            records: sinks
+                .records
+                .map(|sender| RowBuffer::new(sender, size_hint)),
+        }
+    }
+
+    /// Writes the record of every stream, and the duration of those that did not fail,
+    /// without waiting for the writers so that the next streams start on time.
+    fn record(&mut self, sample: &Sample, task: usize, clock: WallClock) {
+        if let Some(records) = &mut self.records {
+            records.push(sample.stream_record(task, clock));
+        }
+        if let Some(durations) = &mut self.durations
+            && sample.outcome != StreamOutcome::Failed
+        {
+            durations.push(sample.duration);
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Some(records) = &mut self.records {
+            records.flush().await;
+        }
+        if let Some(durations) = &mut self.durations {
+            durations.flush().await;
+        }
+    }
+}
+
+/// The streams sent to a single endpoint.
+#[derive(Debug, Clone)]
+pub(crate) struct EndpointStats {
+    pub(crate) failed_streams: u64,
+    /// Durations of the streams that succeeded, in nanoseconds.
+    pub(crate) histogram: Histogram<u64>,
+}
+
+impl EndpointStats {
+    pub(crate) fn new(recording: Recording) -> Self {
+        Self {
+            failed_streams: 0,
+            histogram: recording.histogram(),
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        record(&mut self.histogram, duration);
+    }
+
+    /// Adds the streams of `other`, e.g. recorded by another worker.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.failed_streams += other.failed_streams;
+        self.histogram
+            .add(&other.histogram)
+            .expect("histograms grow to fit the merged durations");
+    }
+}
+
+/// Measurements of a single worker invocation.
+struct Sample {
+    /// When the scheduler planned to run the worker.
+    intended_start: Instant,
+    start: Instant,
+    end: Instant,
+    /// Duration of the stream, excluding the time spent waiting for a connection.
+    duration: Duration,
+    connection_wait: Duration,
+    connect_duration: Option<Duration>,
+    tls_handshake_duration: Option<Duration>,
+    outcome: StreamOutcome,
+    endpoint: usize,
+    connection: u64,
+    phases: StreamPhases,
+    trace: Option<TraceContext>,
+    error: Option<Code>,
+}
+
+impl Sample {
+    /// The gRPC code of the error of a failed stream.
+    fn failure_code(&self) -> Code {
+        self.error.unwrap_or(Code::Unknown)
+    }
+
+    fn stream_record(&self, task: usize, clock: WallClock) -> StreamRecord {
+        StreamRecord {
+            intended_start_ns: clock.unix_nanos(self.intended_start),
+            start_ns: clock.unix_nanos(self.start),
+            end_ns: clock.unix_nanos(self.end),
+            task,
+            endpoint: self.endpoint,
+            connection: self.connection,
+            outcome: self.outcome,
+            connection_wait_ns: records::nanos(self.connection_wait),
+            tls_handshake_ns: self.tls_handshake_duration.map(records::nanos),
+            duration_ns: records::nanos(self.duration),
+            setup_ns: self.phases.setup.map(records::nanos),
+            request_headers_ns: self.phases.request_headers.map(records::nanos),
+            response_headers_ns: self.phases.response_headers.map(records::nanos),
+            time_to_first_response_ns: self.phases.time_to_first_response.map(records::nanos),
+            error: self.error.map(|code| format!("{code:?}")),
+            scenario: Scenario::Headers,
+        }
+    }
 }
 
 /// Runs the given worker and measures its execution time.
-async fn run_with_duration(worker: &impl Worker) -> Result<Duration> {
+async fn run_with_duration(worker: &impl Worker, intended_start: Instant) -> Result<Sample> {
     let start = Instant::now();
-    worker.run().await?;
-    Ok(start.elapsed())
+    let StreamStats {
+        connection_wait,
+        new_connection,
+        tls_handshake,
+        outcome,
+        endpoint,
+        connection,
+        phases,
+        trace,
+        error,
+    } = worker.run().await?;
+    let end = Instant::now();
+
+    Ok(Sample {
+        intended_start,
+        start,
+        end,
+        duration: (end - start).saturating_sub(connection_wait),
+        connection_wait,
+        connect_duration: new_connection.then_some(connection_wait),
+        tls_handshake_duration: tls_handshake,
+        outcome,
+        endpoint,
+        connection,
+        phases,
+        trace,
+        error,
+    })
+}
+
+/// What a loop did since its previous report to its `ProgressReporter`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Progress {
+    /// Streams started.
+    pub(crate) sent: u64,
+    /// Streams ended, by outcome.
+    pub(crate) completed: u64,
+    pub(crate) closed_early: u64,
+    pub(crate) failed: u64,
+    /// The failed streams, by gRPC code of their error.
+    pub(crate) failures: HashMap<Code, u64>,
+    /// Change of the number of streams in flight.
+    pub(crate) in_flight: i64,
+    /// Latency of the streams that did not fail, excluding the connection wait.
+    pub(crate) latency: Buckets,
+    /// The same latency in nanoseconds, with 2 significant digits, for percentiles.
+    /// Only allocated once a stream is recorded, for a reporter that reports
+    /// percentiles.
+    pub(crate) latency_histogram: Option<Histogram<u64>>,
+    /// Delay between the planned and the actual start of the streams.
+    pub(crate) scheduler_lag: Buckets,
+    /// The traced streams that ended.
+    pub(crate) spans: Vec<StreamSpan>,
 }
 
+impl Progress {
+    /// Number of streams that succeeded, as counted in `WorkerResult`.
+    pub(crate) fn succeeded(&self) -> u64 {
+        self.completed + self.closed_early
+    }
+
+    fn sent(&mut self) {
+        self.sent += 1;
+        self.in_flight += 1;
+    }
+
+    /// Records an ended stream, and its latency in `latency_histogram` if `percentiles`.
+    fn record(&mut self, sample: &Sample, task: usize, clock: WallClock, percentiles: bool) {
+        self.in_flight -= 1;
+        if let Some(context) = sample.trace {
+            self.spans.push(StreamSpan {
+                context,
+                record: sample.stream_record(task, clock),
+            });
+        }
+        self.scheduler_lag.record(
+            sample
+                .start
+                .saturating_duration_since(sample.intended_start),
+        );
+        match sample.outcome {
+            StreamOutcome::Completed => self.completed += 1,
+            StreamOutcome::ClosedEarly => self.closed_early += 1,
+            StreamOutcome::Failed => {
+                self.failed += 1;
+                *self.failures.entry(sample.failure_code()).or_default() += 1;
+                return;
+            }
+        }
+        self.latency.record(sample.duration);
+        if percentiles {
+            record(
+                self.latency_histogram.get_or_insert_with(|| {
+                    Histogram::new(2).expect("2 significant digits are valid")
+                }),
+                sample.duration,
+            );
+        }
+    }
+}
+
+/// Receives the progress of the steps, e.g. to draw it.
 pub(crate) trait ProgressReporter: Send + Sync + Clone + 'static {
-    fn report(&self, amount: usize) -> ();
+    /// Called by each loop at every report interval, and once more when it ends.
+    fn report(&self, progress: &Progress) -> ();
+
+    /// Whether the reporter reads `Progress::latency_histogram`, which is only
+    /// allocated for the reporters that do.
+    fn reports_percentiles(&self) -> bool {
+        false
+    }
+
+    /// Called before a step starts, with its target rate in streams per second.
+    fn start_step(&self, _target_rate: u64) {}
+
+    /// Called once every loop of a step ended, with the summary of the step.
+    fn finish_step(&self, _summary: &StepSummary) {}
 }
 
 impl ProgressReporter for ProgressBar {
-    fn report(&self, amount: usize) {
-        self.inc(amount.try_into().unwrap());
+    fn report(&self, progress: &Progress) {
+        self.inc(progress.succeeded());
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicI64, AtomicU32},
+    };
 
     use super::*;
+    use crate::app::{
+        sink::{self, tests::Collected},
+        summary,
+    };
 
     const WORKER_COUNT: usize = 8;
 
     #[derive(Debug, Clone, Default)]
     struct StubProgressReporter {
         pub amount: Arc<AtomicU32>,
+        pub in_flight: Arc<AtomicI64>,
+        pub percentiles: bool,
+        pub histograms: Arc<AtomicU32>,
     }
 
     impl ProgressReporter for StubProgressReporter {
-        fn report(&self, amount: usize) {
+        fn report(&self, progress: &Progress) {
+            let _ = self
+                .in_flight
+                .fetch_add(progress.in_flight, Ordering::Relaxed);
+            if progress.latency_histogram.is_some() {
+                let _ = self.histograms.fetch_add(1, Ordering::Relaxed);
+            }
             let _ = self
                 .amount
-                .fetch_add(amount.try_into().unwrap(), Ordering::Relaxed);
+                .fetch_add(progress.succeeded().try_into().unwrap(), Ordering::Relaxed);
+        }
+
+        fn reports_percentiles(&self) -> bool {
+            self.percentiles
         }
     }
 
@@ -322,9 +791,9 @@ mod tests {
         }
     }
     impl Worker for Arc<StubWorker> {
-        async fn run(&self) -> Result<()> {
+        async fn run(&self) -> Result<StreamStats> {
             let _ = self.triggers.fetch_add(1, Ordering::Relaxed);
-            Ok(())
+            Ok(StreamStats::default())
         }
     }
 
@@ -344,12 +813,12 @@ mod tests {
         }
     }
     impl Worker for Arc<ErrorWorker> {
-        async fn run(&self) -> Result<()> {
+        async fn run(&self) -> Result<StreamStats> {
             let _ = self.triggers.fetch_add(1, Ordering::Relaxed);
             if self.should_error {
                 Err(Error::ConcurrencyMustBeGreaterThanZero)
             } else {
-                Ok(())
+                Ok(StreamStats::default())
             }
         }
     }
@@ -370,19 +839,62 @@ mod tests {
         }
     }
     impl Worker for Arc<SlowWorker> {
-        async fn run(&self) -> Result<()> {
+        async fn run(&self) -> Result<StreamStats> {
             let _ = self.triggers.fetch_add(1, Ordering::Relaxed);
             tokio::time::sleep(self.delay).await;
-            Ok(())
+            Ok(StreamStats::default())
+        }
+    }
+
+    /// A worker whose streams fail, alternately with `INTERNAL` and `DEADLINE_EXCEEDED`.
+    #[derive(Debug, Default)]
+    struct FailingWorker {
+        pub triggers: AtomicU32,
+    }
+    impl Worker for Arc<FailingWorker> {
+        async fn run(&self) -> Result<StreamStats> {
+            let code = match self.triggers.fetch_add(1, Ordering::Relaxed) % 2 {
+                0 => Code::Internal,
+                _ => Code::DeadlineExceeded,
+            };
+            Ok(StreamStats {
+                outcome: StreamOutcome::Failed,
+                error: Some(code),
+                ..StreamStats::default()
+            })
+        }
+    }
+
+    /// A worker that spends `connect_delay` opening a connection, then `delay` on the stream.
+    #[derive(Debug)]
+    struct ConnectingWorker {
+        pub connect_delay: Duration,
+        pub delay: Duration,
+    }
+    impl Worker for Arc<ConnectingWorker> {
+        async fn run(&self) -> Result<StreamStats> {
+            tokio::time::sleep(self.connect_delay).await;
+            tokio::time::sleep(self.delay).await;
+            Ok(StreamStats {
+                connection_wait: self.connect_delay,
+                new_connection: true,
+                tls_handshake: None,
+                ..StreamStats::default()
+            })
         }
     }
 
     #[tokio::test(start_paused = true)]
     async fn test_worker_scheduler() {
         let w = workers();
-        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL).unwrap();
+        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL, Recording::default()).unwrap();
         let durations = scheduler
-            .run(interval(), timeout(), &StubProgressReporter::default())
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -394,7 +906,7 @@ mod tests {
         assert_eq!(calls, vec![13, 13, 13, 13, 12, 12, 12, 12,]);
         let mut result = durations
             .iter()
-            .map(|r| r.durations.len())
+            .map(|r| r.histogram.len())
             .collect::<Vec<_>>();
         result.sort_unstable(); // NOTE: Order is not guaranteed, so we sort it.
 
@@ -403,7 +915,7 @@ mod tests {
 
     #[test]
     fn test_worker_scheduler_new_with_empty_workers() {
-        let result = Scheduler::<Arc<StubWorker>>::new(&[], REPORT_INTERVAL);
+        let result = Scheduler::<Arc<StubWorker>>::new(&[], REPORT_INTERVAL, Recording::default());
         assert!(result.is_err());
         match result.unwrap_err() {
             Error::ConcurrencyMustBeGreaterThanZero => {}
@@ -414,7 +926,7 @@ mod tests {
     #[test]
     fn test_worker_scheduler_new_with_single_worker() {
         let workers = vec![Arc::new(StubWorker::new(0))];
-        let result = Scheduler::new(&workers, REPORT_INTERVAL);
+        let result = Scheduler::new(&workers, REPORT_INTERVAL, Recording::default());
         assert!(result.is_ok());
         // Test that we can create a scheduler with a single worker successfully
         // The behavior is verified by the fact that new() returns Ok
@@ -425,7 +937,7 @@ mod tests {
         // Test that we can create a scheduler with many workers successfully
         let workers: Vec<Arc<StubWorker>> =
             (0..1000).map(|i| Arc::new(StubWorker::new(i))).collect();
-        let result = Scheduler::new(&workers, REPORT_INTERVAL);
+        let result = Scheduler::new(&workers, REPORT_INTERVAL, Recording::default());
         assert!(result.is_ok());
         // The behavior is verified by the fact that new() returns Ok
     }
@@ -433,7 +945,7 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn test_worker_scheduler_handles_fast_intervals() {
         let w = workers();
-        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL).unwrap();
+        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL, Recording::default()).unwrap();
         let short_interval = Duration::from_millis(10);
         let short_timeout = Duration::from_millis(100);
 
@@ -442,6 +954,7 @@ mod tests {
                 short_interval,
                 short_timeout,
                 &StubProgressReporter::default(),
+                &StepSinks::default(),
             )
             .await
             .unwrap();
@@ -457,7 +970,7 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn test_worker_scheduler_handles_slow_intervals() {
         let w = workers();
-        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL).unwrap();
+        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL, Recording::default()).unwrap();
         let long_interval = Duration::from_secs(1);
         let short_timeout = Duration::from_millis(500);
 
@@ -466,6 +979,7 @@ mod tests {
                 long_interval,
                 short_timeout,
                 &StubProgressReporter::default(),
+                &StepSinks::default(),
             )
             .await
             .unwrap();
@@ -484,9 +998,11 @@ mod tests {
             .map(|i| Arc::new(ErrorWorker::new(i, i == 2))) // Worker 2 will error
             .collect();
 
-        let mut scheduler = Scheduler::new(&error_workers, REPORT_INTERVAL).unwrap();
+        let mut scheduler =
+            Scheduler::new(&error_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let reporter = StubProgressReporter::default();
         let result = scheduler
-            .run(interval(), timeout(), &StubProgressReporter::default())
+            .run(interval(), timeout(), &reporter, &StepSinks::default())
             .await;
 
         // Should propagate errors from workers
@@ -495,6 +1011,11 @@ mod tests {
             Error::ConcurrencyMustBeGreaterThanZero => {}
             _ => panic!("Expected ConcurrencyMustBeGreaterThanZero error"),
         }
+        assert_eq!(
+            reporter.in_flight.load(Ordering::Relaxed),
+            0,
+            "The failed stream is no longer in flight"
+        );
     }
 
     #[tokio::test(start_paused = true)]
@@ -510,9 +1031,15 @@ mod tests {
             })
             .collect();
 
-        let mut scheduler = Scheduler::new(&slow_workers, REPORT_INTERVAL).unwrap();
+        let mut scheduler =
+            Scheduler::new(&slow_workers, REPORT_INTERVAL, Recording::default()).unwrap();
         let durations = scheduler
-            .run(interval(), timeout(), &StubProgressReporter::default())
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -526,12 +1053,18 @@ mod tests {
             .map(|i| Arc::new(SlowWorker::new(i, Duration::from_millis(200)))) // Very slow workers
             .collect();
 
-        let mut scheduler = Scheduler::new(&slow_workers, REPORT_INTERVAL).unwrap();
+        let mut scheduler =
+            Scheduler::new(&slow_workers, REPORT_INTERVAL, Recording::default()).unwrap();
         let short_interval = Duration::from_millis(50); // Fast interval
         let timeout = Duration::from_secs(1);
 
         let durations = scheduler
-            .run(short_interval, timeout, &StubProgressReporter::default())
+            .run(
+                short_interval,
+                timeout,
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -540,10 +1073,53 @@ mod tests {
         assert!(durations.len() < 100); // Fewer durations due to saturation
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_scheduler_tracks_in_flight_peak() {
+        let slow_workers = vec![Arc::new(SlowWorker::new(0, Duration::from_millis(175)))];
+
+        let mut scheduler =
+            Scheduler::new(&slow_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let results = scheduler
+            .run(
+                Duration::from_millis(50),
+                Duration::from_secs(1),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
+            .await
+            .unwrap();
+
+        // NOTE: A stream starts every 50ms and lasts 175ms, so 4 streams overlap.
+        assert_eq!(results[0].in_flight_peak, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_scheduler_in_flight_peak_spans_the_loops() {
+        let slow_workers = (0..2)
+            .map(|id| Arc::new(SlowWorker::new(id, Duration::from_millis(120))))
+            .collect::<Vec<_>>();
+
+        let mut scheduler =
+            Scheduler::new(&slow_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let results = scheduler
+            .run(
+                Duration::from_millis(50),
+                Duration::from_secs(1),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
+            .await
+            .unwrap();
+
+        // NOTE: Each loop has 2 streams in flight at most, but not at the same time.
+        let merged = WorkerResult::merge(results, Recording::default());
+        assert_eq!(merged.in_flight_peak, 3);
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_worker_scheduler_respects_timeout() {
         let w = workers();
-        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL).unwrap();
+        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL, Recording::default()).unwrap();
 
         // Use a very short timeout to test timeout behavior
         let very_short_timeout = Duration::from_millis(1);
@@ -552,6 +1128,7 @@ mod tests {
                 interval(),
                 very_short_timeout,
                 &StubProgressReporter::default(),
+                &StepSinks::default(),
             )
             .await
             .unwrap();
@@ -560,6 +1137,111 @@ mod tests {
         assert!(durations.len() < 10);
     }
 
+    /// Durations spread over several orders of magnitude, from 10µs to 1s.
+    fn spread_durations() -> Vec<Duration> {
+        let mut rng = fastrand::Rng::with_seed(42);
+        (0..100_000)
+            .map(|_| {
+                let exponent = rng.f64().mul_add(5.0, 4.0);
+                Duration::from_secs_f64(10_f64.powf(exponent) / 1e9)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_histogram_percentiles_match_exact_percentiles() {
+        let mut durations = spread_durations();
+        durations.sort_unstable();
+
+        for precision in 1..=5 {
+            let mut histogram = Recording { precision }.histogram();
+            for duration in &durations {
+                record(&mut histogram, *duration);
+            }
+
+            let tolerance = 10_f64.powi(-i32::from(precision));
+            for quantile_permyriad in [5_000, 9_000, 9_900, 9_990, 9_999, 10_000] {
+                // NOTE: The exact percentile is the smallest duration with at least `quantile` of the durations below or at it.
+                let rank = durations.len() * quantile_permyriad / 10_000;
+                let exact = durations[rank.max(1) - 1];
+                let quantile = f64::from(u32::try_from(quantile_permyriad).unwrap()) / 1e4;
+                let approximate = Duration::from_nanos(histogram.value_at_quantile(quantile));
+
+                let error = approximate.abs_diff(exact).as_secs_f64() / exact.as_secs_f64();
+                assert!(
+                    error <= tolerance,
+                    "p{quantile} with precision {precision}: expected {exact:?}, got {approximate:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merged_histograms_match_single_histogram() {
+        let durations = spread_durations();
+
+        let mut single = Recording::default().histogram();
+        let mut merged = Recording::default().histogram();
+        for chunk in durations.chunks(7_000) {
+            let mut histogram = Recording::default().histogram();
+            for duration in chunk {
+                record(&mut single, *duration);
+                record(&mut histogram, *duration);
+            }
+            merged.add(&histogram).unwrap();
+        }
+
+        assert_eq!(merged, single);
+    }
+
+    #[test]
+    fn test_merged_results_sum_the_failures_by_code() {
+        let results = [
+            (Code::Unavailable, 2),
+            (Code::Unavailable, 1),
+            (Code::DeadlineExceeded, 1),
+        ]
+        .map(|(code, count)| WorkerResult {
+            failures: HashMap::from([(code, count)]),
+            ..WorkerResult::new(Recording::default())
+        });
+
+        let merged = WorkerResult::merge(results, Recording::default());
+
+        assert_eq!(
+            merged.failures,
+            HashMap::from([(Code::Unavailable, 3), (Code::DeadlineExceeded, 1)])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_scheduler_counts_the_failures_by_code() {
+        let failing_workers = vec![Arc::new(FailingWorker::default())];
+
+        let mut scheduler =
+            Scheduler::new(&failing_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let results = scheduler
+            .run(
+                Duration::from_millis(100),
+                Duration::from_millis(950),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
+            .await
+            .unwrap();
+
+        let merged = WorkerResult::merge(results, Recording::default());
+        assert_eq!(merged.failed_streams, 10);
+        assert_eq!(merged.histogram.len(), 0);
+        assert_eq!(
+            summary::failures_by_code(&merged.failures),
+            BTreeMap::from([
+                ("DeadlineExceeded".to_owned(), 5),
+                ("Internal".to_owned(), 5)
+            ])
+        );
+    }
+
     #[test]
     fn test_worker_trait_implementation() {
         // Test that Worker trait is implemented correctly
@@ -574,9 +1256,15 @@ mod tests {
         let many_workers: Vec<Arc<StubWorker>> =
             (0..100).map(|i| Arc::new(StubWorker::new(i))).collect();
 
-        let mut scheduler = Scheduler::new(&many_workers, REPORT_INTERVAL).unwrap();
+        let mut scheduler =
+            Scheduler::new(&many_workers, REPORT_INTERVAL, Recording::default()).unwrap();
         let durations = scheduler
-            .run(interval(), timeout(), &StubProgressReporter::default())
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -587,9 +1275,14 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn test_worker_scheduler_work_distribution() {
         let w = workers();
-        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL).unwrap();
+        let mut scheduler = Scheduler::new(&w, REPORT_INTERVAL, Recording::default()).unwrap();
         let durations = scheduler
-            .run(interval(), timeout(), &StubProgressReporter::default())
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -599,7 +1292,7 @@ mod tests {
 
         // Verify the expected total durations
         assert_eq!(
-            durations.iter().map(|r| r.durations.len()).sum::<usize>(),
+            durations.iter().map(|r| r.histogram.len()).sum::<u64>(),
             100
         );
     }
@@ -611,7 +1304,8 @@ mod tests {
             .map(|i| Arc::new(SlowWorker::new(i, Duration::from_millis(150)))) // 150ms per task
             .collect();
 
-        let mut scheduler = Scheduler::new(&slow_workers, REPORT_INTERVAL).unwrap();
+        let mut scheduler =
+            Scheduler::new(&slow_workers, REPORT_INTERVAL, Recording::default()).unwrap();
         let short_interval = Duration::from_millis(50); // 50ms interval
         let test_timeout = Duration::from_secs(1); // 1 second test
 
@@ -620,6 +1314,7 @@ mod tests {
                 short_interval,
                 test_timeout,
                 &StubProgressReporter::default(),
+                &StepSinks::default(),
             )
             .await
             .unwrap();
@@ -642,7 +1337,7 @@ mod tests {
         );
 
         // Verify that the number of completed tasks is reasonable for the time period
-        let total_completed_tasks: usize = durations.iter().map(|r| r.durations.len()).sum();
+        let total_completed_tasks: u64 = durations.iter().map(|r| r.histogram.len()).sum();
         assert!(
             total_completed_tasks >= 4,
             "Should complete at least 4 tasks over the test period"
@@ -667,15 +1362,109 @@ mod tests {
 
         // Verify that some tasks completed (indicating concurrent execution)
         // If tasks were running sequentially, very few would complete in 1 second
-        let completed_tasks_per_worker: Vec<usize> =
-            durations.iter().map(|r| r.durations.len()).collect();
-        let total_completed = completed_tasks_per_worker.iter().sum::<usize>();
+        let completed_tasks_per_worker: Vec<u64> =
+            durations.iter().map(|r| r.histogram.len()).collect();
+        let total_completed = completed_tasks_per_worker.iter().sum::<u64>();
         assert!(
             total_completed >= 4,
             "Should complete at least 4 tasks total, but only completed {total_completed}",
         );
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_scheduler_excludes_connect_time_from_durations() {
+        let connecting_workers = vec![Arc::new(ConnectingWorker {
+            connect_delay: Duration::from_millis(30),
+            delay: Duration::from_millis(20),
+        })];
+
+        let collected = Collected::default();
+        let (sender, writer) = sink::spawn({
+            let collected = collected.clone();
+            move || Ok(collected)
+        });
+        let sinks = StepSinks {
+            durations: Some(sender),
+            ..StepSinks::default()
+        };
+        let mut scheduler =
+            Scheduler::new(&connecting_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let results = scheduler
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &sinks,
+            )
+            .await
+            .unwrap();
+        drop(sinks);
+        writer.finish().await.unwrap();
+
+        let durations = collected.rows();
+        let connect = WorkerResult::merge(results, Recording::default()).connect;
+
+        assert!(!durations.is_empty());
+        assert_eq!(u64::try_from(durations.len()).unwrap(), connect.len());
+        assert!(
+            durations.iter().all(|d| *d == Duration::from_millis(20)),
+            "Stream durations should not include the connect time"
+        );
+        for nanos in [connect.min(), connect.max()] {
+            assert!(connect.equivalent(nanos, 30_000_000));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_scheduler_keeps_stream_records() {
+        let connecting_workers = vec![Arc::new(ConnectingWorker {
+            connect_delay: Duration::from_millis(30),
+            delay: Duration::from_millis(20),
+        })];
+
+        let collected = Collected::default();
+        let (sender, writer) = sink::spawn({
+            let collected = collected.clone();
+            move || Ok(collected)
+        });
+        let sinks = StepSinks {
+            records: Some(sender),
+            ..StepSinks::default()
+        };
+        let mut scheduler =
+            Scheduler::new(&connecting_workers, REPORT_INTERVAL, Recording::default()).unwrap();
+        let results = scheduler
+            .run(
+                interval(),
+                timeout(),
+                &StubProgressReporter::default(),
+                &sinks,
+            )
+            .await
+            .unwrap();
+        drop(sinks);
+        writer.finish().await.unwrap();
+
+        let records = collected.rows();
+        assert!(!records.is_empty());
+        assert_eq!(
+            u64::try_from(records.len()).unwrap(),
+            results[0].histogram.len()
+        );
+        for (record, next) in records.iter().zip(&records[1..]) {
+            assert_eq!(
+                next.intended_start_ns - record.intended_start_ns,
+                80_000_000
+            );
+        }
+        for record in &records {
+            assert_eq!(record.end_ns - record.start_ns, 50_000_000);
+            assert_eq!(record.connection_wait_ns, 30_000_000);
+            assert_eq!(record.duration_ns, 20_000_000);
+            assert_eq!(record.outcome, StreamOutcome::Completed);
+        }
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_progress_reporter() {
         // Use fast workers that complete quickly relative to the interval
@@ -685,7 +1474,8 @@ mod tests {
         // Very small report interval to make sure that the stub has the latest value.
         let reporter_interval = Duration::from_millis(10);
 
-        let mut scheduler = Scheduler::new(&fast_workers, reporter_interval).unwrap();
+        let mut scheduler =
+            Scheduler::new(&fast_workers, reporter_interval, Recording::default()).unwrap();
         let progress_reporter = StubProgressReporter::default();
 
         // Use a longer interval and timeout so tasks have time to complete and be reported
@@ -693,7 +1483,12 @@ mod tests {
         let test_timeout = Duration::from_millis(2100);
 
         let _durations = scheduler
-            .run(test_interval, test_timeout, &progress_reporter)
+            .run(
+                test_interval,
+                test_timeout,
+                &progress_reporter,
+                &StepSinks::default(),
+            )
             .await
             .unwrap();
 
@@ -710,6 +1505,32 @@ mod tests {
             "Progress reporter should report exactly the same number of completed tasks"
         );
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency_histograms_are_only_kept_for_reporters_of_percentiles() {
+        for percentiles in [false, true] {
+            let workers = [Arc::new(StubWorker::new(0))];
+            let mut scheduler =
+                Scheduler::new(&workers, REPORT_INTERVAL, Recording::default()).unwrap();
+            let progress_reporter = StubProgressReporter {
+                percentiles,
+                ..StubProgressReporter::default()
+            };
+
+            let _results = scheduler
+                .run(
+                    Duration::from_millis(100),
+                    Duration::from_secs(1),
+                    &progress_reporter,
+                    &StepSinks::default(),
+                )
+                .await
+                .unwrap();
+
+            let histograms = progress_reporter.histograms.load(Ordering::Relaxed);
+            assert_eq!(histograms > 0, percentiles, "percentiles: {percentiles}");
+        }
+    }
 }
 
 // TODO: Test requests sent.
@@ -0,0 +1,468 @@
+use std::{io, num::NonZeroUsize, sync::Arc, thread, time::Duration};
+
+use tokio::{
+    runtime::{Builder, Runtime},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::app::{
+    error::{Error, Result},
+    scheduler::{
+        InFlight, LoopParams, ProgressReporter, Recording, WorkerResult, run_loop, size_hint,
+    },
+    sink::StepSinks,
+    worker::Worker,
+};
+
+/// A scheduler that runs each loop on its own OS thread, pinned to a CPU core,
+/// inside a current-thread Tokio runtime.
+///
+/// Unlike `Scheduler`, the loops do not share a runtime: there is no work stealing
+/// between them and no barrier to wait on before starting, which removes sources
+/// of jitter at high rates. Each shard creates its own worker (and so its own
+/// connections) on its own runtime, and the results of all shards are merged at
+/// the end of each run.
+#[derive(Debug)]
+pub(crate) struct ShardedScheduler<P> {
+    shards: Vec<mpsc::UnboundedSender<RunCommand<P>>>,
+    reporter_interval: Duration,
+    recording: Recording,
+}
+
+/// Asks a shard to run its loop once, and to send back the result.
+#[derive(Debug)]
+struct RunCommand<P> {
+    /// When the loop is cancelled, the same for every shard.
+    deadline: Instant,
+    params: LoopParams,
+    progress_reporter: P,
+    reply: oneshot::Sender<Result<WorkerResult>>,
+}
+
+impl<P> ShardedScheduler<P>
+where
+    P: ProgressReporter,
+{
+    /// Starts `shard_count` shards, each creating its worker with `create_worker`.
+    ///
+    /// `create_worker` is called on the shard's thread with the index of the shard,
+    /// so the worker is bound to the shard's runtime. Returns once every shard is
+    /// ready, or with the first error encountered while creating a worker.
+    pub(crate) async fn new<F, Fut, W>(
+        shard_count: NonZeroUsize,
+        reporter_interval: Duration,
+        recording: Recording,
+        create_worker: F,
+    ) -> Result<Self>
+    where
+        F: Fn(usize) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<W>>,
+        W: Worker + Clone + 'static,
+    {
+        Self::with_runtime(
+            shard_count,
+            reporter_interval,
+            recording,
+            || Builder::new_current_thread().enable_all().build(),
+            create_worker,
+        )
+        .await
+    }
+
+    /// Like `new`, with the runtime of each shard built by `runtime`.
+    async fn with_runtime<F, Fut, W>(
+        shard_count: NonZeroUsize,
+        reporter_interval: Duration,
+        recording: Recording,
+        runtime: fn() -> io::Result<Runtime>,
+        create_worker: F,
+    ) -> Result<Self>
+    where
+        F: Fn(usize) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<W>>,
+        W: Worker + Clone + 'static,
+    {
+        let core_ids = core_affinity::get_core_ids().unwrap_or_default();
+
+        let mut shards = Vec::with_capacity(shard_count.get());
+        let mut ready = Vec::with_capacity(shard_count.get());
+        for idx in 0..shard_count.get() {
+            let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+            let (ready_tx, ready_rx) = oneshot::channel();
+
+            // NOTE: If there are more shards than cores, some cores are shared.
+            let core_id = core_ids.get(idx % core_ids.len().max(1)).copied();
+            let create_worker = create_worker.clone();
+
+            let _handle = thread::Builder::new()
+                .name(format!("shard-{idx}"))
+                .spawn(move || {
+                    if let Some(core_id) = core_id {
+                        let _ = core_affinity::set_for_current(core_id);
+                    }
+
+                    match runtime() {
+                        Ok(runtime) => {
+                            runtime.block_on(run_shard(create_worker(idx), ready_tx, commands_rx));
+                        }
+                        Err(e) => drop(ready_tx.send(Err(Error::FailedToBuildRuntime(e)))),
+                    }
+                })
+                .map_err(Error::FailedToSpawnShard)?;
+
+            shards.push(commands_tx);
+            ready.push(ready_rx);
+        }
+
+        for ready in ready {
+            ready.await.map_err(|_| Error::ShardStopped)??;
+        }
+
+        Ok(Self {
+            shards,
+            reporter_interval,
+            recording,
+        })
+    }
+
+    /// Runs the loop of every shard at a fixed overall rate until the timeout elapses.
+    ///
+    /// The loops start at a staggered offset from a start shared by every shard,
+    /// exactly like `Scheduler::run`, but without waiting on each other. Tokio
+    /// instants use the same monotonic clock in every runtime. Returns one result per
+    /// shard.
+    pub(crate) async fn run(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+        progress_reporter: &P,
+        sinks: &StepSinks,
+    ) -> Result<Vec<WorkerResult>> {
+        let shard_count =
+            u32::try_from(self.shards.len()).map_err(Error::ConcurrencyMustBeLessThanU32Max)?;
+
+        // "Round robin" the shards by multiplying the interval by the shard count.
+        let loop_interval = interval
+            .checked_mul(shard_count)
+            .expect("duration must not overflow");
+        let size_hint = size_hint(timeout, loop_interval)?;
+
+        let in_flight = Arc::new(InFlight::default());
+        let mut replies = Vec::with_capacity(self.shards.len());
+        let start = Instant::now();
+        let mut offset = Duration::ZERO;
+        for (task, shard) in self.shards.iter().enumerate() {
+            offset += interval;
+
+            let (reply, reply_rx) = oneshot::channel();
+            shard
+                .send(RunCommand {
+                    deadline: start + timeout,
+                    params: LoopParams {
+                        task,
+                        start: start + offset,
+                        barrier: None,
+                        interval: loop_interval,
+                        cancelation_token: CancellationToken::new(),
+                        size_hint,
+                        reporter_interval: self.reporter_interval,
+                        recording: self.recording,
+                        in_flight: in_flight.clone(),
+                        sinks: sinks.clone(),
+                    },
+                    progress_reporter: progress_reporter.clone(),
+                    reply,
+                })
+                .map_err(|_| Error::ShardStopped)?;
+            replies.push(reply_rx);
+        }
+
+        let mut results = Vec::with_capacity(replies.len());
+        for reply in replies {
+            results.push(reply.await.map_err(|_| Error::ShardStopped)??);
+        }
+
+        Ok(results)
+    }
+}
+
+/// The share of `total` of a shard, split as evenly as possible: the first
+/// `total % shard_count` shards get one more.
+pub(crate) fn shard_share(total: usize, shard_count: NonZeroUsize, shard: usize) -> usize {
+    total / shard_count.get() + usize::from(shard < total % shard_count.get())
+}
+
+/// Body of a shard thread: creates the worker, then runs the loop each time it is asked to.
+/// The shard stops when the scheduler is dropped.
+async fn run_shard<P, W>(
+    create_worker: impl Future<Output = Result<W>>,
+    ready: oneshot::Sender<Result<()>>,
+    mut commands: mpsc::UnboundedReceiver<RunCommand<P>>,
+) where
+    P: ProgressReporter,
+    W: Worker + Clone,
+{
+    let worker = match create_worker.await {
+        Ok(worker) => worker,
+        Err(e) => {
+            drop(ready.send(Err(e)));
+            return;
+        }
+    };
+    drop(ready.send(Ok(())));
+
+    while let Some(RunCommand {
+        deadline,
+        params,
+        progress_reporter,
+        reply,
+    }) = commands.recv().await
+    {
+        let cancelation_token = params.cancelation_token.clone();
+        let _handle = tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            cancelation_token.cancel();
+        });
+
+        let result = run_loop(params, worker.clone(), progress_reporter).await;
+        drop(reply.send(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::app::{
+        scheduler::{Progress, REPORT_INTERVAL, Scheduler},
+        worker::StreamStats,
+    };
+
+    #[derive(Debug, Clone, Default)]
+    struct StubProgressReporter {
+        pub amount: Arc<AtomicUsize>,
+    }
+
+    impl ProgressReporter for StubProgressReporter {
+        fn report(&self, progress: &Progress) {
+            let _ = self
+                .amount
+                .fetch_add(progress.succeeded().try_into().unwrap(), Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct StubWorker {
+        pub triggers: Arc<AtomicU32>,
+    }
+
+    impl Worker for StubWorker {
+        async fn run(&self) -> Result<StreamStats> {
+            let _ = self.triggers.fetch_add(1, Ordering::Relaxed);
+            Ok(StreamStats::default())
+        }
+    }
+
+    fn shard_count() -> NonZeroUsize {
+        NonZeroUsize::new(4).unwrap()
+    }
+
+    /// A shard runtime whose clock only advances when the shard is idle.
+    fn paused_runtime() -> io::Result<Runtime> {
+        Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_sharded_scheduler_merges_shard_results() {
+        let worker = StubWorker::default();
+        let shard_worker = worker.clone();
+        let mut scheduler = ShardedScheduler::with_runtime(
+            shard_count(),
+            REPORT_INTERVAL,
+            Recording::default(),
+            paused_runtime,
+            move |_| {
+                let worker = shard_worker.clone();
+                async move { Ok(worker) }
+            },
+        )
+        .await
+        .unwrap();
+
+        let results = scheduler
+            .run(
+                Duration::from_millis(10),
+                Duration::from_millis(505),
+                &StubProgressReporter::default(),
+                &StepSinks::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 4, "Should have one result per shard");
+
+        let request_sent = results.iter().map(|r| r.request_sent).sum::<u64>();
+        let completed = results.iter().map(|r| r.histogram.len()).sum::<u64>();
+        let triggers = worker.triggers.load(Ordering::Relaxed);
+        assert_eq!(u64::from(triggers), request_sent);
+        assert_eq!(completed, u64::from(triggers));
+
+        // NOTE: The shards start 10, 20, 30 and 40 ms in and tick every 40 ms.
+        assert_eq!(request_sent, 50);
+    }
+
+    #[test]
+    fn test_shard_share_splits_the_remainder() {
+        let shares = (0..4)
+            .map(|shard| shard_share(10, shard_count(), shard))
+            .collect::<Vec<_>>();
+        assert_eq!(shares, [3, 3, 2, 2]);
+        assert_eq!(shard_share(8, shard_count(), 3), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sharded_scheduler_can_run_several_times() {
+        let mut scheduler = ShardedScheduler::with_runtime(
+            shard_count(),
+            REPORT_INTERVAL,
+            Recording::default(),
+            paused_runtime,
+            async |_| Ok(StubWorker::default()),
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..3 {
+            // NOTE: Paused runtimes each have their own clock, and those of the shards
+            // advanced to the end of the previous run: the shared start must be later.
+            tokio::time::advance(Duration::from_secs(1)).await;
+            let results = scheduler
+                .run(
+                    Duration::from_millis(10),
+                    Duration::from_millis(100),
+                    &StubProgressReporter::default(),
+                    &StepSinks::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(results.len(), 4);
+            let request_sent = results.iter().map(|r| r.request_sent).sum::<u64>();
+            assert_eq!(request_sent, 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sharded_scheduler_creates_one_worker_per_shard() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let shard_created = created.clone();
+        let _scheduler = ShardedScheduler::<StubProgressReporter>::new(
+            shard_count(),
+            REPORT_INTERVAL,
+            Recording::default(),
+            move |_| {
+                let _ = shard_created.fetch_add(1, Ordering::Relaxed);
+                async { Ok(StubWorker::default()) }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(created.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_scheduler_propagates_worker_creation_errors() {
+        let result = ShardedScheduler::<StubProgressReporter>::new(
+            shard_count(),
+            REPORT_INTERVAL,
+            Recording::default(),
+            async |idx| {
+                if idx == 2 {
+                    Err(Error::ConcurrencyMustBeGreaterThanZero)
+                } else {
+                    Ok(StubWorker::default())
+                }
+            },
+        )
+        .await;
+
+        match result.unwrap_err() {
+            Error::ConcurrencyMustBeGreaterThanZero => {}
+            _ => panic!("Expected ConcurrencyMustBeGreaterThanZero error"),
+        }
+    }
+
+    /// Measures the highest rate each execution mode can sustain with a no-op worker,
+    /// i.e. the overhead of the load tester itself.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture bench_max_rate`.
+    #[test]
+    #[ignore = "benchmark, run explicitly in release mode"]
+    fn bench_max_rate() {
+        const STEP: Duration = Duration::from_secs(2);
+        let parallelism = thread::available_parallelism().unwrap();
+
+        for target in [100_000_u32, 250_000, 500_000, 1_000_000, 2_000_000] {
+            let interval = Duration::from_secs(1) / target;
+
+            let multi_thread = Builder::new_multi_thread()
+                .worker_threads(parallelism.get())
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let workers = vec![StubWorker::default(); parallelism.get()];
+                    let mut scheduler =
+                        Scheduler::new(&workers, REPORT_INTERVAL, Recording::default()).unwrap();
+                    scheduler
+                        .run(
+                            interval,
+                            STEP,
+                            &StubProgressReporter::default(),
+                            &StepSinks::default(),
+                        )
+                        .await
+                        .unwrap()
+                });
+
+            let thread_per_core = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let mut scheduler = ShardedScheduler::new(
+                        parallelism,
+                        REPORT_INTERVAL,
+                        Recording::default(),
+                        async |_| Ok(StubWorker::default()),
+                    )
+                    .await
+                    .unwrap();
+                    scheduler
+                        .run(
+                            interval,
+                            STEP,
+                            &StubProgressReporter::default(),
+                            &StepSinks::default(),
+                        )
+                        .await
+                        .unwrap()
+                });
+
+            let rate = |results: &[WorkerResult]| {
+                results.iter().map(|r| r.request_sent).sum::<u64>() / STEP.as_secs()
+            };
+            println!(
+                "target: {target} req/s, multi-thread: {} req/s, thread-per-core: {} req/s",
+                rate(&multi_thread),
+                rate(&thread_per_core),
+            );
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fmt, io, mem,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+use crate::app::records::StreamRecord;
+
+/// Number of rows a loop buffers before sending them to the writer.
+const BATCH_ROWS: usize = 1_024;
+
+/// Number of batches waiting to be written before the loops drop their rows, rather
+/// than wait for the writer and start their streams late.
+const PENDING_BATCHES: usize = 64;
+
+/// Writes rows one at a time, e.g. to a file, on its own thread.
+pub(crate) trait RowWriter<R> {
+    fn write(&mut self, row: &R) -> io::Result<()>;
+    /// Writes what is still buffered, e.g. the last Parquet row group.
+    fn finish(self) -> io::Result<()>;
+}
+
+/// Sends rows to a writer spawned with `spawn`.
+pub(crate) struct RowSender<R> {
+    batches: mpsc::Sender<Vec<R>>,
+    /// The rows dropped because the writer was too far behind.
+    dropped: Arc<AtomicU64>,
+}
+
+impl<R> Clone for RowSender<R> {
+    fn clone(&self) -> Self {
+        Self {
+            batches: self.batches.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<R> fmt::Debug for RowSender<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RowSender").finish()
+    }
+}
+
+/// A writer spawned with `spawn`, which ends once every `RowSender` is dropped.
+#[derive(Debug)]
+pub(crate) struct RowWriterTask {
+    done: oneshot::Receiver<io::Result<()>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl RowWriterTask {
+    /// Waits until every row is written, and returns the first error, if any.
+    pub(crate) async fn finish(self) -> io::Result<()> {
+        self.done
+            .await
+            .map_err(|_| io::Error::other("the row writer panicked"))?
+    }
+
+    /// The rows dropped so far because the writer was too far behind.
+    pub(crate) fn dropped_rows(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Spawns the writer created by `create` on its own thread, so that the rows are
+/// written as they come rather than held in memory until the end of the step.
+///
+/// Once the writer fails, the rows sent to it are dropped, and the error is
+/// returned by `RowWriterTask::finish`.
+pub(crate) fn spawn<R, W>(
+    create: impl FnOnce() -> io::Result<W> + Send + 'static,
+) -> (RowSender<R>, RowWriterTask)
+where
+    R: Send + 'static,
+    W: RowWriter<R>,
+{
+    let (sender, mut receiver) = mpsc::channel::<Vec<R>>(PENDING_BATCHES);
+    let (done, task) = oneshot::channel();
+    let dropped = Arc::<AtomicU64>::default();
+    // NOTE: Not a blocking task of the runtime, as it runs for the whole step.
+    let _handle = thread::spawn(move || {
+        let write = || {
+            let mut writer = create()?;
+            while let Some(rows) = receiver.blocking_recv() {
+                for row in &rows {
+                    writer.write(row)?;
+                }
+            }
+            writer.finish()
+        };
+        drop(done.send(write()));
+    });
+
+    (
+        RowSender {
+            batches: sender,
+            dropped: dropped.clone(),
+        },
+        RowWriterTask {
+            done: task,
+            dropped,
+        },
+    )
+}
+
+/// The rows of a loop, sent to their writer in batches.
+#[derive(Debug)]
+pub(crate) struct RowBuffer<R> {
+    rows: Vec<R>,
+    sender: RowSender<R>,
+}
+
+impl<R> RowBuffer<R> {
+    /// Creates an empty buffer, for a loop expected to send about `size_hint` rows.
+    pub(crate) fn new(sender: RowSender<R>, size_hint: usize) -> Self {
+        Self {
+            rows: Vec::with_capacity(size_hint.min(BATCH_ROWS)),
+            sender,
+        }
+    }
+
+    /// Buffers a row, and sends the batch once it is full. Never waits for the
+    /// writer: the batch is dropped and counted if the writer is too far behind.
+    pub(crate) fn push(&mut self, row: R) {
+        self.rows.push(row);
+        if self.rows.len() < BATCH_ROWS {
+            return;
+        }
+        // NOTE: The rows sent after the writer failed are dropped, and its error is
+        // reported at the end of the step.
+        let rows = self.take();
+        if let Err(TrySendError::Full(rows)) = self.sender.batches.try_send(rows) {
+            let dropped = u64::try_from(rows.len()).unwrap_or(u64::MAX);
+            let _ = self.sender.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends the rows buffered so far, waiting for the writer if needed, e.g. once
+    /// the loop stopped starting streams.
+    pub(crate) async fn flush(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let rows = self.take();
+        drop(self.sender.batches.send(rows).await);
+    }
+
+    fn take(&mut self) -> Vec<R> {
+        mem::replace(&mut self.rows, Vec::with_capacity(BATCH_ROWS))
+    }
+}
+
+/// Where the loops of a step write what they keep of every stream.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StepSinks {
+    /// The duration of every stream that did not fail, with `--raw-samples`.
+    pub(crate) durations: Option<RowSender<Duration>>,
+    /// The record of every stream, with `--stream-records`.
+    pub(crate) records: Option<RowSender<StreamRecord>>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Collects the rows written, for the tests.
+    #[derive(Debug, Clone)]
+    pub(crate) struct Collected<R>(pub(crate) Arc<Mutex<Vec<R>>>);
+
+    impl<R> Default for Collected<R> {
+        fn default() -> Self {
+            Self(Arc::default())
+        }
+    }
+
+    impl<R: Clone> RowWriter<R> for Collected<R> {
+        fn write(&mut self, row: &R) -> io::Result<()> {
+            self.0
+                .lock()
+                .expect("collected rows are not poisoned")
+                .push(row.clone());
+            Ok(())
+        }
+
+        fn finish(self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<R: Clone> Collected<R> {
+        pub(crate) fn rows(&self) -> Vec<R> {
+            self.0
+                .lock()
+                .expect("collected rows are not poisoned")
+                .clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rows_are_written_in_batches_until_the_senders_are_dropped() {
+        let collected = Collected::default();
+        let (sender, task) = spawn({
+            let collected = collected.clone();
+            move || Ok(collected)
+        });
+        let mut first = RowBuffer::new(sender.clone(), 10);
+        let mut second = RowBuffer::new(sender, 10);
+
+        for row in 0..BATCH_ROWS {
+            first.push(row);
+        }
+        second.push(BATCH_ROWS);
+        second.flush().await;
+        drop((first, second));
+        task.finish().await.unwrap();
+
+        let rows = collected.rows();
+        assert_eq!(rows, (0..=BATCH_ROWS).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_writer_errors_are_returned_when_finished() {
+        let (sender, task) = spawn::<u64, Collected<u64>>(|| Err(io::Error::other("disk full")));
+        let mut rows = RowBuffer::new(sender, 1);
+        rows.push(1);
+        rows.flush().await;
+        drop(rows);
+
+        let error = task.finish().await.unwrap_err();
+        assert_eq!(error.to_string(), "disk full");
+    }
+
+    #[tokio::test]
+    async fn test_rows_are_dropped_rather_than_waiting_for_the_writer() {
+        let collected = Collected::default();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let (sender, task) = spawn({
+            let collected = collected.clone();
+            move || {
+                // NOTE: The writer reads nothing until released, like a slow disk.
+                released.recv().map_err(io::Error::other)?;
+                Ok(collected)
+            }
+        });
+        let mut rows = RowBuffer::new(sender, BATCH_ROWS);
+
+        for row in 0..(PENDING_BATCHES + 1) * BATCH_ROWS {
+            rows.push(row);
+        }
+        assert_eq!(task.dropped_rows(), u64::try_from(BATCH_ROWS).unwrap());
+
+        release.send(()).unwrap();
+        drop(rows);
+        task.finish().await.unwrap();
+        assert_eq!(collected.rows().len(), PENDING_BATCHES * BATCH_ROWS);
+    }
+}
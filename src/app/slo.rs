@@ -0,0 +1,294 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use serde::Serialize;
+
+use crate::app::summary::{self, PERCENTILES, StepSummary};
+
+/// A threshold checked after each step, such as `p99<5ms@2000`.
+///
+/// The thresholds are:
+/// - a latency percentile, e.g. `p99<5ms`, in `ns`, `us`, `ms` or `s`, which fails
+///   on a step where no stream succeeded,
+/// - `error_rate`, the streams that could not connect or ended with an error status,
+///   with or without `--reconnect`, as a percentage of the streams sent, e.g.
+///   `error_rate<0.1%`,
+/// - `closed_early`, the number of streams the server closed without an error before
+///   answering both requests, e.g. `closed_early<=0`. A stream ended with an error
+///   status failed instead.
+///
+/// Either `<` or `<=` may be used, and a threshold followed by `@<rate>` is only
+/// checked for the steps up to that target throughput.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Slo {
+    metric: Metric,
+    inclusive: bool,
+    /// In nanoseconds for the latency, in percent for the error rate.
+    threshold: f64,
+    /// The highest target throughput the threshold is checked for, if any.
+    up_to: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    /// The latency at one of `PERCENTILES`, by index.
+    Latency(usize),
+    /// The streams that could not connect or ended with an error status, whatever its
+    /// gRPC code, in percent of the streams sent.
+    ErrorRate,
+    /// The streams the server closed without an error before answering both requests.
+    ClosedEarly,
+}
+
+/// The result of checking a threshold after a step, recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SloCheck {
+    /// The threshold, as given on the command line.
+    pub(crate) slo: String,
+    pub(crate) value: f64,
+    pub(crate) threshold: f64,
+    /// The unit of the value and the threshold: `ns` for the latency, `%` for the
+    /// error rate, `streams` for `closed_early`. A latency checked on a step without
+    /// any stream that succeeded fails, with a value of 0 `streams`.
+    pub(crate) unit: &'static str,
+    pub(crate) passed: bool,
+}
+
+impl Slo {
+    /// Checks the threshold against a step, unless the step is above its rate.
+    pub(crate) fn check(&self, summary: &StepSummary) -> Option<SloCheck> {
+        if self
+            .up_to
+            .is_some_and(|up_to| summary.target_throughput > up_to)
+        {
+            return None;
+        }
+
+        // NOTE: Without streams, the percentiles are 0 and would pass any threshold.
+        if matches!(self.metric, Metric::Latency(_)) && summary.streams == 0 {
+            return Some(SloCheck {
+                slo: self.to_string(),
+                value: 0.0,
+                threshold: self.threshold,
+                unit: "streams",
+                passed: false,
+            });
+        }
+
+        let (value, unit) = match self.metric {
+            Metric::Latency(index) => (summary.percentiles[index].as_secs_f64() * 1e9, "ns"),
+            Metric::ErrorRate if summary.request_sent == 0 => (0.0, "%"),
+            Metric::ErrorRate => (
+                summary::float(summary.failed_streams) / summary::float(summary.request_sent)
+                    * 100.0,
+                "%",
+            ),
+            Metric::ClosedEarly => (summary::float(summary.closed_early_streams), "streams"),
+        };
+        let passed = if self.inclusive {
+            value <= self.threshold
+        } else {
+            value < self.threshold
+        };
+
+        Some(SloCheck {
+            slo: self.to_string(),
+            value,
+            threshold: self.threshold,
+            unit,
+            passed,
+        })
+    }
+}
+
+impl FromStr for Slo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (slo, up_to) = match s.split_once('@') {
+            Some((slo, up_to)) => {
+                let up_to = up_to
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("the rate after @ must be an integer, got {up_to}"))?;
+                (slo, Some(up_to))
+            }
+            None => (s, None),
+        };
+        let (metric, inclusive, threshold) = if let Some((metric, threshold)) = slo.split_once("<=")
+        {
+            (metric, true, threshold)
+        } else {
+            let (metric, threshold) = slo
+                .split_once('<')
+                .ok_or_else(|| format!("expected <metric><<threshold>, got {slo}"))?;
+            (metric, false, threshold)
+        };
+
+        let threshold = threshold.trim();
+        let (metric, threshold) = match metric.trim() {
+            "error_rate" => (
+                Metric::ErrorRate,
+                parse_number(threshold.strip_suffix('%').unwrap_or(threshold))?,
+            ),
+            "closed_early" => (Metric::ClosedEarly, parse_number(threshold)?),
+            name => {
+                let index = PERCENTILES
+                    .iter()
+                    .position(|percentile| name == format!("p{percentile}"))
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown metric {name}, expected a percentile such as p99, \
+                             error_rate or closed_early"
+                        )
+                    })?;
+                (Metric::Latency(index), parse_latency(threshold)?)
+            }
+        };
+
+        Ok(Self {
+            metric,
+            inclusive,
+            threshold,
+            up_to,
+        })
+    }
+}
+
+impl fmt::Display for Slo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.metric {
+            Metric::Latency(index) => write!(f, "p{}", PERCENTILES[index])?,
+            Metric::ErrorRate => f.write_str("error_rate")?,
+            Metric::ClosedEarly => f.write_str("closed_early")?,
+        }
+        f.write_str(if self.inclusive { "<=" } else { "<" })?;
+        match self.metric {
+            Metric::Latency(_) => write!(f, "{:?}", Duration::from_secs_f64(self.threshold / 1e9))?,
+            Metric::ErrorRate => write!(f, "{}%", self.threshold)?,
+            Metric::ClosedEarly => write!(f, "{}", self.threshold)?,
+        }
+        if let Some(up_to) = self.up_to {
+            write!(f, "@{up_to}")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(v: &str) -> Result<f64, String> {
+    v.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| format!("the threshold must be a positive number, got {v}"))
+}
+
+/// Parses a latency such as `5ms` into nanoseconds.
+fn parse_latency(v: &str) -> Result<f64, String> {
+    let units = [
+        ("ns", 1.0),
+        ("us", 1e3),
+        ("µs", 1e3),
+        ("ms", 1e6),
+        ("s", 1e9),
+    ];
+    units
+        .iter()
+        .find_map(|(unit, nanos)| Some(parse_number(v.strip_suffix(unit)?).map(|v| v * nanos)))
+        .unwrap_or_else(|| {
+            Err(format!(
+                "the latency must end with ns, us, ms or s, got {v}"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{phases::Phases, scheduler::Recording};
+
+    fn summary(target_throughput: u64, p99: Duration) -> StepSummary {
+        let mut histogram = hdrhistogram::Histogram::new(3).unwrap();
+        histogram.record(1_000).unwrap();
+        let mut summary = StepSummary::new(
+            target_throughput,
+            Duration::from_secs(10),
+            2_000,
+            3,
+            1,
+            &histogram,
+            &Phases::new(Recording::default()),
+        );
+        summary.percentiles[2] = p99;
+        summary
+    }
+
+    #[test]
+    fn test_parse_slos() {
+        let slo: Slo = "p99 < 5ms @ 2000".parse().unwrap();
+        assert_eq!(slo.to_string(), "p99<5ms@2000");
+        assert!((slo.threshold - 5e6).abs() < 1e-6);
+
+        let slo: Slo = "error_rate<0.1%".parse().unwrap();
+        assert_eq!(slo.to_string(), "error_rate<0.1%");
+        let slo: Slo = "closed_early<=0".parse().unwrap();
+        assert_eq!(slo.to_string(), "closed_early<=0");
+
+        for invalid in [
+            "p98<5ms",
+            "p99<5",
+            "p99>5ms",
+            "error_rate<-1%",
+            "p99<5ms@fast",
+        ] {
+            assert!(
+                invalid.parse::<Slo>().is_err(),
+                "{invalid} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_slos() {
+        let slo: Slo = "p99<5ms@2000".parse().unwrap();
+        assert!(
+            slo.check(&summary(100, Duration::from_millis(4)))
+                .unwrap()
+                .passed
+        );
+        let check = slo.check(&summary(100, Duration::from_millis(5))).unwrap();
+        assert!(!check.passed);
+        assert!((check.value - 5e6).abs() < 1e-6);
+        assert!(slo.check(&summary(3000, Duration::from_secs(1))).is_none());
+
+        // 3 failed streams out of 2000 sent.
+        let slo: Slo = "error_rate<0.1%".parse().unwrap();
+        let check = slo.check(&summary(100, Duration::ZERO)).unwrap();
+        assert!(!check.passed);
+        assert!((check.value - 0.15).abs() < 1e-9);
+
+        let slo: Slo = "closed_early<=0".parse().unwrap();
+        assert!(slo.check(&summary(100, Duration::ZERO)).unwrap().passed);
+    }
+
+    #[test]
+    fn test_latency_slos_fail_without_streams() {
+        let failed = StepSummary::new(
+            100,
+            Duration::from_secs(10),
+            1_000,
+            1_000,
+            1,
+            &hdrhistogram::Histogram::new(3).unwrap(),
+            &Phases::new(Recording::default()),
+        );
+        assert_eq!(failed.streams, 0);
+
+        let slo: Slo = "p99<5ms".parse().unwrap();
+        let check = slo.check(&failed).unwrap();
+        assert!(!check.passed);
+        assert!(check.value.abs() < 1e-9);
+        assert_eq!(check.unit, "streams");
+
+        let slo: Slo = "error_rate<=100%".parse().unwrap();
+        assert!(slo.check(&failed).unwrap().passed);
+    }
+}
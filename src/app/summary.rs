@@ -0,0 +1,296 @@
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Write as _},
+    time::Duration,
+};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use tonic::Code;
+
+use crate::app::phases::Phases;
+
+/// The percentiles of the latency reported for each step.
+pub(crate) const PERCENTILES: [f64; 5] = [50.0, 90.0, 99.0, 99.9, 99.99];
+
+/// The outcome of a step, at a single target throughput.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct StepSummary {
+    pub(crate) target_throughput: u64,
+    /// Streams opened per second.
+    pub(crate) achieved_throughput: u64,
+    /// Streams opened, as a percentage of the streams planned for the step.
+    pub(crate) percent_of_target_throughput: u64,
+    pub(crate) request_sent: u64,
+    /// Number of streams that succeeded.
+    pub(crate) streams: u64,
+    pub(crate) failed_streams: u64,
+    /// The failed streams by gRPC code of their error, e.g. `Unavailable`.
+    #[serde(
+        rename = "failures_by_code",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub(crate) failures: BTreeMap<String, u64>,
+    /// Number of the succeeded streams that the server closed before answering both
+    /// requests.
+    pub(crate) closed_early_streams: u64,
+    /// Highest number of streams in flight at once.
+    pub(crate) in_flight_peak: usize,
+    #[serde(rename = "min_ns", serialize_with = "as_nanos")]
+    pub(crate) min: Duration,
+    #[serde(rename = "mean_ns", serialize_with = "as_nanos")]
+    pub(crate) mean: Duration,
+    #[serde(rename = "stddev_ns", serialize_with = "as_nanos")]
+    pub(crate) stddev: Duration,
+    #[serde(rename = "max_ns", serialize_with = "as_nanos")]
+    pub(crate) max: Duration,
+    /// Latency at each of `PERCENTILES`.
+    #[serde(rename = "percentiles_ns", serialize_with = "percentiles_as_nanos")]
+    pub(crate) percentiles: [Duration; PERCENTILES.len()],
+    /// Latency of each phase of the streams.
+    pub(crate) phases: Phases<LatencySummary>,
+}
+
+/// The latency of some of the streams of a step, e.g. of a phase of the streams or
+/// of the streams sent to an endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct LatencySummary {
+    /// Number of streams, e.g. that reached the phase.
+    pub(crate) streams: u64,
+    #[serde(rename = "mean_ns", serialize_with = "as_nanos")]
+    pub(crate) mean: Duration,
+    #[serde(rename = "max_ns", serialize_with = "as_nanos")]
+    pub(crate) max: Duration,
+    #[serde(rename = "percentiles_ns", serialize_with = "percentiles_as_nanos")]
+    pub(crate) percentiles: [Duration; PERCENTILES.len()],
+}
+
+impl LatencySummary {
+    pub(crate) fn new(histogram: &Histogram<u64>) -> Self {
+        Self {
+            streams: histogram.len(),
+            mean: Duration::from_secs_f64(histogram.mean() / 1e9),
+            max: Duration::from_nanos(histogram.max()),
+            percentiles: percentiles(histogram),
+        }
+    }
+}
+
+fn percentiles(histogram: &Histogram<u64>) -> [Duration; PERCENTILES.len()] {
+    PERCENTILES.map(|percentile| Duration::from_nanos(histogram.value_at_percentile(percentile)))
+}
+
+/// The failed streams by the name of the gRPC code of their error, as summarized.
+pub(crate) fn failures_by_code(failures: &HashMap<Code, u64>) -> BTreeMap<String, u64> {
+    failures
+        .iter()
+        .map(|(code, count)| (format!("{code:?}"), *count))
+        .collect()
+}
+
+/// A count as a float, saturated to `u32::MAX` so that the conversion is exact.
+pub(crate) fn float(count: u64) -> f64 {
+    f64::from(u32::try_from(count).unwrap_or(u32::MAX))
+}
+
+fn as_nanos<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_nanos())
+}
+
+/// Serializes the percentiles as a map, e.g. `{"p50": 1000, "p99.9": 2000}`.
+fn percentiles_as_nanos<S: serde::Serializer>(
+    percentiles: &[Duration; PERCENTILES.len()],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        PERCENTILES
+            .iter()
+            .zip(percentiles)
+            .map(|(percentile, latency)| (format!("p{percentile}"), latency.as_nanos())),
+    )
+}
+
+impl StepSummary {
+    /// Summarizes a step from the histograms of the latency of its streams and of
+    /// their phases, in nanoseconds.
+    pub(crate) fn new(
+        target_throughput: u64,
+        test_duration: Duration,
+        request_sent: u64,
+        failed_streams: u64,
+        in_flight_peak: usize,
+        histogram: &Histogram<u64>,
+        phases: &Phases<Histogram<u64>>,
+    ) -> Self {
+        let target_request_count = (test_duration.as_secs() * target_throughput).max(1);
+
+        Self {
+            target_throughput,
+            achieved_throughput: request_sent / test_duration.as_secs().max(1),
+            percent_of_target_throughput: 100 * request_sent / target_request_count,
+            request_sent,
+            streams: histogram.len(),
+            failed_streams,
+            failures: BTreeMap::new(),
+            closed_early_streams: 0,
+            in_flight_peak,
+            min: Duration::from_nanos(histogram.min()),
+            mean: Duration::from_secs_f64(histogram.mean() / 1e9),
+            stddev: Duration::from_secs_f64(histogram.stdev() / 1e9),
+            max: Duration::from_nanos(histogram.max()),
+            percentiles: percentiles(histogram),
+            phases: phases.map(LatencySummary::new),
+        }
+    }
+}
+
+impl fmt::Display for StepSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} req/s: {} req/s achieved ({}% of planned requests sent), {} streams, {} failed, {} closed early, in flight peak: {}",
+            self.target_throughput,
+            self.achieved_throughput,
+            self.percent_of_target_throughput,
+            self.streams,
+            self.failed_streams,
+            self.closed_early_streams,
+            self.in_flight_peak,
+        )?;
+        write!(
+            f,
+            "\n  min: {:?}, avg: {:?}, stddev: {:?}",
+            self.min, self.mean, self.stddev
+        )?;
+        for (percentile, latency) in PERCENTILES.iter().zip(self.percentiles) {
+            write!(f, ", p{percentile}: {latency:?}")?;
+        }
+        write!(f, ", max: {:?}", self.max)?;
+        for (phase, summary) in self.phases.named() {
+            write!(f, "\n  {phase}: avg: {:?}", summary.mean)?;
+            for (percentile, latency) in PERCENTILES.iter().zip(summary.percentiles) {
+                write!(f, ", p{percentile}: {latency:?}")?;
+            }
+            write!(f, ", max: {:?}", summary.max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the summaries of the steps as a table, to compare them at the end of the run.
+/// Latencies are in milliseconds.
+pub(crate) fn table(summaries: impl IntoIterator<Item = impl Borrow<StepSummary>>) -> String {
+    let mut table = format!(
+        "{:>10} {:>10} {:>6} {:>10} {:>8} {:>9}",
+        "target/s", "achieved/s", "sent%", "streams", "failed", "in flight"
+    );
+    for header in ["avg", "stddev"] {
+        let _ = write!(table, " {header:>10}");
+    }
+    for percentile in PERCENTILES {
+        let _ = write!(table, " {:>10}", format!("p{percentile}"));
+    }
+    let _ = write!(table, " {:>10}", "max");
+
+    for summary in summaries {
+        let summary = summary.borrow();
+        let _ = write!(
+            table,
+            "\n{:>10} {:>10} {:>6} {:>10} {:>8} {:>9}",
+            summary.target_throughput,
+            summary.achieved_throughput,
+            summary.percent_of_target_throughput,
+            summary.streams,
+            summary.failed_streams,
+            summary.in_flight_peak,
+        );
+        let latencies = [summary.mean, summary.stddev]
+            .into_iter()
+            .chain(summary.percentiles)
+            .chain([summary.max]);
+        for latency in latencies {
+            let _ = write!(table, " {:>10.3}", latency.as_secs_f64() * 1e3);
+        }
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::scheduler::Recording;
+
+    fn histogram(latencies_ms: impl IntoIterator<Item = u64>) -> Histogram<u64> {
+        let mut histogram = Histogram::new(3).unwrap();
+        for latency in latencies_ms {
+            histogram.record(latency * 1_000_000).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_summary_of_step_without_streams() {
+        let phases = Phases::new(Recording::default());
+        let summary = StepSummary::new(
+            10,
+            Duration::from_secs(10),
+            100,
+            100,
+            3,
+            &histogram([]),
+            &phases,
+        );
+
+        assert_eq!(summary.streams, 0);
+        assert_eq!(summary.failed_streams, 100);
+        assert_eq!(summary.max, Duration::ZERO);
+        assert_eq!(summary.percentiles, [Duration::ZERO; PERCENTILES.len()]);
+        assert!(summary.to_string().contains("0 streams, 100 failed"));
+        assert_eq!(summary.phases.setup.streams, 0);
+    }
+
+    #[test]
+    fn test_summary_percentiles_and_table() {
+        let summary = StepSummary::new(
+            100,
+            Duration::from_secs(10),
+            990,
+            0,
+            5,
+            &histogram(1..=10_000),
+            &Phases {
+                setup: histogram([1]),
+                request_headers: histogram([2]),
+                response_headers: histogram([3]),
+                time_to_first_response: histogram([3]),
+            },
+        );
+
+        assert_eq!(summary.achieved_throughput, 99);
+        assert_eq!(summary.percent_of_target_throughput, 99);
+        assert_eq!(summary.streams, 10_000);
+        let expected_ms = [5_000, 9_000, 9_900, 9_990, 9_999];
+        for (latency, expected) in summary.percentiles.iter().zip(expected_ms) {
+            // NOTE: The histogram keeps 3 significant digits.
+            let expected = Duration::from_millis(expected);
+            assert!(
+                latency.abs_diff(expected) <= expected / 1_000,
+                "Expected {expected:?}, got {latency:?}"
+            );
+        }
+
+        assert!(
+            summary.to_string().contains("\n  setup: avg: 1."),
+            "Got {summary}"
+        );
+
+        let table = table(&[summary]);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("p99.99"), "Got {table}");
+        let columns = lines[1].split_whitespace().collect::<Vec<_>>();
+        assert_eq!(columns[..6], ["100", "99", "99", "10000", "0", "5"]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::Stream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{UnixListener, UnixStream},
+};
+use tokio_stream::StreamExt;
+use tonic::{
+    Code, Request, Response, Status, Streaming,
+    codec::CompressionEncoding,
+    transport::{Server, server::Connected},
+};
+
+use tonic_health::server::{HealthReporter, health_reporter};
+
+use crate::generated::envoy::service::ext_proc::v3::{
+    ProcessingRequest, ProcessingResponse,
+    external_processor_server::{ExternalProcessor, ExternalProcessorServer},
+};
+
+/// An in-process `ext_proc` server answering every request with an empty response.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EchoServer {
+    /// The `grpc-encoding` of the calls received, if any.
+    pub(crate) encodings: Arc<Mutex<Vec<Option<String>>>>,
+    /// The requests received, in the order they arrived.
+    pub(crate) requests: Arc<Mutex<Vec<ProcessingRequest>>>,
+    /// If set, the stream ends with this code instead of answering the second request.
+    pub(crate) fail_with: Option<Code>,
+    /// How long to wait before accepting each stream, and before each response.
+    pub(crate) delay: Duration,
+    /// If set, the response headers are only sent with the first response, like
+    /// grpc-go does, instead of when the stream is accepted.
+    pub(crate) headers_with_first_response: bool,
+}
+
+#[tonic::async_trait]
+impl ExternalProcessor for EchoServer {
+    type ProcessStream = Pin<Box<dyn Stream<Item = Result<ProcessingResponse, Status>> + Send>>;
+
+    async fn process(
+        &self,
+        request: Request<Streaming<ProcessingRequest>>,
+    ) -> Result<Response<Self::ProcessStream>, Status> {
+        let encoding = request
+            .metadata()
+            .get("grpc-encoding")
+            .and_then(|encoding| encoding.to_str().ok())
+            .map(str::to_string);
+        self.encodings
+            .lock()
+            .expect("encodings lock is not poisoned")
+            .push(encoding);
+
+        let (fail_with, delay) = (self.fail_with, self.delay);
+        let requests = self.requests.clone();
+        let mut answered = 0;
+        let mut respond = move |request: Result<ProcessingRequest, Status>| {
+            answered += 1;
+            match fail_with {
+                Some(code) if answered > 1 => Err(Status::new(code, "failed by the test server")),
+                _ => request.map(|request| {
+                    requests
+                        .lock()
+                        .expect("requests lock is not poisoned")
+                        .push(request);
+                    ProcessingResponse::default()
+                }),
+            }
+        };
+        let mut stream = request.into_inner();
+        tokio::time::sleep(delay).await;
+
+        // NOTE: `tonic` sends the response headers as soon as the stream is returned.
+        let mut first = None;
+        if self.headers_with_first_response
+            && let Some(request) = stream.next().await
+        {
+            first = Some(delayed(delay, respond(request)).await);
+        }
+        let responses = futures::StreamExt::then(stream.map(respond), move |response| {
+            delayed(delay, response)
+        });
+
+        Ok(Response::new(Box::pin(
+            tokio_stream::iter(first).chain(responses),
+        )))
+    }
+}
+
+async fn delayed<T>(delay: Duration, value: T) -> T {
+    tokio::time::sleep(delay).await;
+    value
+}
+
+/// Serves `EchoServer` on the accepted connections, in a background task.
+pub(crate) fn serve<S, T>(incoming: S)
+where
+    S: Stream<Item = io::Result<T>> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let _reporter = spawn_server(incoming, EchoServer::default(), false);
+}
+
+/// Serves `EchoServer` on the connections accepted by a Unix socket listener.
+pub(crate) fn serve_unix(listener: UnixListener) {
+    serve_unix_with(listener, EchoServer::default());
+}
+
+/// Serves the given `EchoServer` on the connections accepted by a Unix socket
+/// listener, to inspect the calls it received.
+pub(crate) fn serve_unix_with(listener: UnixListener, server: EchoServer) {
+    let _reporter = spawn_server(unix_incoming(listener), server, false);
+}
+
+/// Serves `EchoServer` and the `grpc.health.v1.Health` service on the connections
+/// accepted by a Unix socket listener. The returned reporter sets the health.
+pub(crate) fn serve_unix_with_health(listener: UnixListener) -> HealthReporter {
+    spawn_server(unix_incoming(listener), EchoServer::default(), true)
+        .expect("health service is served")
+}
+
+fn unix_incoming(listener: UnixListener) -> impl Stream<Item = io::Result<UnixStream>> {
+    futures::stream::unfold(listener, async |listener| {
+        let stream = listener.accept().await.map(|(stream, _)| stream);
+        Some((stream, listener))
+    })
+}
+
+fn spawn_server<S, T>(incoming: S, server: EchoServer, health: bool) -> Option<HealthReporter>
+where
+    S: Stream<Item = io::Result<T>> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let incoming = Box::pin(incoming.map(|stream| stream.map(Accepted)));
+    let (reporter, health_server) = health.then(health_reporter).unzip();
+
+    let _handle = tokio::spawn(
+        Server::builder()
+            .add_service(
+                ExternalProcessorServer::new(server)
+                    .accept_compressed(CompressionEncoding::Gzip)
+                    .accept_compressed(CompressionEncoding::Zstd)
+                    .send_compressed(CompressionEncoding::Gzip)
+                    .send_compressed(CompressionEncoding::Zstd),
+            )
+            .add_optional_service(health_server)
+            .serve_with_incoming(incoming),
+    );
+
+    reporter
+}
+
+/// An accepted connection, which `tonic` can serve.
+#[derive(Debug)]
+struct Accepted<T>(T);
+
+impl<T> Connected for Accepted<T> {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl<T> AsyncRead for Accepted<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Accepted<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,964 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::TryFutureExt as _;
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpSocket, TcpStream, UnixStream, lookup_host},
+    time::Instant,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{
+        ClientConfig, RootCertStore,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject},
+    },
+};
+use tonic::transport::{Endpoint, Uri};
+use tower_service::Service;
+
+use crate::app::{
+    cli::{TlsArgs, TransportArgs},
+    error::{Error, Result},
+};
+
+/// A byte stream to the `ext_proc` server.
+pub(crate) trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Io for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+/// The authority sent to the server when connecting through a Unix domain socket.
+const SOCKET_AUTHORITY: &str = "localhost";
+
+/// Where the connections to the `ext_proc` server are opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// The host and port of the URI, over TCP.
+    Tcp,
+    /// A Unix domain socket bound to this path.
+    Unix(PathBuf),
+    /// A Unix domain socket bound to this name in the Linux abstract namespace.
+    UnixAbstract(Vec<u8>),
+}
+
+impl Target {
+    /// Parses the URI of the `ext_proc` server.
+    ///
+    /// Follows the gRPC naming conventions: `unix:path` and `unix://absolute_path`
+    /// for Unix domain sockets, `unix-abstract:name` for abstract sockets, and any
+    /// other URI for TCP. Returns the target and the URI to give to `tonic`, which
+    /// does not know how to handle Unix domain socket URIs.
+    pub(crate) fn parse(uri: &str, tls: bool) -> (Self, String) {
+        let socket_uri = || {
+            let scheme = if tls { "https" } else { "http" };
+            format!("{scheme}://{SOCKET_AUTHORITY}")
+        };
+
+        if let Some(name) = uri.strip_prefix("unix-abstract:") {
+            (Target::UnixAbstract(name.as_bytes().to_vec()), socket_uri())
+        } else if let Some(path) = uri
+            .strip_prefix("unix://")
+            .or_else(|| uri.strip_prefix("unix:"))
+        {
+            (Target::Unix(PathBuf::from(path)), socket_uri())
+        } else {
+            (Target::Tcp, uri.to_string())
+        }
+    }
+}
+
+/// Creates the endpoint used to open the connections to the `ext_proc` server,
+/// with the HTTP/2 settings of the transport options applied.
+///
+/// The TCP settings are applied by the `Connector` instead, see `TcpSettings`.
+pub(crate) fn endpoint(uri: &str, args: &TransportArgs) -> Result<Endpoint> {
+    let mut endpoint = Endpoint::new(uri.to_string())
+        .map_err(Error::FailedToCreateEndpoint)?
+        .initial_stream_window_size(args.http2_initial_stream_window_size)
+        .initial_connection_window_size(args.http2_initial_connection_window_size)
+        .http2_adaptive_window(args.http2_adaptive_window)
+        .max_frame_size(args.http2_max_frame_size)
+        .buffer_size(args.request_buffer_size);
+
+    if let Some(interval) = args.http2_keepalive_interval {
+        endpoint = endpoint
+            .http2_keep_alive_interval(Duration::from_secs(interval))
+            .keep_alive_while_idle(args.http2_keepalive_while_idle);
+    }
+    if let Some(timeout) = args.http2_keepalive_timeout {
+        endpoint = endpoint.keep_alive_timeout(Duration::from_secs(timeout));
+    }
+    if let Some(limit) = args.max_concurrent_streams {
+        endpoint = endpoint.concurrency_limit(limit);
+    }
+    if let Some(timeout) = args.connect_timeout_ms {
+        endpoint = endpoint.connect_timeout(Duration::from_millis(timeout));
+    }
+
+    Ok(endpoint)
+}
+
+/// Socket options of the TCP connections.
+///
+/// `tonic` only applies them when it opens the connections itself, so the
+/// `Connector` does it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TcpSettings {
+    pub(crate) nodelay: bool,
+    pub(crate) send_buffer_size: Option<u32>,
+    pub(crate) recv_buffer_size: Option<u32>,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl From<&TransportArgs> for TcpSettings {
+    fn from(args: &TransportArgs) -> Self {
+        Self {
+            nodelay: args.tcp_nodelay,
+            send_buffer_size: args.tcp_send_buffer_size,
+            recv_buffer_size: args.tcp_recv_buffer_size,
+        }
+    }
+}
+
+impl TcpSettings {
+    /// Connects to the first address the host resolves to that accepts the connection.
+    async fn connect(self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in lookup_host((host, port)).await? {
+            let socket = if address.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            if let Some(size) = self.send_buffer_size {
+                socket.set_send_buffer_size(size)?;
+            }
+            if let Some(size) = self.recv_buffer_size {
+                socket.set_recv_buffer_size(size)?;
+            }
+
+            match socket.connect(address).await {
+                Ok(stream) => {
+                    stream.set_nodelay(self.nodelay)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{host} has no address"))
+        }))
+    }
+}
+
+/// Opens the connections to the `ext_proc` server, optionally over TLS.
+///
+/// Each connector records the duration of the last TLS handshake it performed, so
+/// the handshake cost can be reported separately from the connect latency. Use
+/// `Connector::for_connection` to get a connector with its own record.
+#[derive(Debug, Clone)]
+pub(crate) struct Connector {
+    target: Target,
+    tcp: TcpSettings,
+    tls: Option<TlsConfig>,
+    tls_handshake: Arc<Mutex<Option<Duration>>>,
+    traffic: Traffic,
+}
+
+impl Connector {
+    pub(crate) fn new(target: Target, tcp: TcpSettings, tls: Option<TlsConfig>) -> Self {
+        Self {
+            target,
+            tcp,
+            tls,
+            tls_handshake: Arc::default(),
+            traffic: Traffic::default(),
+        }
+    }
+
+    /// Returns a connector with the same settings, to open a single connection.
+    /// The traffic of the connection is counted with the traffic of this connector.
+    pub(crate) fn for_connection(&self) -> Self {
+        Self {
+            tls_handshake: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// The bytes sent and received on all the connections opened by this connector.
+    pub(crate) fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    /// Duration of the last TLS handshake performed by this connector.
+    pub(crate) fn tls_handshake(&self) -> Option<Duration> {
+        *self
+            .tls_handshake
+            .lock()
+            .expect("TLS handshake lock is not poisoned")
+    }
+
+    async fn connect(self, uri: Uri) -> io::Result<Box<dyn Io>> {
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+        // NOTE: IPv6 hosts are enclosed in brackets in URIs.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let default_port = if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        };
+        let port = uri.port_u16().unwrap_or(default_port);
+
+        let stream: Box<dyn Io> = match &self.target {
+            Target::Tcp => Box::new(self.tcp.connect(host, port).await?),
+            Target::Unix(path) => Box::new(UnixStream::connect(path).await?),
+            Target::UnixAbstract(name) => Box::new(connect_abstract(name.clone()).await?),
+        };
+        // NOTE: Counted below TLS, to get the bytes on the wire.
+        let stream: Box<dyn Io> = Box::new(CountingIo {
+            inner: stream,
+            traffic: self.traffic.clone(),
+        });
+
+        let Some(tls) = &self.tls else {
+            return Ok(stream);
+        };
+
+        let server_name = match &tls.server_name {
+            Some(server_name) => server_name.clone(),
+            None => ServerName::try_from(host.to_string())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        };
+
+        let start = Instant::now();
+        let stream = tls.connector.connect(server_name, stream).await?;
+        *self
+            .tls_handshake
+            .lock()
+            .expect("TLS handshake lock is not poisoned") = Some(start.elapsed());
+
+        Ok(Box::new(stream))
+    }
+}
+
+/// Counters of the bytes sent and received on a set of connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Traffic {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+impl Traffic {
+    pub(crate) fn bytes_sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bytes_received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// A byte stream counting the bytes going through it.
+struct CountingIo<T> {
+    inner: T,
+    traffic: Traffic,
+}
+
+impl<T> AsyncRead for CountingIo<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        let _ = self
+            .traffic
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+impl<T> AsyncWrite for CountingIo<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            let _ = self
+                .traffic
+                .sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            let _ = self
+                .traffic
+                .sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Connects to a Unix domain socket in the abstract namespace.
+#[cfg(target_os = "linux")]
+async fn connect_abstract(name: Vec<u8>) -> io::Result<UnixStream> {
+    use std::os::{linux::net::SocketAddrExt as _, unix::net};
+
+    // NOTE: The connect blocks while the backlog of the server is full.
+    let stream = tokio::task::spawn_blocking(move || {
+        let address = net::SocketAddr::from_abstract_name(name)?;
+        let stream = net::UnixStream::connect_addr(&address)?;
+        stream.set_nonblocking(true)?;
+        Ok::<_, io::Error>(stream)
+    })
+    .await
+    .map_err(io::Error::other)??;
+
+    UnixStream::from_std(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+#[expect(clippy::unused_async, reason = "matches the Linux implementation")]
+async fn connect_abstract(_name: Vec<u8>) -> io::Result<UnixStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract Unix domain sockets are only supported on Linux",
+    ))
+}
+
+impl Service<Uri> for Connector {
+    type Response = TokioIo<Box<dyn Io>>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        Box::pin(self.clone().connect(uri).map_ok(TokioIo::new))
+    }
+}
+
+/// The client side TLS configuration, loaded once at startup.
+#[derive(Clone)]
+pub(crate) struct TlsConfig {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsConfig {
+    /// Loads the certificates and keys referenced by the TLS options.
+    pub(crate) fn load(args: &TlsArgs) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        match &args.ca {
+            Some(path) => {
+                for certificate in read_certificates(path)? {
+                    roots
+                        .add(certificate)
+                        .map_err(|e| invalid_tls_configuration(path, &e))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::InvalidTlsConfiguration(e.to_string()))?
+            .with_root_certificates(roots);
+
+        let mut config = match (&args.cert, &args.key) {
+            (Some(cert_path), Some(key_path)) => {
+                let certificates = read_certificates(cert_path)?;
+                let key = PrivateKeyDer::from_pem_file(key_path)
+                    .map_err(|e| invalid_tls_configuration(key_path, &e))?;
+                builder
+                    .with_client_auth_cert(certificates, key)
+                    .map_err(|e| invalid_tls_configuration(cert_path, &e))?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = args
+            .alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+
+        let server_name = args
+            .server_name
+            .as_ref()
+            .map(|name| {
+                ServerName::try_from(name.clone())
+                    .map_err(|e| Error::InvalidTlsConfiguration(format!("{name}: {e}")))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| invalid_tls_configuration(path, &e))?;
+
+    if certificates.is_empty() {
+        return Err(invalid_tls_configuration(path, &"no certificate found"));
+    }
+
+    Ok(certificates)
+}
+
+fn invalid_tls_configuration(path: &Path, e: &impl fmt::Display) -> Error {
+    Error::InvalidTlsConfiguration(format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        num::{NonZeroU64, NonZeroUsize},
+    };
+
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::{TcpListener, UnixListener},
+    };
+    use tokio_rustls::{
+        TlsAcceptor,
+        rustls::{ServerConfig, server::WebPkiClientVerifier},
+    };
+    use tonic::codec::CompressionEncoding;
+
+    use super::*;
+    use crate::app::{
+        balancer::Balancer,
+        connection::{Churn, ConnectionPool},
+        metadata::MetadataInterceptor,
+        test_server,
+        worker::{CompressionSettings, GrpcWorker, StreamStats, Worker},
+    };
+
+    const SERVER_NAME: &str = "ext-proc.test";
+
+    /// A CA, and the server and client certificates it signed, written as PEM files.
+    struct Pki {
+        directory: TempDir,
+        ca: CertificateDer<'static>,
+        server_chain: Vec<CertificateDer<'static>>,
+        server_key: PrivateKeyDer<'static>,
+    }
+
+    impl Pki {
+        fn generate() -> Self {
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+            let server_key = KeyPair::generate().unwrap();
+            let server_cert = CertificateParams::new(vec![SERVER_NAME.to_string()])
+                .unwrap()
+                .signed_by(&server_key, &ca)
+                .unwrap();
+
+            let client_key = KeyPair::generate().unwrap();
+            let client_cert = CertificateParams::new(vec!["client.test".to_string()])
+                .unwrap()
+                .signed_by(&client_key, &ca)
+                .unwrap();
+
+            let directory = TempDir::new().unwrap();
+            std::fs::write(directory.path().join("ca.pem"), ca.pem()).unwrap();
+            std::fs::write(directory.path().join("client.pem"), client_cert.pem()).unwrap();
+            std::fs::write(
+                directory.path().join("client.key"),
+                client_key.serialize_pem(),
+            )
+            .unwrap();
+
+            Self {
+                directory,
+                ca: ca.der().clone(),
+                server_chain: vec![server_cert.der().clone()],
+                server_key: PrivateKeyDer::try_from(server_key.serialize_der()).unwrap(),
+            }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.directory.path().join(name)
+        }
+
+        fn tls_args(&self, client_auth: bool, server_name: Option<&str>) -> TlsArgs {
+            TlsArgs {
+                ca: Some(self.path("ca.pem")),
+                cert: client_auth.then(|| self.path("client.pem")),
+                key: client_auth.then(|| self.path("client.key")),
+                server_name: server_name.map(str::to_string),
+                alpn: vec!["h2".to_string()],
+            }
+        }
+
+        /// Starts an `ext_proc` server requiring a client certificate signed by the CA.
+        async fn serve(&self) -> String {
+            let provider = Arc::new(ring::default_provider());
+
+            let mut roots = RootCertStore::empty();
+            roots.add(self.ca.clone()).unwrap();
+            let client_verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()
+                    .unwrap();
+
+            let mut config = ServerConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_client_cert_verifier(client_verifier)
+                .with_single_cert(self.server_chain.clone(), self.server_key.clone_key())
+                .unwrap();
+            config.alpn_protocols = vec![b"h2".to_vec()];
+
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let incoming =
+                futures::stream::unfold((listener, acceptor), async |(listener, acceptor)| {
+                    loop {
+                        let (stream, _) = listener.accept().await.ok()?;
+                        // NOTE: Failed handshakes are expected in tests, keep serving.
+                        if let Ok(stream) = acceptor.accept(stream).await {
+                            return Some((Ok(stream), (listener, acceptor)));
+                        }
+                    }
+                });
+            test_server::serve(incoming);
+
+            format!("https://{address}")
+        }
+    }
+
+    async fn run_stream(uri: &str, args: &TlsArgs) -> Result<StreamStats> {
+        let connector = Connector::new(
+            Target::Tcp,
+            TcpSettings::default(),
+            Some(TlsConfig::load(args)?),
+        );
+        let connections = ConnectionPool::connect(
+            Endpoint::new(uri.to_string()).unwrap(),
+            connector,
+            NonZeroUsize::MIN,
+            Churn::EveryStreams(NonZeroU64::MIN),
+            None,
+        )
+        .await?;
+
+        GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        )
+        .run()
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_mtls_stream_measures_handshake() {
+        let pki = Pki::generate();
+        let uri = pki.serve().await;
+
+        let stats = run_stream(&uri, &pki.tls_args(true, Some(SERVER_NAME)))
+            .await
+            .unwrap();
+
+        assert!(stats.new_connection);
+        let tls_handshake = stats.tls_handshake.expect("TLS handshake is measured");
+        assert!(tls_handshake > Duration::ZERO);
+        assert!(tls_handshake <= stats.connection_wait);
+    }
+
+    #[tokio::test]
+    async fn test_mtls_stream_fails_without_client_certificate() {
+        let pki = Pki::generate();
+        let uri = pki.serve().await;
+
+        let result = run_stream(&uri, &pki.tls_args(false, Some(SERVER_NAME))).await;
+
+        assert!(result.is_err(), "The server requires a client certificate");
+    }
+
+    #[tokio::test]
+    async fn test_tls_stream_fails_without_server_name_override() {
+        let pki = Pki::generate();
+        let uri = pki.serve().await;
+
+        // NOTE: The server certificate is not valid for 127.0.0.1.
+        let result = run_stream(&uri, &pki.tls_args(true, None)).await;
+
+        match result {
+            Err(Error::FailedToConnectToEndpoint(_)) => {}
+            other => panic!("Expected FailedToConnectToEndpoint error, got {other:?}"),
+        }
+    }
+
+    async fn run_socket_stream(uri: &str) -> Result<StreamStats> {
+        let (target, uri) = Target::parse(uri, false);
+        let connector = Connector::new(target, TcpSettings::default(), None);
+        let connections = ConnectionPool::connect(
+            Endpoint::new(uri).unwrap(),
+            connector,
+            NonZeroUsize::MIN,
+            Churn::Never,
+            None,
+        )
+        .await?;
+
+        GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        )
+        .run()
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_tcp_settings_are_applied_to_the_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let settings = TcpSettings {
+            nodelay: false,
+            send_buffer_size: Some(1 << 15),
+            recv_buffer_size: Some(1 << 15),
+        };
+
+        let stream = settings.connect("127.0.0.1", port).await.unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        let socket = TcpSocket::from_std_stream(stream.into_std().unwrap());
+        // NOTE: Linux doubles the requested sizes to leave room for its bookkeeping.
+        let factor = if cfg!(target_os = "linux") { 2 } else { 1 };
+        assert_eq!(socket.send_buffer_size().unwrap(), factor << 15);
+        assert_eq!(socket.recv_buffer_size().unwrap(), factor << 15);
+
+        let stream = TcpSettings::default()
+            .connect("127.0.0.1", port)
+            .await
+            .unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+
+    /// Reads the next HTTP/2 frame: its type, stream identifier and payload.
+    async fn read_frame(stream: &mut TcpStream) -> (u8, u32, Vec<u8>) {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).await.unwrap();
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+        let stream_id =
+            u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0; length as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        (header[3], stream_id, payload)
+    }
+
+    #[tokio::test]
+    async fn test_http2_settings_are_sent_to_the_server() {
+        const SETTINGS: u8 = 0x4;
+        const PING: u8 = 0x6;
+        const WINDOW_UPDATE: u8 = 0x8;
+        const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+        const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+        const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        let args = TransportArgs {
+            http2_initial_stream_window_size: Some(1 << 20),
+            http2_initial_connection_window_size: Some(1 << 22),
+            http2_adaptive_window: false,
+            http2_keepalive_interval: Some(1),
+            http2_keepalive_timeout: Some(5),
+            http2_keepalive_while_idle: true,
+            http2_max_frame_size: Some(1 << 15),
+            max_concurrent_streams: Some(16),
+            request_buffer_size: Some(64),
+            tcp_nodelay: true,
+            tcp_send_buffer_size: None,
+            tcp_recv_buffer_size: None,
+            connect_timeout_ms: Some(1000),
+        };
+        let endpoint = endpoint(&uri, &args).unwrap();
+        // NOTE: The channel is kept in the task output so the connection stays open.
+        let client = tokio::spawn(async move { endpoint.connect().await });
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut preface = [0; 24];
+        stream.read_exact(&mut preface).await.unwrap();
+        assert_eq!(&preface, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        // An empty SETTINGS frame, then the acknowledgement of the client settings.
+        stream
+            .write_all(&[0, 0, 0, SETTINGS, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        stream
+            .write_all(&[0, 0, 0, SETTINGS, 1, 0, 0, 0, 0])
+            .await
+            .unwrap();
+
+        let (kind, _, payload) = read_frame(&mut stream).await;
+        assert_eq!(kind, SETTINGS);
+        let settings: HashMap<u16, u32> = payload
+            .chunks_exact(6)
+            .map(|setting| {
+                (
+                    u16::from_be_bytes([setting[0], setting[1]]),
+                    u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]),
+                )
+            })
+            .collect();
+        assert_eq!(
+            settings.get(&SETTINGS_INITIAL_WINDOW_SIZE),
+            Some(&(1 << 20))
+        );
+        assert_eq!(settings.get(&SETTINGS_MAX_FRAME_SIZE), Some(&(1 << 15)));
+
+        let frames = async {
+            let mut connection_window = None;
+            loop {
+                match read_frame(&mut stream).await {
+                    (WINDOW_UPDATE, 0, payload) => {
+                        let increment = u32::from_be_bytes(payload[..4].try_into().unwrap());
+                        connection_window = Some(DEFAULT_WINDOW_SIZE + (increment & 0x7fff_ffff));
+                    }
+                    (PING, _, _) => return connection_window,
+                    _ => {}
+                }
+            }
+        };
+        // NOTE: The keepalive ping is sent after one second, even without a stream in flight.
+        let connection_window = tokio::time::timeout(Duration::from_secs(5), frames)
+            .await
+            .expect("Expected a keepalive ping");
+        assert_eq!(connection_window, Some(1 << 22));
+
+        client.abort();
+    }
+
+    #[tokio::test]
+    async fn test_compressed_streams_count_traffic() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        let server = test_server::EchoServer::default();
+        test_server::serve_unix_with(UnixListener::bind(&path).unwrap(), server.clone());
+        let (target, uri) = Target::parse(&format!("unix:{}", path.display()), false);
+
+        for encoding in [
+            None,
+            Some(CompressionEncoding::Gzip),
+            Some(CompressionEncoding::Zstd),
+        ] {
+            let connector = Connector::new(target.clone(), TcpSettings::default(), None);
+            let traffic = connector.traffic().clone();
+            let connections = ConnectionPool::connect(
+                Endpoint::new(uri.clone()).unwrap(),
+                connector,
+                NonZeroUsize::MIN,
+                Churn::Never,
+                None,
+            )
+            .await
+            .unwrap();
+            let compression = CompressionSettings {
+                send: encoding,
+                accept: encoding.into_iter().collect(),
+            };
+
+            let result = GrpcWorker::new(
+                &Balancer::from(connections),
+                MetadataInterceptor::default(),
+                compression,
+                None,
+                None,
+            )
+            .run()
+            .await;
+
+            assert!(
+                result.is_ok(),
+                "Expected {encoding:?} stream to succeed, got {result:?}"
+            );
+            assert!(traffic.bytes_sent() > 0);
+            assert!(traffic.bytes_received() > 0);
+        }
+
+        let encodings = server.encodings.lock().unwrap().clone();
+        assert_eq!(
+            encodings,
+            [None, Some("gzip".to_string()), Some("zstd".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_target_parse() {
+        assert_eq!(
+            Target::parse("grpc://localhost:12345", false),
+            (Target::Tcp, "grpc://localhost:12345".to_string())
+        );
+        assert_eq!(
+            Target::parse("unix:///tmp/sock", false),
+            (
+                Target::Unix(PathBuf::from("/tmp/sock")),
+                "http://localhost".to_string()
+            )
+        );
+        assert_eq!(
+            Target::parse("unix:relative/sock", true),
+            (
+                Target::Unix(PathBuf::from("relative/sock")),
+                "https://localhost".to_string()
+            )
+        );
+        assert_eq!(
+            Target::parse("unix-abstract:ext-proc", false),
+            (
+                Target::UnixAbstract(b"ext-proc".to_vec()),
+                "http://localhost".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_stream() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        test_server::serve_unix(UnixListener::bind(&path).unwrap());
+
+        let stats = run_socket_stream(&format!("unix://{}", path.display()))
+            .await
+            .unwrap();
+
+        assert!(stats.tls_handshake.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_stream_fails_without_server() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("missing.sock");
+
+        let result = run_socket_stream(&format!("unix:{}", path.display())).await;
+
+        match result {
+            Err(Error::FailedToConnectToEndpoint(_)) => {}
+            other => panic!("Expected FailedToConnectToEndpoint error, got {other:?}"),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abstract_unix_socket_stream() {
+        use std::os::{linux::net::SocketAddrExt as _, unix::net};
+
+        let name = format!("ext-proc-load-tester-{}", std::process::id());
+        let address = net::SocketAddr::from_abstract_name(&name).unwrap();
+        let listener = net::UnixListener::bind_addr(&address).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        test_server::serve_unix(UnixListener::from_std(listener).unwrap());
+
+        let stats = run_socket_stream(&format!("unix-abstract:{name}"))
+            .await
+            .unwrap();
+
+        assert!(stats.tls_handshake.is_none());
+    }
+
+    #[test]
+    fn test_tls_config_rejects_invalid_ca() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+
+        let args = TlsArgs {
+            ca: Some(path),
+            cert: None,
+            key: None,
+            server_name: None,
+            alpn: vec![],
+        };
+
+        match TlsConfig::load(&args) {
+            Err(Error::InvalidTlsConfiguration(_)) => {}
+            other => panic!("Expected InvalidTlsConfiguration error, got {other:?}"),
+        }
+    }
+}
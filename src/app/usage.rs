@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use cpu_time::ProcessTime;
+use serde::Serialize;
+
+use crate::app::transport::Traffic;
+
+/// Resources used by a step, to compare the cost of settings such as compression
+/// next to the latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Usage {
+    /// Bytes sent on the wire, including the HTTP/2 and TLS framing.
+    pub(crate) bytes_sent: u64,
+    /// Bytes received on the wire, including the HTTP/2 and TLS framing.
+    pub(crate) bytes_received: u64,
+    /// CPU time of the load tester process, if the platform reports it.
+    #[serde(rename = "cpu_time_ms", serialize_with = "as_millis")]
+    pub(crate) cpu_time: Option<Duration>,
+}
+
+#[expect(clippy::ref_option, reason = "required by serde")]
+fn as_millis<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_some(&duration.as_millis()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Measures the resources used from its creation.
+#[derive(Debug)]
+pub(crate) struct UsageMeter {
+    traffic: Vec<Traffic>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    cpu_time: Option<ProcessTime>,
+}
+
+impl UsageMeter {
+    pub(crate) fn start(traffic: &[Traffic]) -> Self {
+        Self {
+            traffic: traffic.to_vec(),
+            bytes_sent: traffic.iter().map(Traffic::bytes_sent).sum(),
+            bytes_received: traffic.iter().map(Traffic::bytes_received).sum(),
+            cpu_time: ProcessTime::try_now().ok(),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Usage {
+        let bytes_sent = self.traffic.iter().map(Traffic::bytes_sent).sum::<u64>();
+        let bytes_received = self
+            .traffic
+            .iter()
+            .map(Traffic::bytes_received)
+            .sum::<u64>();
+        Usage {
+            bytes_sent: bytes_sent - self.bytes_sent,
+            bytes_received: bytes_received - self.bytes_received,
+            cpu_time: self.cpu_time.and_then(|start| start.try_elapsed().ok()),
+        }
+    }
+}
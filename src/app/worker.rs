@@ -1,66 +1,537 @@
-use tokio::sync::mpsc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time::Instant};
 use tokio_stream::{StreamExt, wrappers::ReceiverStream};
-use tonic::transport::Channel;
+use tonic::{
+    Code, Request, Streaming, codec::CompressionEncoding, metadata::MetadataValue,
+    service::Interceptor,
+};
 
 use crate::{
     app::{
+        balancer::Balancer,
+        connection::ConnectionPool,
+        downtime::DowntimeTracker,
         error::{Error, Result},
+        metadata::MetadataInterceptor,
+        otlp::{TraceContext, TraceSampler},
+        phases::StreamPhases,
         sample_requests::{request_headers, response_headers},
     },
-    generated::envoy::service::ext_proc::v3::external_processor_client::ExternalProcessorClient,
+    generated::envoy::service::ext_proc::v3::{
+        ProcessingResponse, external_processor_client::ExternalProcessorClient,
+    },
 };
 
 #[allow(dead_code)]
 pub(crate) trait Worker {
-    fn run(&self) -> impl Future<Output = Result<()>> + Send;
+    fn run(&self) -> impl Future<Output = Result<StreamStats>> + Send;
+}
+
+/// What a worker observed while running a stream, besides its duration.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct StreamStats {
+    /// Time spent waiting for a connection before opening the stream.
+    /// It is not part of the stream latency.
+    pub(crate) connection_wait: Duration,
+    /// Whether a new connection was opened for the stream, in which case
+    /// `connection_wait` is the connect latency.
+    pub(crate) new_connection: bool,
+    /// Duration of the TLS handshake of the new connection, part of `connection_wait`.
+    pub(crate) tls_handshake: Option<Duration>,
+    pub(crate) outcome: StreamOutcome,
+    /// Index of the endpoint the stream was sent to.
+    pub(crate) endpoint: usize,
+    /// Identifier of the connection the stream used, 0 if it failed before getting one.
+    pub(crate) connection: u64,
+    pub(crate) phases: StreamPhases,
+    /// Set when the stream is traced.
+    pub(crate) trace: Option<TraceContext>,
+    /// The gRPC code of the error, if the stream failed.
+    pub(crate) error: Option<Code>,
+}
+
+/// How a stream ended.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StreamOutcome {
+    /// The server answered both requests.
+    #[default]
+    Completed,
+    /// The server closed the stream before answering both requests.
+    ClosedEarly,
+    /// The connection could not be opened, or the call ended with an error status.
+    Failed,
+}
+
+impl StreamOutcome {
+    /// The name of the outcome, as serialized.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            StreamOutcome::Completed => "completed",
+            StreamOutcome::ClosedEarly => "closed_early",
+            StreamOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// The exchange of `ext_proc` messages a stream runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Scenario {
+    /// The request headers, then the response headers, which every stream runs.
+    #[default]
+    Headers,
+}
+
+impl Scenario {
+    /// The name of the scenario, as serialized.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Scenario::Headers => "headers",
+        }
+    }
+}
+
+/// The compression of the messages of the `process` calls.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompressionSettings {
+    /// The compression of the requests, if any.
+    pub(crate) send: Option<CompressionEncoding>,
+    /// The compressions the server may use for the responses.
+    pub(crate) accept: Vec<CompressionEncoding>,
 }
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
-pub(crate) struct GrpcWorker {
-    channel: Channel,
+pub(crate) struct GrpcWorker<I = MetadataInterceptor> {
+    endpoints: Balancer,
+    /// Applied to every `process` call, e.g. to attach metadata.
+    interceptor: I,
+    compression: CompressionSettings,
+    /// Set when reconnecting is enabled: the streams failing because the server is
+    /// unavailable, and those succeeding, are then recorded in it, by endpoint.
+    downtime: Option<DowntimeTracker>,
+    /// Set when streams are traced: their `traceparent` is then sent to the server.
+    traces: Option<TraceSampler>,
 }
 
-impl GrpcWorker {
+impl<I> GrpcWorker<I>
+where
+    I: Interceptor + Clone,
+{
     #[allow(dead_code)]
-    pub(crate) fn new(channel: &Channel) -> Self {
+    pub(crate) fn new(
+        endpoints: &Balancer,
+        interceptor: I,
+        compression: CompressionSettings,
+        downtime: Option<&DowntimeTracker>,
+        traces: Option<TraceSampler>,
+    ) -> Self {
         Self {
-            channel: channel.clone(),
+            endpoints: endpoints.clone(),
+            interceptor,
+            compression,
+            downtime: downtime.cloned(),
+            traces,
         }
     }
-}
 
-impl Worker for GrpcWorker {
-    async fn run(&self) -> Result<()> {
-        let mut client = ExternalProcessorClient::new(self.channel.clone());
+    async fn run_stream(
+        &self,
+        connections: &ConnectionPool,
+        trace: Option<TraceContext>,
+    ) -> Result<StreamStats> {
+        let connection = connections.acquire().await?;
+        // NOTE: The stream is closed early until the server answers both requests.
+        let mut stats = StreamStats {
+            connection_wait: connection.wait,
+            new_connection: connection.new_connection,
+            tls_handshake: connection.tls_handshake,
+            outcome: StreamOutcome::ClosedEarly,
+            connection: connection.connection,
+            trace,
+            ..StreamStats::default()
+        };
+
+        let mut client =
+            ExternalProcessorClient::with_interceptor(connection.channel, self.interceptor.clone());
+        if let Some(encoding) = self.compression.send {
+            client = client.send_compressed(encoding);
+        }
+        for &encoding in &self.compression.accept {
+            client = client.accept_compressed(encoding);
+        }
 
+        let traceparent = trace.map(TraceContext::traceparent);
+        let opened = Instant::now();
         let (tx, rx) = mpsc::channel(2);
-        tx.send(request_headers::create_processing_request())
-            .await
-            .map_err(|e| Error::CannotSendInitialRequest(Box::new(e)))?;
+        tx.send(request_headers::create_processing_request(
+            traceparent.clone(),
+        ))
+        .await
+        .map_err(|e| Error::CannotSendInitialRequest(Box::new(e)))?;
 
-        let request_stream = ReceiverStream::new(rx);
+        let mut request = Request::new(ReceiverStream::new(rx));
+        if let Some(traceparent) = traceparent {
+            let traceparent = MetadataValue::try_from(traceparent)
+                .expect("traceparent is a valid metadata value");
+            drop(request.metadata_mut().insert("traceparent", traceparent));
+        }
 
         let response = client
-            .process(request_stream)
+            .process(request)
             .await
             .map_err(|e| Error::FailedToCallExtProc(Box::new(e)))?;
 
         let mut response_stream = response.into_inner();
+        let accepted = Instant::now();
+        stats.phases.setup = Some(accepted - opened);
 
-        let Some(_processing_response) = response_stream.next().await else {
+        let Some(_processing_response) = next_response(&mut response_stream).await? else {
             // Early return if the stream is closed.
-            return Ok(());
+            return Ok(stats);
         };
+        let first_response = Instant::now();
+        stats.phases.request_headers = Some(first_response - accepted);
+        stats.phases.time_to_first_response = Some(first_response - opened);
 
+        let sent = Instant::now();
         let Ok(()) = tx.send(response_headers::create_processing_request()).await else {
-            return Ok(());
+            return Ok(stats);
         };
 
-        let Some(_processing_response) = response_stream.next().await else {
-            return Ok(());
+        let Some(_processing_response) = next_response(&mut response_stream).await? else {
+            return Ok(stats);
+        };
+        stats.phases.response_headers = Some(sent.elapsed());
+
+        Ok(StreamStats {
+            outcome: StreamOutcome::Completed,
+            ..stats
+        })
+    }
+}
+
+/// Returns the next response, or `None` if the server closed the stream.
+///
+/// A stream the server ends with an error status fails like a call that could not be
+/// made, so that it is not counted as closed early.
+async fn next_response(
+    responses: &mut Streaming<ProcessingResponse>,
+) -> Result<Option<ProcessingResponse>> {
+    responses
+        .next()
+        .await
+        .transpose()
+        .map_err(|status| Error::FailedToCallExtProc(Box::new(status)))
+}
+
+impl<I> Worker for GrpcWorker<I>
+where
+    I: Interceptor + Clone + Send + Sync,
+{
+    async fn run(&self) -> Result<StreamStats> {
+        let start = Instant::now();
+        let endpoint = self.endpoints.pick();
+        let trace = self.traces.and_then(TraceSampler::sample);
+        let result = self
+            .run_stream(endpoint.connections, trace)
+            .await
+            .map(|stats| StreamStats {
+                endpoint: endpoint.index,
+                ..stats
+            });
+
+        // NOTE: `tonic` channels reconnect by themselves once the server is back.
+        let error = match result {
+            Ok(stats) => {
+                if let Some(downtime) = &self.downtime {
+                    downtime.record_success(endpoint.index, start);
+                }
+                return Ok(stats);
+            }
+            Err(e) if e.is_stream_failure() => e,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(downtime) = &self.downtime
+            && error.is_unavailable()
+        {
+            downtime.record_failure(endpoint.index, start);
+        }
+        Ok(StreamStats {
+            outcome: StreamOutcome::Failed,
+            endpoint: endpoint.index,
+            trace,
+            error: Some(error.code()),
+            ..StreamStats::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        num::{NonZeroU64, NonZeroUsize},
+    };
+
+    use indicatif::ProgressBar;
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+    use tonic::transport::Endpoint;
+
+    use super::*;
+    use crate::app::{
+        connection::Churn,
+        scheduler::{REPORT_INTERVAL, Recording, Scheduler, WorkerResult},
+        sink::StepSinks,
+        summary::{self, StepSummary},
+        test_server::{self, EchoServer},
+        transport::{Connector, Target, TcpSettings},
+    };
+
+    /// Connects to the test server listening in `directory`.
+    async fn connect(directory: &TempDir, churn: Churn) -> ConnectionPool {
+        let (target, uri) = Target::parse(
+            &format!("unix:{}", directory.path().join("ext_proc.sock").display()),
+            false,
+        );
+        ConnectionPool::connect(
+            Endpoint::new(uri).unwrap(),
+            Connector::new(target, TcpSettings::default(), None),
+            NonZeroUsize::MIN,
+            churn,
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_worker_records_downtime_while_server_is_down() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        test_server::serve_unix(UnixListener::bind(&path).unwrap());
+
+        let connections = connect(&directory, Churn::EveryStreams(NonZeroU64::MIN)).await;
+        let downtime = DowntimeTracker::new(1);
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            Some(&downtime),
+            None,
+        );
+        let start = Instant::now();
+
+        // NOTE: New connections fail once the socket is gone, like during a restart.
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(worker.run().await.unwrap().outcome, StreamOutcome::Failed);
+        assert_eq!(worker.run().await.unwrap().outcome, StreamOutcome::Failed);
+
+        test_server::serve_unix(UnixListener::bind(&path).unwrap());
+        assert_eq!(
+            worker.run().await.unwrap().outcome,
+            StreamOutcome::Completed
+        );
+
+        let downtimes = downtime.take(start);
+        assert_eq!(downtimes.len(), 1);
+        assert_eq!(downtimes[0].failed_streams, 2);
+    }
+
+    #[tokio::test]
+    async fn test_worker_records_failures_without_reconnect() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("ext_proc.sock");
+        test_server::serve_unix(UnixListener::bind(&path).unwrap());
+
+        let connections = connect(&directory, Churn::EveryStreams(NonZeroU64::MIN)).await;
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        let stats = worker.run().await.unwrap();
+        assert_eq!(stats.outcome, StreamOutcome::Failed);
+        assert_eq!(stats.error, Some(tonic::Code::Unavailable));
+    }
+
+    #[tokio::test]
+    async fn test_worker_records_errors_other_than_unavailable_without_downtime() {
+        let directory = TempDir::new().unwrap();
+        test_server::serve_unix(
+            UnixListener::bind(directory.path().join("ext_proc.sock")).unwrap(),
+        );
+
+        let connections = connect(&directory, Churn::Never).await;
+        let downtime = DowntimeTracker::new(1);
+        let deny = |_: tonic::Request<()>| -> Result<_, tonic::Status> {
+            Err(tonic::Status::permission_denied("no token"))
+        };
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            deny,
+            CompressionSettings::default(),
+            Some(&downtime),
+            None,
+        );
+
+        let stats = worker.run().await.unwrap();
+        assert_eq!(stats.outcome, StreamOutcome::Failed);
+        assert_eq!(stats.error, Some(tonic::Code::PermissionDenied));
+        assert!(downtime.take(Instant::now()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_worker_measures_each_phase() {
+        const DELAY: Duration = Duration::from_millis(100);
+        let directory = TempDir::new().unwrap();
+        test_server::serve_unix_with(
+            UnixListener::bind(directory.path().join("ext_proc.sock")).unwrap(),
+            EchoServer {
+                delay: DELAY,
+                ..EchoServer::default()
+            },
+        );
+        let connections = connect(&directory, Churn::Never).await;
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        );
+
+        let stats = worker.run().await.unwrap();
+        assert_eq!(stats.outcome, StreamOutcome::Completed);
+        // NOTE: The server waits once before accepting the stream, and once before
+        // each response.
+        let phases = stats.phases;
+        let within = |phase: Option<Duration>, delays: u32| {
+            let phase = phase.unwrap();
+            assert!(
+                phase >= DELAY * delays && phase < DELAY * (delays + 1),
+                "{phase:?} is not {delays} delays, in {phases:?}"
+            );
+        };
+        within(phases.setup, 1);
+        within(phases.request_headers, 1);
+        within(phases.response_headers, 1);
+        within(phases.time_to_first_response, 2);
+    }
+
+    #[tokio::test]
+    async fn test_worker_counts_request_headers_in_setup_with_late_server_headers() {
+        const DELAY: Duration = Duration::from_millis(100);
+        let directory = TempDir::new().unwrap();
+        test_server::serve_unix_with(
+            UnixListener::bind(directory.path().join("ext_proc.sock")).unwrap(),
+            EchoServer {
+                delay: DELAY,
+                headers_with_first_response: true,
+                ..EchoServer::default()
+            },
+        );
+        let connections = connect(&directory, Churn::Never).await;
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        );
+
+        let stats = worker.run().await.unwrap();
+        assert_eq!(stats.outcome, StreamOutcome::Completed);
+        // NOTE: The server accepts the stream with its answer to the request headers.
+        let phases = stats.phases;
+        let setup = phases.setup.unwrap();
+        assert!(
+            setup >= DELAY * 2 && setup < DELAY * 3,
+            "{setup:?} is not 2 delays, in {phases:?}"
+        );
+        assert!(phases.request_headers.unwrap() < DELAY, "{phases:?}");
+        assert_eq!(
+            phases.time_to_first_response,
+            Some(setup + phases.request_headers.unwrap())
+        );
+    }
+
+    /// A worker sending streams to a server which ends them with `code`.
+    async fn worker_failing_with(code: tonic::Code) -> (TempDir, GrpcWorker) {
+        let directory = TempDir::new().unwrap();
+        test_server::serve_unix_with(
+            UnixListener::bind(directory.path().join("ext_proc.sock")).unwrap(),
+            EchoServer {
+                fail_with: Some(code),
+                ..EchoServer::default()
+            },
+        );
+        let connections = connect(&directory, Churn::Never).await;
+        let worker = GrpcWorker::new(
+            &Balancer::from(connections),
+            MetadataInterceptor::default(),
+            CompressionSettings::default(),
+            None,
+            None,
+        );
+        (directory, worker)
+    }
+
+    #[tokio::test]
+    async fn test_streams_ended_by_the_server_are_not_closed_early() {
+        for code in [tonic::Code::Unavailable, tonic::Code::Internal] {
+            let (_directory, worker) = worker_failing_with(code).await;
+            let stats = worker.run().await.unwrap();
+            assert_eq!(stats.outcome, StreamOutcome::Failed);
+            assert_eq!(stats.error, Some(code));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_reports_streams_failed_with_internal() {
+        let (_directory, worker) = worker_failing_with(tonic::Code::Internal).await;
+        let mut scheduler =
+            Scheduler::new(&[worker], REPORT_INTERVAL, Recording::default()).unwrap();
+
+        // NOTE: A single stream starts, at the first tick.
+        let results = scheduler
+            .run(
+                Duration::from_secs(1),
+                Duration::from_millis(500),
+                &ProgressBar::hidden(),
+                &StepSinks::default(),
+            )
+            .await
+            .unwrap();
+        let result = WorkerResult::merge(results, Recording::default());
+        let summary = StepSummary {
+            failures: summary::failures_by_code(&result.failures),
+            ..StepSummary::new(
+                1,
+                Duration::from_secs(1),
+                result.request_sent,
+                result.failed_streams,
+                result.in_flight_peak,
+                &result.histogram,
+                &result.phases,
+            )
         };
 
-        Ok(())
+        assert_eq!(summary.request_sent, 1);
+        assert_eq!(summary.streams, 0);
+        assert_eq!(summary.failed_streams, 1);
+        assert_eq!(
+            summary.failures,
+            BTreeMap::from([("Internal".to_owned(), 1)])
+        );
     }
 }
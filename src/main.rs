@@ -70,9 +70,8 @@ mod app;
 
 mod generated;
 
-#[tokio::main]
-async fn main() {
-    if let Err(e) = app::run().await {
+fn main() {
+    if let Err(e) = app::run() {
         eprintln!("Error: {e}");
         std::process::exit(e.exit_code());
     }